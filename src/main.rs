@@ -1,15 +1,248 @@
 extern crate rpn_calc;
 
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
 use rpn_calc::rpncalc;
+use rpn_calc::rpncalc::cli::Notation;
+use rpn_calc::rpncalc::config::{default_config_file, Config};
+use rpn_calc::rpncalc::editor::{History, LineEditor, DEFAULT_HISTORY_SIZE};
+use rpn_calc::rpncalc::filter::{OutputFormat, StackMode};
+use rpn_calc::rpncalc::plugin;
+use rpn_calc::rpncalc::session::default_session_file;
+
+struct Options {
+    filter: Option<String>,
+    stack_mode: StackMode,
+    format: OutputFormat,
+    notation: Notation,
+    history: bool,
+    session: bool,
+    atomic: bool,
+    tui: bool,
+    plugins: Option<PathBuf>,
+    config: Option<PathBuf>,
+}
+
+fn usage() {
+    println!("Usage: rpn-calc [OPTIONS]");
+    println!();
+    println!("Options:");
+    println!("  --filter [PROGRAM]\tEvaluate each input line followed by PROGRAM");
+    println!("  --continuous\t\tKeep the stack between input lines in filter mode");
+    println!("  --json\t\tWrite a JSON object per input line in filter mode");
+    println!("  --infix\t\tRead the lines entered as infix expressions, as in 2 * (3 + 4)");
+    println!("  --tui\t\t\tFull-screen interface with the stack, history and modes");
+    println!("  --atomic\t\tUndo a line at its first error, skipping the rest of it");
+    println!("  --no-history\t\tNeither read nor save the history of the lines entered");
+    println!("  --no-session\t\tNeither restore the last session nor save it on exit");
+    println!("  --config FILE\t\tRead the settings from FILE (default: ~/.config/rpn-calc/config)");
+    println!("  --plugins DIR\t\tLoad the plugins of DIR (default: ~/.config/rpn-calc/plugins)");
+    println!("  -h --help\t\tDisplay this message");
+    println!();
+    println!("Filter mode is enabled automatically when stdin is not a terminal.");
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        filter: None,
+        stack_mode: StackMode::PerLine,
+        format: OutputFormat::Text,
+        notation: Notation::Rpn,
+        history: true,
+        session: true,
+        atomic: false,
+        tui: false,
+        plugins: plugin::default_plugins_dir(),
+        config: None,
+    };
+
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--filter" => {
+                let program = args.next_if(|a| !a.starts_with("--"));
+                options.filter = Some(program.unwrap_or_default());
+            }
+            "--continuous" => options.stack_mode = StackMode::Continuous,
+            "--json" => options.format = OutputFormat::Json,
+            "--infix" => options.notation = Notation::Infix,
+            "--no-history" => options.history = false,
+            "--no-session" => options.session = false,
+            "--atomic" => options.atomic = true,
+            "--tui" => options.tui = true,
+            "--config" => match args.next() {
+                Some(file) => options.config = Some(PathBuf::from(file)),
+                None => {
+                    eprintln!("rpn-calc: expected a file after '--config'");
+                    std::process::exit(2);
+                }
+            },
+            "--plugins" => match args.next() {
+                Some(dir) => options.plugins = Some(PathBuf::from(dir)),
+                None => {
+                    eprintln!("rpn-calc: expected a directory after '--plugins'");
+                    std::process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                usage();
+                std::process::exit(0);
+            }
+            _ => {
+                eprintln!("rpn-calc: unknown option '{}'", arg);
+                eprintln!("'rpn-calc --help' for a list of options");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let interactive = std::io::stdin().is_terminal() && options.format == OutputFormat::Text;
+    if options.filter.is_none() && !interactive {
+        options.filter = Some(String::new());
+    }
+
+    options
+}
+
+fn run_filter(program: &str, options: &Options, config: &Config) {
+    let mut my_calc = rpncalc::RpnCalc::new();
+    apply_config(config, &mut my_calc, false);
+    if options.atomic {
+        my_calc.set_atomic(true);
+    }
+    let mut filter = rpncalc::RpnCalc::filter(program, options.stack_mode);
+    filter.set_output_format(options.format);
+    filter.set_notation(options.notation);
+
+    let success = filter.run(
+        &mut my_calc,
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+        std::io::stderr().lock(),
+    );
+
+    match success {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("rpn-calc: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn line_editor(use_history_file: bool, size: usize) -> LineEditor {
+    let history = match rpncalc::editor::default_history_file() {
+        Some(file) if use_history_file => History::with_file(size, file),
+        _ => History::new(size),
+    };
+    LineEditor::new(history)
+}
+
+fn run_tui(calc: &mut rpncalc::RpnCalc, editor: LineEditor, options: &Options, config: &Config) {
+    let mut tui = rpncalc::RpnCalc::tui(editor);
+    tui.set_notation(options.notation);
+    if let Some(prompt) = config.prompt() {
+        tui.set_prompt(prompt);
+    }
+    if let Err(e) = tui.run_in_terminal(calc) {
+        eprintln!("rpn-calc: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn load_plugins(dir: &Path) {
+    for (path, result) in plugin::load_plugins(dir) {
+        if let Err(e) = result {
+            eprintln!("Error: Could not load the plugin {}: {}", path.display(), e);
+        }
+    }
+}
+
+// The settings of `file`, or of the default file if it exists
+fn load_config(file: Option<&Path>) -> Config {
+    let default_file = default_config_file().filter(|f| f.exists());
+    let Some(file) = file.or(default_file.as_deref()) else {
+        return Config::default();
+    };
+    match Config::load(file) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!(
+                "Error: Could not read the configuration {}: {}",
+                file.display(),
+                e
+            );
+            Config::default()
+        }
+    }
+}
+
+fn apply_config(config: &Config, calc: &mut rpncalc::RpnCalc, restored: bool) {
+    if let Err(e) = config.apply(calc, restored) {
+        eprintln!("Error: Could not apply the configuration: {}", e);
+    }
+}
 
 fn main() {
+    let options = parse_args();
+
+    if let Some(dir) = &options.plugins {
+        load_plugins(dir);
+    }
+    let config = load_config(options.config.as_deref());
+
+    if let Some(program) = &options.filter {
+        run_filter(program, &options, &config);
+        return;
+    }
+
     let mut my_calc = rpncalc::RpnCalc::new();
 
-    let mut cli = rpncalc::RpnCalc::cli();
+    let history_size = config.history_size().unwrap_or(DEFAULT_HISTORY_SIZE);
+    let session_file = default_session_file().filter(|_| options.session);
+
+    // The full-screen interface has its own screen
+    if !options.tui {
+        println!("CLI reverse polish notation calculator.");
+        println!("'help' for a list of commands");
+    }
+    let mut restored = false;
+    if let Some(file) = session_file.as_ref().filter(|f| f.exists()) {
+        match my_calc.load_session(file) {
+            Ok(()) => restored = true,
+            Err(e) => println!("Error: Could not restore the session: {}", e),
+        }
+    }
+    apply_config(&config, &mut my_calc, restored);
+    if options.atomic {
+        my_calc.set_atomic(true);
+    }
+
+    if options.tui {
+        run_tui(
+            &mut my_calc,
+            line_editor(options.history, history_size),
+            &options,
+            &config,
+        );
+    } else {
+        let mut cli = rpncalc::RpnCalc::cli();
+        cli.enable_line_editor(line_editor(options.history, history_size));
+        cli.set_notation(options.notation);
+        if let Some(prompt) = config.prompt() {
+            cli.set_prompt(prompt);
+        }
+        while cli.keep_running() {
+            cli.sync_with(&my_calc);
+            my_calc.process(cli.read_new_command(std::io::stdin().lock()));
+        }
+    }
 
-    println!("CLI reverse polish notation calculator.");
-    println!("'help' for a list of commands");
-    while cli.keep_running() {
-        my_calc.process(cli.read_new_command(std::io::stdin().lock()));
+    if let Some(file) = session_file {
+        if let Err(e) = my_calc.save_session(&file) {
+            eprintln!("Error: Could not save the session: {}", e);
+        }
     }
 }
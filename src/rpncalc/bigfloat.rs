@@ -0,0 +1,801 @@
+// Decimal floating point numbers of any precision: a sign, an integer
+// mantissa and a power of 10. Sums, differences, products, quotients and
+// square roots are computed exactly, or with a digit marking an inexact tail,
+// before being rounded half to even to the number of digits, so they are
+// correctly rounded. The other functions are computed with guard digits and
+// then rounded.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::expr::Function;
+
+const BASE: u64 = 1_000_000_000;
+const BASE_DIGITS: usize = 9;
+
+// Guard digits of the functions, on top of the digits lost in the argument
+// reductions
+const GUARD_DIGITS: usize = 10;
+
+// Unsigned integers in base 10^9, least significant limb first, without
+// leading zero limbs
+#[derive(Clone, PartialEq, Debug, Default)]
+struct Natural {
+    limbs: Vec<u32>,
+}
+
+impl Natural {
+    fn from_u64(mut n: u64) -> Natural {
+        let mut limbs = vec![];
+        while n > 0 {
+            limbs.push((n % BASE) as u32);
+            n /= BASE;
+        }
+        Natural { limbs }
+    }
+
+    fn normalized(mut limbs: Vec<u32>) -> Natural {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Natural { limbs }
+    }
+
+    // `digits` holding only ASCII digits
+    fn parse(digits: &str) -> Natural {
+        let bytes = digits.as_bytes();
+        let limbs = bytes
+            .rchunks(BASE_DIGITS)
+            .map(|chunk| chunk.iter().fold(0, |n, b| n * 10 + (b - b'0') as u32))
+            .collect();
+        Natural::normalized(limbs)
+    }
+
+    fn pow10(k: usize) -> Natural {
+        Natural::from_u64(1).mul_pow10(k)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    fn is_odd(&self) -> bool {
+        self.limbs.first().is_some_and(|limb| limb % 2 == 1)
+    }
+
+    fn digits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => (self.limbs.len() - 1) * BASE_DIGITS + top.to_string().len(),
+            None => 0,
+        }
+    }
+
+    fn trailing_zeros(&self) -> usize {
+        let Some(low) = self.limbs.iter().position(|&limb| limb != 0) else {
+            return 0;
+        };
+        let mut limb = self.limbs[low];
+        let mut zeros = low * BASE_DIGITS;
+        while limb.is_multiple_of(10) {
+            limb /= 10;
+            zeros += 1;
+        }
+        zeros
+    }
+
+    fn cmp(&self, other: &Natural) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+
+    fn add(&self, other: &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let a = *self.limbs.get(i).unwrap_or(&0) as u64;
+            let b = *other.limbs.get(i).unwrap_or(&0) as u64;
+            let sum = a + b + carry;
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        limbs.push(carry as u32);
+        Natural::normalized(limbs)
+    }
+
+    // `other` being at most `self`
+    fn sub(&self, other: &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0;
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let b = *other.limbs.get(i).unwrap_or(&0) as i64 + borrow;
+            let (difference, next) = match a as i64 - b {
+                d if d < 0 => (d + BASE as i64, 1),
+                d => (d, 0),
+            };
+            limbs.push(difference as u32);
+            borrow = next;
+        }
+        Natural::normalized(limbs)
+    }
+
+    fn mul_small(&self, m: u32) -> Natural {
+        let mut limbs = Vec::with_capacity(self.limbs.len() + 1);
+        let mut carry = 0;
+        for &limb in self.limbs.iter() {
+            let product = limb as u64 * m as u64 + carry;
+            limbs.push((product % BASE) as u32);
+            carry = product / BASE;
+        }
+        limbs.push(carry as u32);
+        Natural::normalized(limbs)
+    }
+
+    fn add_small(&self, a: u32) -> Natural {
+        self.add(&Natural::from_u64(a as u64))
+    }
+
+    fn mul(&self, other: &Natural) -> Natural {
+        if self.is_zero() || other.is_zero() {
+            return Natural::default();
+        }
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let product = limbs[i + j] + a as u64 * b as u64 + carry;
+                limbs[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            limbs[i + other.limbs.len()] += carry;
+        }
+        Natural::normalized(limbs.into_iter().map(|limb| limb as u32).collect())
+    }
+
+    fn mul_pow10(&self, k: usize) -> Natural {
+        if self.is_zero() {
+            return Natural::default();
+        }
+        let mut limbs = vec![0; k / BASE_DIGITS];
+        limbs.extend_from_slice(&self.mul_small(10u32.pow((k % BASE_DIGITS) as u32)).limbs);
+        Natural { limbs }
+    }
+
+    fn divmod_small(&self, d: u32) -> (Natural, u32) {
+        let mut limbs = vec![0; self.limbs.len()];
+        let mut remainder = 0;
+        for (i, &limb) in self.limbs.iter().enumerate().rev() {
+            let n = remainder * BASE + limb as u64;
+            limbs[i] = (n / d as u64) as u32;
+            remainder = n % d as u64;
+        }
+        (Natural::normalized(limbs), remainder as u32)
+    }
+
+    // The quotient and remainder by 10^k
+    fn divmod_pow10(&self, k: usize) -> (Natural, Natural) {
+        let shift = (k / BASE_DIGITS).min(self.limbs.len());
+        let low = Natural::normalized(self.limbs[..shift].to_vec());
+        let high = Natural {
+            limbs: self.limbs[shift..].to_vec(),
+        };
+        let (quotient, remainder) = high.divmod_small(10u32.pow((k % BASE_DIGITS) as u32));
+        let remainder = Natural::from_u64(remainder as u64).mul_pow10(shift * BASE_DIGITS);
+        (quotient, remainder.add(&low))
+    }
+
+    // Long division of Knuth's algorithm D, `d` being nonzero
+    fn divmod(&self, d: &Natural) -> (Natural, Natural) {
+        if self.cmp(d) == Ordering::Less {
+            return (Natural::default(), self.clone());
+        }
+        if d.limbs.len() == 1 {
+            let (quotient, remainder) = self.divmod_small(d.limbs[0]);
+            return (quotient, Natural::from_u64(remainder as u64));
+        }
+
+        // Scaled so that the top limb of the divisor is at least BASE / 2
+        let factor = (BASE / (*d.limbs.last().unwrap() as u64 + 1)) as u32;
+        let mut u = self.mul_small(factor).limbs;
+        u.resize(self.limbs.len() + 1, 0);
+        let v = d.mul_small(factor).limbs;
+        let n = v.len();
+        let m = u.len() - n - 1;
+        let (top, next) = (v[n - 1] as u64, v[n - 2] as u64);
+
+        let mut quotient = vec![0; m + 1];
+        for j in (0..=m).rev() {
+            let numerator = u[j + n] as u64 * BASE + u[j + n - 1] as u64;
+            let (mut q, mut r) = (numerator / top, numerator % top);
+            while q >= BASE || q * next > r * BASE + u[j + n - 2] as u64 {
+                q -= 1;
+                r += top;
+                if r >= BASE {
+                    break;
+                }
+            }
+
+            let (mut borrow, mut carry) = (0i64, 0u64);
+            for i in 0..n {
+                let product = q * v[i] as u64 + carry;
+                carry = product / BASE;
+                let difference = u[i + j] as i64 - (product % BASE) as i64 - borrow;
+                (u[i + j], borrow) = match difference {
+                    d if d < 0 => ((d + BASE as i64) as u32, 1),
+                    d => (d as u32, 0),
+                };
+            }
+            let difference = u[j + n] as i64 - carry as i64 - borrow;
+            if difference < 0 {
+                // q was one too large, the divisor is added back
+                q -= 1;
+                let mut carry = 0;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = (sum % BASE) as u32;
+                    carry = sum / BASE;
+                }
+                u[j + n] = ((difference + BASE as i64) as u64 + carry - BASE) as u32;
+            } else {
+                u[j + n] = difference as u32;
+            }
+            quotient[j] = q as u32;
+        }
+
+        let remainder = Natural::normalized(u[..n].to_vec()).divmod_small(factor).0;
+        (Natural::normalized(quotient), remainder)
+    }
+
+    // The largest integer whose square is at most `self`, by Newton's method
+    // from above
+    fn isqrt(&self) -> Natural {
+        if self.is_zero() {
+            return Natural::default();
+        }
+        let mut x = Natural::pow10(self.digits().div_ceil(2));
+        loop {
+            let y = x.add(&self.divmod(&x).0).divmod_small(2).0;
+            if y.cmp(&x) != Ordering::Less {
+                return x;
+            }
+            x = y;
+        }
+    }
+}
+
+impl fmt::Display for Natural {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(top) = self.limbs.last() else {
+            return write!(f, "0");
+        };
+        write!(f, "{}", top)?;
+        for limb in self.limbs.iter().rev().skip(1) {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+// The mantissa has no trailing zeros, zero being positive with a zero exponent
+#[derive(Clone, PartialEq, Debug)]
+pub struct BigFloat {
+    negative: bool,
+    mantissa: Natural,
+    exponent: i64,
+}
+
+impl BigFloat {
+    fn new(negative: bool, mantissa: Natural, exponent: i64) -> BigFloat {
+        if mantissa.is_zero() {
+            return BigFloat::zero();
+        }
+        let zeros = mantissa.trailing_zeros();
+        BigFloat {
+            negative,
+            mantissa: mantissa.divmod_pow10(zeros).0,
+            exponent: exponent + zeros as i64,
+        }
+    }
+
+    fn zero() -> BigFloat {
+        BigFloat {
+            negative: false,
+            mantissa: Natural::default(),
+            exponent: 0,
+        }
+    }
+
+    fn from_int(n: i64) -> BigFloat {
+        BigFloat::new(n < 0, Natural::from_u64(n.unsigned_abs()), 0)
+    }
+
+    // Decimal literals, as in -12.5 or 1.25e-3
+    pub fn parse(s: &str) -> Option<BigFloat> {
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (number, exponent) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits = format!("{}{}", integer, fraction);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let exponent = exponent.checked_sub(fraction.len() as i64)?;
+        Some(BigFloat::new(negative, Natural::parse(&digits), exponent))
+    }
+
+    // The shortest decimal reading back to the same f64, as 0.1 for 0.1
+    pub fn from_f64(x: f64) -> Option<BigFloat> {
+        if !x.is_finite() {
+            return None;
+        }
+        BigFloat::parse(&format!("{:e}", x))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let sign = if self.negative { "-" } else { "" };
+        format!("{}{}e{}", sign, self.mantissa, self.exponent)
+            .parse()
+            .unwrap()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    fn is_integer(&self) -> bool {
+        self.exponent >= 0
+    }
+
+    // Position of the leading digit, 0 for the units
+    fn top(&self) -> i64 {
+        self.exponent + self.mantissa.digits() as i64 - 1
+    }
+
+    pub fn neg(&self) -> BigFloat {
+        BigFloat {
+            negative: !self.negative && !self.is_zero(),
+            ..self.clone()
+        }
+    }
+
+    fn cmp_abs(&self, other: &BigFloat) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => {}
+        }
+        self.top().cmp(&other.top()).then_with(|| {
+            let exponent = self.exponent.min(other.exponent);
+            let a = self.mantissa.mul_pow10((self.exponent - exponent) as usize);
+            let b = other
+                .mantissa
+                .mul_pow10((other.exponent - exponent) as usize);
+            a.cmp(&b)
+        })
+    }
+
+    // Rounded half to even to `digits` significant digits
+    pub fn round(&self, digits: usize) -> BigFloat {
+        let length = self.mantissa.digits();
+        if length <= digits {
+            return self.clone();
+        }
+        let dropped = length - digits;
+        let (mut mantissa, rest) = self.mantissa.divmod_pow10(dropped);
+        let half = Natural::pow10(dropped - 1).mul_small(5);
+        let up = match rest.cmp(&half) {
+            Ordering::Greater => true,
+            Ordering::Equal => mantissa.is_odd(),
+            Ordering::Less => false,
+        };
+        if up {
+            mantissa = mantissa.add_small(1);
+        }
+        BigFloat::new(self.negative, mantissa, self.exponent + dropped as i64)
+    }
+
+    // The mantissa with a 1 appended when the exact value has more digits, a
+    // tail below the rounding digit keeping the rounding correct
+    fn inexact(negative: bool, mantissa: Natural, exponent: i64, exact: bool) -> BigFloat {
+        if exact {
+            BigFloat::new(negative, mantissa, exponent)
+        } else {
+            BigFloat::new(negative, mantissa.mul_small(10).add_small(1), exponent - 1)
+        }
+    }
+
+    pub fn add(&self, other: &BigFloat, digits: usize) -> BigFloat {
+        if self.is_zero() || other.is_zero() {
+            let sum = if self.is_zero() { other } else { self };
+            return sum.round(digits);
+        }
+        let (large, small) = match self.top() >= other.top() {
+            true => (self, other),
+            false => (other, self),
+        };
+        // Far below the rounding digit and the last digit of the larger
+        // operand, the smaller one only matters as a tail
+        let floor = (large.top() - digits as i64 - 3).min(large.exponent - 1);
+        let small = match small.top() < floor {
+            true => BigFloat::new(small.negative, Natural::from_u64(1), floor),
+            false => small.clone(),
+        };
+
+        let exponent = large.exponent.min(small.exponent);
+        let a = large
+            .mantissa
+            .mul_pow10((large.exponent - exponent) as usize);
+        let b = small
+            .mantissa
+            .mul_pow10((small.exponent - exponent) as usize);
+        let (negative, mantissa) = if large.negative == small.negative {
+            (large.negative, a.add(&b))
+        } else if a.cmp(&b) == Ordering::Less {
+            (small.negative, b.sub(&a))
+        } else {
+            (large.negative, a.sub(&b))
+        };
+        BigFloat::new(negative, mantissa, exponent).round(digits)
+    }
+
+    pub fn sub(&self, other: &BigFloat, digits: usize) -> BigFloat {
+        self.add(&other.neg(), digits)
+    }
+
+    pub fn mul(&self, other: &BigFloat, digits: usize) -> BigFloat {
+        let mantissa = self.mantissa.mul(&other.mantissa);
+        let exponent = self.exponent + other.exponent;
+        BigFloat::new(self.negative != other.negative, mantissa, exponent).round(digits)
+    }
+
+    // None for a zero divisor
+    pub fn div(&self, other: &BigFloat, digits: usize) -> Option<BigFloat> {
+        if other.is_zero() {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(BigFloat::zero());
+        }
+        // At least one digit past the rounding digit in the quotient
+        let (a, b) = (
+            self.mantissa.digits() as i64,
+            other.mantissa.digits() as i64,
+        );
+        let scale = (digits as i64 + 2 + b - a).max(0);
+        let numerator = self.mantissa.mul_pow10(scale as usize);
+        let (quotient, remainder) = numerator.divmod(&other.mantissa);
+        let exponent = self.exponent - other.exponent - scale;
+        let negative = self.negative != other.negative;
+        Some(BigFloat::inexact(negative, quotient, exponent, remainder.is_zero()).round(digits))
+    }
+
+    // None for a negative number
+    pub fn sqrt(&self, digits: usize) -> Option<BigFloat> {
+        if self.negative {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(BigFloat::zero());
+        }
+        // At least one digit past the rounding digit in the root, from an
+        // even power of 10
+        let mut scale = (2 * (digits + 2)).saturating_sub(self.mantissa.digits()) as i64;
+        if (self.exponent - scale) % 2 != 0 {
+            scale += 1;
+        }
+        let n = self.mantissa.mul_pow10(scale as usize);
+        let root = n.isqrt();
+        let exact = root.mul(&root) == n;
+        let exponent = (self.exponent - scale) / 2;
+        Some(BigFloat::inexact(false, root, exponent, exact).round(digits))
+    }
+
+    // x^y, by repeated squaring for integer powers; None out of the domain
+    pub fn pow(&self, y: &BigFloat, digits: usize) -> Option<BigFloat> {
+        if self.is_zero() {
+            return (!y.negative && !y.is_zero()).then(BigFloat::zero);
+        }
+        let n = y.to_f64();
+        if y.is_integer() && n.abs() <= 1e9 {
+            let n = n as i64;
+            let working = digits + GUARD_DIGITS + 10;
+            let mut power = BigFloat::from_int(1);
+            let mut square = self.clone();
+            let mut k = n.unsigned_abs();
+            while k > 0 {
+                if k % 2 == 1 {
+                    power = power.mul(&square, working);
+                }
+                square = square.mul(&square, working);
+                k /= 2;
+            }
+            return match n < 0 {
+                true => BigFloat::from_int(1).div(&power, digits),
+                false => Some(power.round(digits)),
+            };
+        }
+        if self.negative {
+            return None;
+        }
+        let working = digits + GUARD_DIGITS + y.top().max(0) as usize;
+        let ln = self.ln(working)?;
+        y.mul(&ln, working).exp(digits)
+    }
+
+    // None out of the domain of the function, or when the result overflows
+    pub fn apply(&self, function: Function, digits: usize) -> Option<BigFloat> {
+        match function {
+            Function::Sqrt => self.sqrt(digits),
+            Function::Exp => self.exp(digits),
+            Function::Ln => self.ln(digits),
+            Function::Sin => Some(self.sin_cos(digits)?.0),
+            Function::Cos => Some(self.sin_cos(digits)?.1),
+            Function::Tan => {
+                let working = digits + GUARD_DIGITS;
+                let (sin, cos) = self.sin_cos(working)?;
+                sin.div(&cos, digits)
+            }
+            Function::Asin => self.asin(digits),
+            Function::Acos => {
+                let working = digits + GUARD_DIGITS;
+                let half_pi = pi(working).div(&BigFloat::from_int(2), working)?;
+                Some(half_pi.sub(&self.asin(working)?, digits))
+            }
+            Function::Atan => self.atan(digits),
+        }
+    }
+
+    // `apply` with the angles in degrees, exact at the multiples of 90
+    pub fn apply_degrees(&self, function: Function, digits: usize) -> Option<BigFloat> {
+        let exact = function.right_angle(self.to_f64());
+        let right_angle = BigFloat::from_f64((self.to_f64() / 90.0).round() * 90.0);
+        if let (Some(value), true) = (exact, right_angle.as_ref() == Some(self)) {
+            return BigFloat::from_f64(value);
+        }
+        let working = digits + GUARD_DIGITS;
+        let degree = pi(working).div(&BigFloat::from_int(180), working)?;
+        match function {
+            Function::Sin | Function::Cos | Function::Tan => {
+                self.mul(&degree, working).apply(function, digits)
+            }
+            Function::Asin | Function::Acos | Function::Atan => {
+                self.apply(function, working)?.div(&degree, digits)
+            }
+            _ => self.apply(function, digits),
+        }
+    }
+
+    // exp(x) = 10^k exp(x - k ln 10), the rest being halved before the Taylor
+    // series and the result squared back
+    fn exp(&self, digits: usize) -> Option<BigFloat> {
+        const HALVINGS: u32 = 10;
+        let k = (self.to_f64() / std::f64::consts::LN_10).round();
+        if k.abs() > 1e15 {
+            return None;
+        }
+        let k = k as i64;
+        let working = digits + GUARD_DIGITS + 4 + k.unsigned_abs().to_string().len();
+        let ln10 = ln(&BigFloat::from_int(10), working);
+        let rest = self.sub(&BigFloat::from_int(k).mul(&ln10, working), working);
+        let x = rest.div(&BigFloat::from_int(1 << HALVINGS), working)?;
+
+        let mut sum = BigFloat::from_int(1);
+        let mut term = BigFloat::from_int(1);
+        for n in 1.. {
+            term = term.mul(&x, working).div(&BigFloat::from_int(n), working)?;
+            if negligible(&term, &sum, working) {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        for _ in 0..HALVINGS {
+            sum = sum.mul(&sum, working);
+        }
+        sum.exponent += k;
+        Some(sum.round(digits))
+    }
+
+    // ln(m 10^e) = ln(m) + e ln 10, m being between 1 and 10
+    fn ln(&self, digits: usize) -> Option<BigFloat> {
+        if self.negative || self.is_zero() {
+            return None;
+        }
+        let e = self.top();
+        let working = digits + GUARD_DIGITS + e.unsigned_abs().to_string().len();
+        let m = BigFloat {
+            exponent: self.exponent - e,
+            ..self.clone()
+        };
+        let ln10 = ln(&BigFloat::from_int(10), working);
+        let sum = ln(&m, working).add(&BigFloat::from_int(e).mul(&ln10, working), working);
+        Some(sum.round(digits))
+    }
+
+    // sin(x) and cos(x) from those of the rest of x less a multiple of pi/2
+    fn sin_cos(&self, digits: usize) -> Option<(BigFloat, BigFloat)> {
+        let quadrant = (self.to_f64() / std::f64::consts::FRAC_PI_2).round();
+        if quadrant.abs() > 1e15 {
+            return None;
+        }
+        let quadrant = quadrant as i64;
+        let working = digits + GUARD_DIGITS + quadrant.unsigned_abs().to_string().len();
+        let half_pi = pi(working).div(&BigFloat::from_int(2), working)?;
+        let x = self.sub(
+            &BigFloat::from_int(quadrant).mul(&half_pi, working),
+            working,
+        );
+        let x2 = x.mul(&x, working);
+
+        let series = |first: BigFloat, offset: i64| -> Option<BigFloat> {
+            let (mut sum, mut term) = (first.clone(), first);
+            for n in 1.. {
+                let denominator = BigFloat::from_int((2 * n + offset - 1) * (2 * n + offset));
+                term = term.mul(&x2, working).div(&denominator, working)?.neg();
+                if negligible(&term, &sum, working) {
+                    break;
+                }
+                sum = sum.add(&term, working);
+            }
+            Some(sum)
+        };
+        let sin = series(x.clone(), 1)?;
+        let cos = series(BigFloat::from_int(1), 0)?;
+        let (sin, cos) = match quadrant.rem_euclid(4) {
+            0 => (sin, cos),
+            1 => (cos, sin.neg()),
+            2 => (sin.neg(), cos.neg()),
+            _ => (cos.neg(), sin),
+        };
+        Some((sin.round(digits), cos.round(digits)))
+    }
+
+    // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), down to small x for the
+    // Taylor series
+    fn atan(&self, digits: usize) -> Option<BigFloat> {
+        let working = digits + GUARD_DIGITS;
+        let one = BigFloat::from_int(1);
+        if self.cmp_abs(&one) == Ordering::Greater {
+            let half_pi = pi(working).div(&BigFloat::from_int(2), working)?;
+            let half_pi = if self.negative {
+                half_pi.neg()
+            } else {
+                half_pi
+            };
+            let atan = one.div(self, working)?.atan(working)?;
+            return Some(half_pi.sub(&atan, digits));
+        }
+
+        let small = BigFloat::parse("0.01").unwrap();
+        let mut x = self.clone();
+        let mut doublings = 0;
+        while x.cmp_abs(&small) == Ordering::Greater {
+            let root = one.add(&x.mul(&x, working), working).sqrt(working)?;
+            x = x.div(&one.add(&root, working), working)?;
+            doublings += 1;
+        }
+        let x2 = x.mul(&x, working);
+        let (mut sum, mut power) = (x.clone(), x);
+        for n in 1.. {
+            power = power.mul(&x2, working).neg();
+            let term = power.div(&BigFloat::from_int(2 * n + 1), working)?;
+            if negligible(&term, &sum, working) {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        let scale = BigFloat::from_int(1 << doublings);
+        Some(sum.mul(&scale, digits))
+    }
+
+    // asin(x) = atan(x / sqrt(1 - x^2)), and +-pi/2 at +-1
+    fn asin(&self, digits: usize) -> Option<BigFloat> {
+        let working = digits + GUARD_DIGITS;
+        let one = BigFloat::from_int(1);
+        match self.cmp_abs(&one) {
+            Ordering::Greater => None,
+            Ordering::Equal => {
+                let half_pi = pi(working).div(&BigFloat::from_int(2), digits)?;
+                Some(if self.negative {
+                    half_pi.neg()
+                } else {
+                    half_pi
+                })
+            }
+            Ordering::Less => {
+                let root = one.sub(&self.mul(self, working), working).sqrt(working)?;
+                self.div(&root, working)?.atan(digits)
+            }
+        }
+    }
+}
+
+// Whether `term` no longer changes `sum` to `digits` digits
+fn negligible(term: &BigFloat, sum: &BigFloat, digits: usize) -> bool {
+    term.is_zero() || (!sum.is_zero() && term.top() < sum.top() - digits as i64 - 2)
+}
+
+// pi = 16 atan(1/5) - 4 atan(1/239), from Machin's formula
+fn pi(digits: usize) -> BigFloat {
+    let working = digits + 5;
+    let atan_inverse = |n: i64| {
+        let x = BigFloat::from_int(1)
+            .div(&BigFloat::from_int(n), working)
+            .unwrap();
+        let x2 = x.mul(&x, working);
+        let (mut sum, mut power) = (x.clone(), x);
+        for k in 1.. {
+            power = power.mul(&x2, working).neg();
+            let term = power.div(&BigFloat::from_int(2 * k + 1), working).unwrap();
+            if negligible(&term, &sum, working) {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        sum
+    };
+    let a = atan_inverse(5).mul(&BigFloat::from_int(16), working);
+    let b = atan_inverse(239).mul(&BigFloat::from_int(4), working);
+    a.sub(&b, digits)
+}
+
+// ln(x) = 2^k ln(x^(1/2^k)) for x between 1 and 10, the root being close
+// enough to 1 for the series ln(x) = 2 atanh((x - 1) / (x + 1))
+fn ln(x: &BigFloat, digits: usize) -> BigFloat {
+    const ROOTS: u32 = 10;
+    let working = digits + 4;
+    let one = BigFloat::from_int(1);
+    let mut root = x.clone();
+    for _ in 0..ROOTS {
+        root = root.sqrt(working).unwrap();
+    }
+    let numerator = root.sub(&one, working);
+    let z = numerator.div(&root.add(&one, working), working).unwrap();
+    let z2 = z.mul(&z, working);
+    let (mut sum, mut power) = (z.clone(), z);
+    for k in 1.. {
+        power = power.mul(&z2, working);
+        let term = power.div(&BigFloat::from_int(2 * k + 1), working).unwrap();
+        if negligible(&term, &sum, working) {
+            break;
+        }
+        sum = sum.add(&term, working);
+    }
+    sum.mul(&BigFloat::from_int(2 << ROOTS), digits)
+}
+
+// Plain decimals from 1e-7 to 1e21 or the number of digits, scientific
+// notation otherwise, as in 1.5e-9
+impl fmt::Display for BigFloat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let digits = self.mantissa.to_string();
+        let (length, top) = (digits.len() as i64, self.top());
+        if top < -7 || top >= length.max(21) {
+            write!(f, "{}", &digits[..1])?;
+            if length > 1 {
+                write!(f, ".{}", &digits[1..])?;
+            }
+            write!(f, "e{}", top)
+        } else if self.exponent >= 0 {
+            write!(f, "{}{}", digits, "0".repeat(self.exponent as usize))
+        } else if top >= 0 {
+            let point = (top + 1) as usize;
+            write!(f, "{}.{}", &digits[..point], &digits[point..])
+        } else {
+            write!(f, "0.{}{}", "0".repeat((-top - 1) as usize), digits)
+        }
+    }
+}
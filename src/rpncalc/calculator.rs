@@ -1,143 +1,1700 @@
-use super::cli::{CliCmd, CliOperation};
-use super::RpnCalc;
+use std::path::PathBuf;
 
-pub fn process(c: &mut RpnCalc, cmd: &CliCmd) {
+use super::bigfloat::BigFloat;
+use super::cli::{self, Argument, CliCmd, CliOperation, SYNTAX_HELP};
+use super::date::{DateTime, Duration};
+use super::debug;
+use super::error::CalcError;
+use super::expr::{AngleMode, Expr, Function};
+use super::infix;
+use super::integrate;
+use super::matrix::Matrix;
+use super::observer;
+use super::poly;
+use super::registry::{
+    self, plain, with_argument, with_commands, with_line, Aliases, Builtin, Exprs, DEFAULT_ALIASES,
+};
+use super::script::Script;
+use super::session::{self, SessionError};
+use super::solve;
+use super::value::{Program, Value};
+use super::{Output, RpnCalc};
+
+// Depth of word and program calls, past which recursion is assumed endless
+const MAX_NESTED_CALLS: usize = 64;
+
+// Digits of Big numbers left on the stack once the precision mode is off
+const F64_DIGITS: usize = 17;
+
+// The built-in commands, in the order of the help
+pub(super) static BUILTINS: &[Builtin] = &[
+    plain(
+        &["+", "a", "add"],
+        2,
+        "Add the top two numbers from the stack",
+        add,
+        Exprs::Binary(|a, b| a + b),
+    ),
+    plain(
+        &["-", "s", "sub"],
+        2,
+        "Subtract the top two numbers from the stack",
+        subtract,
+        Exprs::Binary(|a, b| a - b),
+    ),
+    plain(
+        &["*", "x", "mul"],
+        2,
+        "Multiply the top two numbers from the stack",
+        multiply,
+        Exprs::Binary(|a, b| a * b),
+    ),
+    plain(
+        &["/", "d", "div"],
+        2,
+        "Divide the top two numbers from the stack",
+        divide,
+        Exprs::Binary(|a, b| a / b),
+    ),
+    plain(
+        &["sqrt"],
+        1,
+        "Calculate the square root of the top of the stack",
+        square_root,
+        Exprs::Unary(|a| Expr::call(Function::Sqrt, a)),
+    ),
+    plain(
+        &["^", "pow"],
+        2,
+        "Power the top two numbers of the stack",
+        power,
+        Exprs::Binary(Expr::pow),
+    ),
+    plain(
+        &["neg", "chs"],
+        1,
+        "Negate the top of the stack",
+        negate,
+        Exprs::Unary(|a| -a),
+    ),
+    plain(
+        &["exp"],
+        1,
+        "Exponential of the top of the stack",
+        |c| call(c, Function::Exp),
+        Exprs::Unary(|a| Expr::call(Function::Exp, a)),
+    ),
+    plain(
+        &["ln"],
+        1,
+        "Natural logarithm of the top of the stack",
+        |c| call(c, Function::Ln),
+        Exprs::Unary(|a| Expr::call(Function::Ln, a)),
+    ),
+    plain(
+        &["sin"],
+        1,
+        "Sine of the top of the stack",
+        |c| call(c, Function::Sin),
+        Exprs::Unary(|a| Expr::call(Function::Sin, a)),
+    ),
+    plain(
+        &["cos"],
+        1,
+        "Cosine of the top of the stack",
+        |c| call(c, Function::Cos),
+        Exprs::Unary(|a| Expr::call(Function::Cos, a)),
+    ),
+    plain(
+        &["tan"],
+        1,
+        "Tangent of the top of the stack",
+        |c| call(c, Function::Tan),
+        Exprs::Unary(|a| Expr::call(Function::Tan, a)),
+    ),
+    plain(
+        &["asin"],
+        1,
+        "Inverse sine of the top of the stack",
+        |c| call(c, Function::Asin),
+        Exprs::Unary(|a| Expr::call(Function::Asin, a)),
+    ),
+    plain(
+        &["acos"],
+        1,
+        "Inverse cosine of the top of the stack",
+        |c| call(c, Function::Acos),
+        Exprs::Unary(|a| Expr::call(Function::Acos, a)),
+    ),
+    plain(
+        &["atan"],
+        1,
+        "Inverse tangent of the top of the stack",
+        |c| call(c, Function::Atan),
+        Exprs::Unary(|a| Expr::call(Function::Atan, a)),
+    ),
+    plain(
+        &["deg"],
+        0,
+        "Trigonometric functions on angles in degrees",
+        |c| set_angle(c, AngleMode::Degrees),
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["rad"],
+        0,
+        "Trigonometric functions on angles in radians (default)",
+        |c| set_angle(c, AngleMode::Radians),
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["dup"],
+        1,
+        "Duplicate the top of the stack",
+        duplicate,
+        Exprs::Stack(|e| e.push(e.last().unwrap().clone())),
+    ),
+    plain(
+        &["swap"],
+        2,
+        "Swap the top two levels of the stack",
+        swap,
+        Exprs::Stack(|e| {
+            let n = e.len();
+            e.swap(n - 2, n - 1)
+        }),
+    ),
+    plain(
+        &["drop"],
+        1,
+        "Drop the top of the stack",
+        drop,
+        Exprs::Stack(|e| e.truncate(e.len() - 1)),
+    ),
+    plain(
+        &["++", "aa"],
+        1,
+        "Sum all the stack",
+        add_all,
+        Exprs::Stack(|e| reduce(e, |a, b| a + b)),
+    ),
+    plain(
+        &["**", "xx"],
+        1,
+        "Multiply all the stack",
+        mult_all,
+        Exprs::Stack(|e| reduce(e, |a, b| a * b)),
+    ),
+    plain(
+        &["c", "clear"],
+        0,
+        "Clear the stack",
+        clear,
+        Exprs::Stack(Vec::clear),
+    ),
+    plain(
+        &["p", "print"],
+        0,
+        "Display the stack",
+        list,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["exprs"],
+        "on|off",
+        "Track the expression that produced each stack level",
+        cli::switch,
+        |c, on| track_exprs_mode(c, on.is_on()?),
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["expr"],
+        0,
+        "Display the expression of the top of the stack",
+        show_expr,
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["subst"],
+        3,
+        "Substitute a value for a symbol, e.g. <expr> 5 'x subst",
+        substitute,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["eval"],
+        1,
+        "Evaluate the expression on top of the stack to a number",
+        eval,
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["deriv"],
+        2,
+        "Differentiate an expression by a symbol, e.g. <expr> 'x deriv",
+        derivative,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["def"],
+        2,
+        "Define a word running a program, e.g. { dup * } 'sq def",
+        define,
+        Exprs::Stack(|e| e.truncate(e.len() - 2)),
+    ),
+    with_line(
+        &["defscript"],
+        "<script>",
+        "Define a word computed by a script, e.g. hyp(a, b) = sqrt(a^2 + b^2)\n\
+         with let, if, else, while and return, as in f(n) { return n*2 }",
+        cli::script,
+        |c, script| define_script(c, script.script()?),
+    ),
+    with_line(
+        &["alias"],
+        "[<name> <command>]",
+        "Make name run a command, e.g. alias m *, or list the aliases",
+        cli::alias,
+        alias_command,
+    ),
+    with_argument(
+        &["unalias"],
+        "<name>",
+        "Remove an alias, built-in ones included, e.g. unalias x",
+        cli::alias_name,
+        |c, name| c.aliases.remove(name.word()?),
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["trace"],
+        "on|off",
+        "Print each command run with the stack before and after it",
+        cli::switch,
+        set_trace,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["step"],
+        "on|off",
+        "Pause after each command run: s or Enter to step, c to continue, q to stop",
+        cli::switch,
+        set_step,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["break"],
+        "<word>",
+        "Pause before the word runs, as the step mode does",
+        cli::word_name,
+        set_breakpoint,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["unbreak"],
+        "<word>",
+        "Remove the breakpoint on the word",
+        cli::word_name,
+        remove_breakpoint,
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["solve"],
+        2,
+        "Root of a program near a guess or in a bracket, e.g. { sq 2 - } 1 solve\n\
+         or x of A*x = b from A and b, e.g. [[2 0][0 4]] [1 2] solve",
+        solve,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["integrate"],
+        3,
+        "Integral of a program and its error, e.g. { sin } 0 3.14 integrate",
+        integrate,
+        Exprs::Results(2),
+    ),
+    with_argument(
+        &["tolerance"],
+        "<tol>",
+        "Relative tolerance of integrate (default: 1e-10)",
+        cli::positive_number,
+        set_tolerance,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["maxevals"],
+        "<n>",
+        "Function evaluations allowed to integrate (default: 10000)",
+        cli::positive_integer,
+        set_max_evaluations,
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["prec"],
+        "<digits>|off",
+        "Compute with numbers of that many digits, or f64 numbers",
+        cli::digits,
+        |c, digits| set_precision(c, digits.count().ok()),
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["atomic"],
+        "on|off",
+        "Undo a line at its first error, skipping the rest of it",
+        cli::switch,
+        set_atomic,
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["dot"],
+        2,
+        "Dot product of the top two vectors",
+        |c| binary_vector(c, |a, b| Ok(Value::Number(dot(a, b)?))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["cross"],
+        2,
+        "Cross product of the top two vectors",
+        |c| binary_vector(c, cross),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["norm"],
+        1,
+        "Euclidean norm of a vector",
+        |c| unary_vector(c, |v| Value::Number(dot(v, v).unwrap().sqrt())),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["len"],
+        1,
+        "Number of elements of a vector",
+        |c| unary_vector(c, |v| Value::Number(v.len() as f64)),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["sum"],
+        1,
+        "Sum of the elements of a vector",
+        |c| unary_vector(c, |v| Value::Number(v.iter().sum())),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["prod"],
+        1,
+        "Product of the elements of a vector",
+        |c| unary_vector(c, |v| Value::Number(v.iter().product())),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["get"],
+        2,
+        "Element of a vector from 1, e.g. [4 5 6] 2 get",
+        get,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["->list"],
+        1,
+        "Pack the n numbers below n in a vector, e.g. 1 2 3 3 ->list",
+        to_list,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["list->"],
+        1,
+        "Push the elements of a vector to the stack",
+        from_list,
+        Exprs::Replaced(1),
+    ),
+    plain(
+        &["trn"],
+        1,
+        "Transpose of a matrix",
+        |c| unary(c, |a| Ok(Value::Matrix(matrix(a)?.transpose()))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["det"],
+        1,
+        "Determinant of a matrix",
+        |c| unary(c, |a| Ok(Value::Number(matrix(a)?.det()?))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["inv"],
+        1,
+        "Inverse of a matrix",
+        |c| unary(c, |a| Ok(Value::Matrix(matrix(a)?.inverse()?))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["rank"],
+        1,
+        "Rank of a matrix",
+        |c| unary(c, |a| Ok(Value::Number(matrix(a)?.rank() as f64))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["identity"],
+        1,
+        "Identity matrix of size n, e.g. 3 identity",
+        identity,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["lu"],
+        1,
+        "L, U and P of P*A = L*U, L having a unit diagonal",
+        lu,
+        Exprs::Results(3),
+    ),
+    plain(
+        &["qr"],
+        1,
+        "Q and R of A = Q*R, Q orthogonal and R upper triangular",
+        qr,
+        Exprs::Results(2),
+    ),
+    plain(
+        &["today"],
+        0,
+        "Push the current date",
+        |c| push(c, Value::Date(DateTime::today())),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["now"],
+        0,
+        "Push the current date and time",
+        |c| push(c, Value::Date(DateTime::now())),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["dow"],
+        1,
+        "Day of the week of a date, 1 for Monday to 7 for Sunday",
+        |c| unary(c, |a| Ok(Value::Number(date(a)?.day_of_week() as f64))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["addbd"],
+        2,
+        "Add weekdays to a date, e.g. 2026-10-16 1 addbd",
+        add_business_days,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["bdays"],
+        2,
+        "Weekdays from a date to another, the last one excluded",
+        business_days,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["->unix"],
+        1,
+        "Convert a date to Unix time seconds",
+        |c| unary(c, |a| Ok(Value::Number(date(a)?.unix() as f64))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["unix->"],
+        1,
+        "Convert Unix time seconds to a date",
+        from_unix,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["polyval"],
+        2,
+        "Value of a polynomial, e.g. [1 -3 2] 5 polyval",
+        poly_eval,
+        Exprs::Results(1),
+    ),
+    plain(
+        &["padd"],
+        2,
+        "Add the top two polynomials",
+        |c| binary_poly(c, poly::add),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["psub"],
+        2,
+        "Subtract the top two polynomials",
+        |c| binary_poly(c, |p, q| poly::add(p, &poly::negate(q))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["pmul"],
+        2,
+        "Multiply the top two polynomials",
+        |c| binary_poly(c, poly::mul),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["pdiv"],
+        2,
+        "Quotient and remainder of the top two polynomials",
+        poly_divide,
+        Exprs::Results(2),
+    ),
+    plain(
+        &["pder"],
+        1,
+        "Derivative of the polynomial on top of the stack",
+        |c| unary(c, |p| Ok(Value::Vector(poly::derivative(vector(p)?)))),
+        Exprs::Results(1),
+    ),
+    plain(
+        &["roots"],
+        1,
+        "Real parts, then imaginary parts of the roots of a polynomial",
+        roots,
+        Exprs::Results(2),
+    ),
+    with_argument(
+        &["save"],
+        "[file]",
+        "Save the session to file (default: session file)",
+        cli::file,
+        |c, file| save(c, file.word().ok()),
+        Exprs::Unchanged,
+    ),
+    with_argument(
+        &["load"],
+        "[file]",
+        "Restore the session from file (default: session file)",
+        cli::file,
+        |c, file| load(c, file.word().ok()),
+        Exprs::Reset,
+    ),
+    with_commands(
+        &["infix"],
+        "<expr>",
+        "Evaluate an infix expression, e.g. (8*8 + 6*6)^0.5",
+        infix::parse,
+    ),
+    with_line(
+        &["rpn"],
+        "<expr>",
+        "Display the RPN commands of an infix expression",
+        cli::rpn,
+        |c, rpn| show_rpn(c, rpn.text()?),
+    ),
+    plain(
+        &["h", "help"],
+        0,
+        "Display this message",
+        help,
+        Exprs::Unchanged,
+    ),
+    plain(
+        &["cls"],
+        0,
+        "Clear the cli screen",
+        clear_screen,
+        Exprs::Unchanged,
+    ),
+    // Read by the interfaces, which stop once the line ran
+    plain(
+        &["q", "quit"],
+        0,
+        "Quit the program",
+        |_| Ok(()),
+        Exprs::Unchanged,
+    ),
+];
+
+pub fn process(c: &mut RpnCalc, cmd: &CliCmd) -> Result<(), CalcError> {
+    // The observers see the commands of the lines only
+    if c.calls == 0 {
+        return observer::process(c, cmd, |c| debug_command(c, cmd));
+    }
+    debug_command(c, cmd)
+}
+
+fn debug_command(c: &mut RpnCalc, cmd: &CliCmd) -> Result<(), CalcError> {
+    if c.debugger.is_on() {
+        return debug::process(c, cmd, |c| run_command(c, cmd));
+    }
+    run_command(c, cmd)
+}
+
+fn run_command(c: &mut RpnCalc, cmd: &CliCmd) -> Result<(), CalcError> {
+    let result = apply(c, cmd);
+    if result.is_ok() && c.exprs.is_some() {
+        track_exprs(c, &cmd.oper);
+    }
+    result
+}
+
+// Runs the commands of a line until one fails, then gives its index with the
+// error, the stack being back as it was before the line
+pub(super) fn process_line(c: &mut RpnCalc, cmds: &[CliCmd]) -> Result<(), (usize, CalcError)> {
+    let stack = c.stack.clone();
+    let exprs = c.exprs.clone();
+    for (i, cmd) in cmds.iter().enumerate() {
+        if let Err(e) = process(c, cmd) {
+            let undone = std::mem::replace(&mut c.stack, stack);
+            observer::stack_changed(c, &undone);
+            c.exprs = exprs;
+            return Err((i, e));
+        }
+    }
+    Ok(())
+}
+
+fn apply(c: &mut RpnCalc, cmd: &CliCmd) -> Result<(), CalcError> {
     match cmd.oper {
-        CliOperation::Push(number) => push(c, number),
-        CliOperation::Add => add(c),
-        CliOperation::Subtract => subtract(c),
-        CliOperation::Multiply => multiply(c),
-        CliOperation::Divide => divide(c),
-        CliOperation::SquareRoot => square_root(c),
-        CliOperation::Power => power(c),
-        CliOperation::AddAll => add_all(c),
-        CliOperation::MultAll => mult_all(c),
-        CliOperation::Clear => clear(c),
-        CliOperation::List => list(c),
-        _ => {}
+        CliOperation::Push(number) => push_number(c, number),
+        CliOperation::PushDecimal(ref literal) => push_decimal(c, literal),
+        CliOperation::PushSymbol(ref name) => push(c, Value::Symbolic(Expr::Symbol(name.clone()))),
+        CliOperation::PushProgram(ref source) => push(c, Value::Program(Program::new(source))),
+        CliOperation::PushVector(ref v) => push(c, Value::Vector(v.clone())),
+        CliOperation::PushMatrix(ref m) => push(c, Value::Matrix(m.clone())),
+        CliOperation::PushDate(date) => push(c, Value::Date(date)),
+        CliOperation::PushDuration(duration) => push(c, Value::Duration(duration)),
+        CliOperation::Word(ref name) => word(c, name),
+        CliOperation::Registered(ref name) => registered(c, name),
+        CliOperation::Builtin(builtin, ref argument) => builtin.run(c, argument),
+        CliOperation::SyntaxError(ref e) => Err(CalcError::Syntax(e.clone())),
+        CliOperation::Unknown => Err(CalcError::UnknownCommand),
+        CliOperation::Empty => Ok(()),
     }
 }
 
-fn push(c: &mut RpnCalc, number: f64) {
-    c.stack.push(number)
+fn push(c: &mut RpnCalc, value: Value) -> Result<(), CalcError> {
+    c.stack.push(value);
+    Ok(())
 }
 
-fn clear(c: &mut RpnCalc) {
-    c.stack.clear()
+// Numbers are pushed as Big in the precision mode, from the shortest decimal
+// of the f64
+fn push_number(c: &mut RpnCalc, number: f64) -> Result<(), CalcError> {
+    match c.precision.and_then(|_| BigFloat::from_f64(number)) {
+        Some(n) => push(c, Value::Big(n)),
+        None => push(c, Value::Number(number)),
+    }
 }
 
-fn list(c: &RpnCalc) {
-    println!("{:?}", c.stack)
+// Every digit of the literal is kept in the precision mode, the operations
+// rounding their results
+fn push_decimal(c: &mut RpnCalc, literal: &str) -> Result<(), CalcError> {
+    let n = BigFloat::parse(literal).ok_or(CalcError::WrongType)?;
+    match c.precision {
+        Some(_) => push(c, Value::Big(n)),
+        None => push(c, Value::Number(n.to_f64())),
+    }
 }
 
-fn add(c: &mut RpnCalc) {
-    if c.stack.len() < 2 {
-        return;
+// The numbers on the stack switch to Big numbers, or back to f64
+fn set_precision(c: &mut RpnCalc, digits: Option<usize>) -> Result<(), CalcError> {
+    c.precision = digits;
+    for value in c.stack.iter_mut() {
+        *value = match (&value, digits) {
+            (Value::Number(n), Some(_)) => match BigFloat::from_f64(*n) {
+                Some(n) => Value::Big(n),
+                None => continue,
+            },
+            (Value::Big(n), None) => Value::Number(n.to_f64()),
+            _ => continue,
+        };
     }
+    Ok(())
+}
 
-    let result = c.stack.pop().unwrap() + c.stack.pop().unwrap();
-    c.stack.push(result);
+fn clear(c: &mut RpnCalc) -> Result<(), CalcError> {
+    c.stack.clear();
+    Ok(())
+}
+
+fn list(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let Some(exprs) = &c.exprs else {
+        let stack = format!("{:?}", c.stack);
+        c.print(stack);
+        return Ok(());
+    };
+
+    // One level per line, the top of the stack being level 1
+    let width = c
+        .stack
+        .iter()
+        .map(|n| n.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = c
+        .stack
+        .iter()
+        .zip(exprs.iter())
+        .enumerate()
+        .map(|(level, (number, expr))| {
+            format!("{}: {:<width$}  {}", c.stack.len() - level, number, expr)
+        })
+        .collect();
+    lines.into_iter().for_each(|line| c.print(line));
+    Ok(())
+}
+
+fn track_exprs_mode(c: &mut RpnCalc, on: bool) -> Result<(), CalcError> {
+    // The values already on the stack are their own expressions
+    c.exprs = on.then(|| c.stack.iter().map(Value::tracked_expr).collect());
+    Ok(())
+}
+
+fn show_expr(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let exprs = c.exprs.as_ref().ok_or(CalcError::ExprsOff)?;
+    if let Some(expr) = exprs.last().cloned() {
+        c.print(expr);
+    }
+    Ok(())
+}
+
+// Replays the operation on the expressions after it succeeded on the stack
+fn track_exprs(c: &mut RpnCalc, oper: &CliOperation) {
+    let exprs = c.exprs.as_mut().unwrap();
+    let mut reset = false;
+    match oper {
+        CliOperation::Push(number) => exprs.push(Expr::Number(*number)),
+        CliOperation::PushSymbol(name) => exprs.push(Expr::Symbol(name.clone())),
+        CliOperation::PushDecimal(_)
+        | CliOperation::PushProgram(_)
+        | CliOperation::PushVector(_)
+        | CliOperation::PushMatrix(_)
+        | CliOperation::PushDate(_)
+        | CliOperation::PushDuration(_) => exprs.push(c.stack.last().unwrap().tracked_expr()),
+        CliOperation::Builtin(builtin, _) => match builtin.exprs {
+            Exprs::Unchanged => {}
+            Exprs::Unary(f) => {
+                let a = exprs.pop().unwrap();
+                exprs.push(f(a));
+            }
+            Exprs::Binary(f) => {
+                let b = exprs.pop().unwrap();
+                let a = exprs.pop().unwrap();
+                exprs.push(f(a, b));
+            }
+            Exprs::Stack(f) => f(exprs),
+            // The expressions are the results themselves
+            Exprs::Results(produced) => {
+                let consumed = exprs.len() + produced - c.stack.len();
+                replace_results(exprs, &c.stack, consumed);
+            }
+            Exprs::Replaced(consumed) => replace_results(exprs, &c.stack, consumed),
+            Exprs::Reset => reset = true,
+        },
+        // The operands are replaced by the results, as expressions, while
+        // words running programs tracked their commands
+        CliOperation::Registered(_) | CliOperation::Word(_) => {
+            let arity = match oper {
+                CliOperation::Registered(name) => registry::registered(name).map(|op| op.arity()),
+                CliOperation::Word(name) => c.scripts.get(name).map(Script::arity),
+                _ => None,
+            };
+            if let Some(arity) = arity {
+                let kept = exprs.len().saturating_sub(arity).min(c.stack.len());
+                replace_results(exprs, &c.stack, exprs.len() - kept);
+            }
+        }
+        CliOperation::SyntaxError(_) | CliOperation::Unknown | CliOperation::Empty => {}
+    }
+
+    // Operations replacing the stack, like load, restart from the values
+    if reset || exprs.len() != c.stack.len() {
+        *exprs = c.stack.iter().map(Value::tracked_expr).collect();
+    }
+}
+
+// Replaces the expressions of the levels consumed by those of the results
+fn replace_results(exprs: &mut Vec<Expr>, stack: &[Value], consumed: usize) {
+    exprs.truncate(exprs.len() - consumed);
+    exprs.extend(stack[exprs.len()..].iter().map(Value::tracked_expr));
+}
+
+// The expressions of the stack combined into one, as by ++ and **
+fn reduce(exprs: &mut Vec<Expr>, f: fn(Expr, Expr) -> Expr) {
+    let result = exprs.drain(..).reduce(f).unwrap();
+    exprs.push(result);
+}
+
+fn show_rpn(c: &mut RpnCalc, rpn: &str) -> Result<(), CalcError> {
+    c.print(rpn);
+    Ok(())
+}
+
+fn session_file(file: Option<&str>) -> Result<PathBuf, CalcError> {
+    match file {
+        Some(file) => Ok(PathBuf::from(file)),
+        None => Ok(session::default_session_file().ok_or(SessionError::NoDefaultFile)?),
+    }
+}
+
+fn save(c: &RpnCalc, file: Option<&str>) -> Result<(), CalcError> {
+    c.save_session(&session_file(file)?)
+}
+
+fn load(c: &mut RpnCalc, file: Option<&str>) -> Result<(), CalcError> {
+    c.load_session(&session_file(file)?)
+}
+
+fn help(c: &mut RpnCalc) -> Result<(), CalcError> {
+    c.print("Commands:");
+    let help = c.aliases.help();
+    let syntax = SYNTAX_HELP.iter().map(|(usage, help)| (*usage, *help));
+    let commands = help
+        .iter()
+        .map(|(usage, help)| (usage.as_str(), help.as_str()));
+    for (usage, help) in syntax.chain(commands) {
+        for (i, line) in help.lines().enumerate() {
+            c.print(format!("  {:<24}{}", if i == 0 { usage } else { "" }, line));
+        }
+    }
+    Ok(())
+}
+
+// Only the terminal is cleared, the other outputs being lines
+fn clear_screen(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if c.output == Output::Stdout {
+        print!("\x1B[2J\x1B[1;1H");
+    }
+    Ok(())
+}
+
+fn set_angle(c: &mut RpnCalc, angle: AngleMode) -> Result<(), CalcError> {
+    c.angle = angle;
+    Ok(())
+}
+
+fn set_atomic(c: &mut RpnCalc, on: &Argument) -> Result<(), CalcError> {
+    c.atomic = on.is_on()?;
+    Ok(())
+}
+
+fn set_tolerance(c: &mut RpnCalc, tolerance: &Argument) -> Result<(), CalcError> {
+    c.tolerance = tolerance.number()?;
+    Ok(())
+}
+
+fn set_max_evaluations(c: &mut RpnCalc, n: &Argument) -> Result<(), CalcError> {
+    c.max_evaluations = n.count()?;
+    Ok(())
+}
+
+fn set_trace(c: &mut RpnCalc, on: &Argument) -> Result<(), CalcError> {
+    c.debugger.trace = on.is_on()?;
+    Ok(())
+}
+
+// The step mode pauses from the next line
+fn set_step(c: &mut RpnCalc, on: &Argument) -> Result<(), CalcError> {
+    let on = on.is_on()?;
+    c.debugger.step = on;
+    c.debugger.stepping &= on;
+    Ok(())
+}
+
+fn set_breakpoint(c: &mut RpnCalc, name: &Argument) -> Result<(), CalcError> {
+    let name = name.word()?;
+    check_word_name(&c.aliases, name)?;
+    c.debugger.breakpoints.insert(name.to_string());
+    Ok(())
+}
+
+fn remove_breakpoint(c: &mut RpnCalc, name: &Argument) -> Result<(), CalcError> {
+    let name = name.word()?;
+    if !c.debugger.breakpoints.remove(name) {
+        return Err(CalcError::Syntax(format!("No breakpoint on '{}'", name)));
+    }
+    Ok(())
+}
+
+// A date and a duration give a date, two durations a duration
+fn add(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    binary(c, |a, b| match (a, b) {
+        (Value::Date(date), Value::Duration(d)) | (Value::Duration(d), Value::Date(date)) => {
+            date_value(date.add(*d))
+        }
+        (Value::Duration(a), Value::Duration(b)) => duration_value(a.add(*b)),
+        _ => match big_operands(a, b) {
+            Some((a, b)) => Ok(Value::Big(a.add(&b, digits))),
+            None => Value::binary(a, b, |a, b| Ok(a + b), |a, b| a + b),
+        },
+    })
+}
+
+// The duration between two dates, or a date a duration earlier
+fn subtract(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    binary(c, |a, b| match (a, b) {
+        (Value::Date(a), Value::Date(b)) => Ok(Value::Duration(a.since(b))),
+        (Value::Date(date), Value::Duration(d)) => date_value(date.add(d.negate())),
+        (Value::Duration(a), Value::Duration(b)) => duration_value(a.add(b.negate())),
+        _ => match big_operands(a, b) {
+            Some((a, b)) => Ok(Value::Big(a.sub(&b, digits))),
+            None => Value::binary(a, b, |a, b| Ok(a - b), |a, b| a - b),
+        },
+    })
+}
+
+// The matrix product on matrices, vectors being columns on the right of a
+// matrix and rows on its left
+fn multiply(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    binary(c, |a, b| match (a, b) {
+        (Value::Matrix(a), Value::Matrix(b)) => Ok(Value::Matrix(a.mul(b)?)),
+        (Value::Matrix(a), Value::Vector(b)) => {
+            Ok(Value::Vector(a.mul(&Matrix::column(b)?)?.into_vector()))
+        }
+        (Value::Vector(a), Value::Matrix(b)) => {
+            let row = Matrix::column(a)?.transpose();
+            Ok(Value::Vector(row.mul(b)?.into_vector()))
+        }
+        (Value::Duration(d), n @ (Value::Number(_) | Value::Big(_)))
+        | (n @ (Value::Number(_) | Value::Big(_)), Value::Duration(d)) => {
+            duration_value(d.scale(n.number().unwrap()))
+        }
+        _ => match big_operands(a, b) {
+            Some((a, b)) => Ok(Value::Big(a.mul(&b, digits))),
+            None => Value::binary(a, b, |a, b| Ok(a * b), |a, b| a * b),
+        },
+    })
+}
+
+// The numeric checks apply to every element of vectors and matrices, and to
+// the number operand of an expression. Matrices are only divided by numbers.
+fn divide(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    binary(c, |dividend, divisor| {
+        if matches!(divisor, Value::Matrix(_)) {
+            return Err(CalcError::WrongType);
+        }
+        if let Some((a, b)) = big_operands(dividend, divisor) {
+            return a
+                .div(&b, digits)
+                .map(Value::Big)
+                .ok_or(CalcError::ZeroDivision);
+        }
+        if *divisor == 0.0 || matches!(divisor, Value::Duration(d) if d.is_zero()) {
+            return Err(CalcError::ZeroDivision);
+        }
+        // A duration divided by a number, or the ratio of two durations
+        match (dividend, divisor) {
+            (Value::Duration(d), n @ (Value::Number(_) | Value::Big(_))) => {
+                return duration_value(d.scale(1.0 / n.number().unwrap()))
+            }
+            (Value::Duration(a), Value::Duration(b)) => return Ok(Value::Number(a.ratio(b))),
+            _ => {}
+        }
+
+        let number = |a: f64, b: f64| match b {
+            0.0 => Err(CalcError::ZeroDivision),
+            b => Ok(a / b),
+        };
+        Value::binary(dividend, divisor, number, |a, b| a / b)
+    })
+}
+
+fn square_root(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    unary(c, |a| {
+        if matches!(a, Value::Matrix(_)) {
+            return Err(CalcError::WrongType);
+        }
+        if let Value::Big(a) = a {
+            return Ok(Value::Big(
+                a.sqrt(digits).ok_or(CalcError::NegativeSquareRoot)?,
+            ));
+        }
+        let number = |a: f64| match a {
+            a if a < 0.0 => Err(CalcError::NegativeSquareRoot),
+            a => Ok(a.sqrt()),
+        };
+        Value::unary(a, number, |a| Expr::call(Function::Sqrt, a))
+    })
+}
+
+fn power(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let digits = digits(c);
+    binary(c, |base, exponent| {
+        if matches!(base, Value::Matrix(_)) || matches!(exponent, Value::Matrix(_)) {
+            return Err(CalcError::WrongType);
+        }
+        if let Some((a, b)) = big_operands(base, exponent) {
+            if a.is_zero() && b.is_zero() {
+                return Err(CalcError::ZeroPowerZero);
+            }
+            let power = a.pow(&b, digits).ok_or(CalcError::OutOfDomain("^"))?;
+            return Ok(Value::Big(power));
+        }
+        if *base == 0.0 && *exponent == 0.0 {
+            return Err(CalcError::ZeroPowerZero);
+        }
+        let number = |a: f64, b: f64| match (a, b) {
+            (0.0, 0.0) => Err(CalcError::ZeroPowerZero),
+            (a, b) => Ok(a.powf(b)),
+        };
+        Value::binary(base, exponent, number, Expr::pow)
+    })
+}
+
+fn negate(c: &mut RpnCalc) -> Result<(), CalcError> {
+    unary(c, |a| match a {
+        Value::Duration(d) => Ok(Value::Duration(d.negate())),
+        Value::Big(n) => Ok(Value::Big(n.neg())),
+        a => Value::unary(a, |a| Ok(-a), |a| -a),
+    })
+}
+
+fn call(c: &mut RpnCalc, function: Function) -> Result<(), CalcError> {
+    let digits = digits(c);
+    let degrees = c.angle == AngleMode::Degrees;
+    unary(c, |a| match a {
+        Value::Number(n) if !function.in_domain(*n) => Err(CalcError::OutOfDomain(function.name())),
+        Value::Number(n) if degrees => Ok(Value::Number(function.apply_degrees(*n))),
+        Value::Number(n) => Ok(Value::Number(function.apply(*n))),
+        Value::Big(n) if degrees => match n.apply_degrees(function, digits) {
+            Some(n) => Ok(Value::Big(n)),
+            None => Err(CalcError::OutOfDomain(function.name())),
+        },
+        Value::Big(n) => match n.apply(function, digits) {
+            Some(n) => Ok(Value::Big(n)),
+            None => Err(CalcError::OutOfDomain(function.name())),
+        },
+        Value::Symbolic(a) => Ok(Value::symbolic(Expr::call(function, a.clone()))),
+        _ => Err(CalcError::WrongType),
+    })
+}
+
+fn add_all(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if c.stack.is_empty() {
+        return Err(CalcError::StackUnderflow);
+    }
+
+    let digits = digits(c);
+    let sum = match (bigs(&c.stack), numbers(&c.stack)) {
+        (Some(bigs), _) => {
+            let sum = bigs.into_iter().reduce(|a, b| a.add(&b, digits));
+            Value::Big(sum.unwrap())
+        }
+        (None, Some(numbers)) => Value::Number(numbers.iter().sum()),
+        (None, None) => {
+            let exprs: Option<Vec<Expr>> = c.stack.iter().map(Value::to_expr).collect();
+            let exprs = exprs.ok_or(CalcError::WrongType)?;
+            Value::symbolic(exprs.into_iter().reduce(|a, b| a + b).unwrap())
+        }
+    };
+    c.stack.clear();
+    c.stack.push(sum);
     print_top(c);
+    Ok(())
 }
 
-fn subtract(c: &mut RpnCalc) {
-    if c.stack.len() < 2 {
-        return;
+fn mult_all(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if c.stack.is_empty() {
+        return Err(CalcError::StackUnderflow);
     }
 
-    let subtrahend = c.stack.pop().unwrap();
-    let minuend = c.stack.pop().unwrap();
-    c.stack.push(minuend - subtrahend);
+    let digits = digits(c);
+    let product = match (bigs(&c.stack), numbers(&c.stack)) {
+        (Some(bigs), _) => {
+            let product = bigs.into_iter().reduce(|a, b| a.mul(&b, digits));
+            Value::Big(product.unwrap())
+        }
+        (None, Some(numbers)) => {
+            let mut mult: f64 = 1.0;
+            for i in numbers.iter() {
+                mult *= i;
+            }
+            Value::Number(mult)
+        }
+        (None, None) => {
+            let exprs: Option<Vec<Expr>> = c.stack.iter().map(Value::to_expr).collect();
+            let exprs = exprs.ok_or(CalcError::WrongType)?;
+            Value::symbolic(exprs.into_iter().reduce(|a, b| a * b).unwrap())
+        }
+    };
+    c.stack.clear();
+    c.stack.push(product);
     print_top(c);
+    Ok(())
 }
 
-fn multiply(c: &mut RpnCalc) {
-    if c.stack.len() < 2 {
-        return;
+// The expression below the top two levels with the value of level 2
+// substituted for the symbol on level 1: `'x 1 + 5 'x subst` is 6
+fn substitute(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if c.stack.len() < 3 {
+        return Err(CalcError::StackUnderflow);
     }
+    let Some(Value::Symbolic(Expr::Symbol(name))) = c.stack.last() else {
+        return Err(CalcError::NotASymbol);
+    };
+
+    let len = c.stack.len();
+    let (Some(expr), Some(value)) = (c.stack[len - 3].to_expr(), c.stack[len - 2].to_expr()) else {
+        return Err(CalcError::WrongType);
+    };
 
-    let result = c.stack.pop().unwrap() * c.stack.pop().unwrap();
+    let result = Value::symbolic(expr.substitute(name, &value));
+    c.stack.truncate(len - 3);
     c.stack.push(result);
     print_top(c);
+    Ok(())
 }
 
-fn divide(c: &mut RpnCalc) {
-    if c.stack.len() < 2 {
-        return;
+// The derivative of the expression on level 2 by the symbol on level 1
+fn derivative(c: &mut RpnCalc) -> Result<(), CalcError> {
+    binary(c, |expr, symbol| {
+        let Value::Symbolic(Expr::Symbol(name)) = symbol else {
+            return Err(CalcError::NotASymbol);
+        };
+        let expr = expr.to_expr().ok_or(CalcError::WrongType)?;
+        Ok(Value::symbolic(expr.derivative(name)))
+    })
+}
+
+// Evaluates an expression to a number, or runs a program
+fn eval(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if let Some(Value::Program(program)) = c.stack.last() {
+        let program = program.clone();
+        c.stack.pop();
+        if let Some(exprs) = &mut c.exprs {
+            exprs.pop();
+        }
+        return run(c, &program);
     }
+    unary(c, |a| {
+        let expr = a.to_expr().ok_or(CalcError::WrongType)?;
+        let number = expr.eval().map_err(CalcError::UnboundSymbol)?;
+        Ok(Value::Number(number))
+    })
+}
 
-    if *c.stack.last().unwrap() == 0.0 {
-        println!("Error: Zero division");
-        return;
+fn word(c: &mut RpnCalc, name: &str) -> Result<(), CalcError> {
+    if let Some(script) = c.scripts.get(name) {
+        let script = script.clone();
+        return run_script(c, &script);
+    }
+    let program = c.words.get(name).ok_or(CalcError::UnknownCommand)?.clone();
+    run(c, &program)
+}
+
+// Runs the commands of a program, printing only the final top of the stack
+pub(super) fn run(c: &mut RpnCalc, program: &Program) -> Result<(), CalcError> {
+    if c.calls >= MAX_NESTED_CALLS {
+        return Err(CalcError::TooManyNestedCalls);
     }
 
-    let divisor = c.stack.pop().unwrap();
-    let dividend = c.stack.pop().unwrap();
-    c.stack.push(dividend / divisor);
+    c.calls += 1;
+    let echo = std::mem::replace(&mut c.echo, false);
+    let result = program
+        .commands()
+        .iter()
+        .try_for_each(|cmd| match cmd.oper {
+            CliOperation::Unknown => Err(CalcError::UnknownCommand),
+            _ => process(c, cmd),
+        });
+    c.echo = echo;
+    c.calls -= 1;
+    result?;
     print_top(c);
+    Ok(())
 }
 
-fn square_root(c: &mut RpnCalc) {
-    if c.stack.len() < 1 {
-        return;
+// The script replaces the top levels of the stack with its results, leaving
+// the stack unchanged when it fails
+fn run_script(c: &mut RpnCalc, script: &Script) -> Result<(), CalcError> {
+    let len = c.stack.len();
+    if len < script.arity() {
+        return Err(CalcError::StackUnderflow);
     }
+    let args: Option<Vec<f64>> = c.stack[len - script.arity()..]
+        .iter()
+        .map(Value::number)
+        .collect();
+    let results = script.call(&args.ok_or(CalcError::WrongType)?, c.angle)?;
+    c.stack.truncate(len - script.arity());
+    for result in results {
+        push_number(c, result)?;
+    }
+    print_top(c);
+    Ok(())
+}
 
-    if *c.stack.last().unwrap() < 0.0 {
-        println!("Error: Negative number square root");
-        return;
+// The stack is left unchanged when the operation fails
+fn registered(c: &mut RpnCalc, name: &str) -> Result<(), CalcError> {
+    let operation = registry::registered(name).ok_or(CalcError::UnknownCommand)?;
+    if c.stack.len() < operation.arity() {
+        return Err(CalcError::StackUnderflow);
+    }
+    // The results are tracked as their own expressions, once it succeeded
+    let exprs = c.exprs.take();
+    let stack = c.stack.clone();
+    let result = operation.execute(c);
+    c.exprs = exprs;
+    if result.is_err() {
+        c.stack = stack;
     }
+    result?;
+    print_top(c);
+    Ok(())
+}
 
-    let result = c.stack.pop().unwrap().sqrt();
-    c.stack.push(result);
+// `{ program } 'name def` makes `name` run the program
+fn define(c: &mut RpnCalc) -> Result<(), CalcError> {
+    if c.stack.len() < 2 {
+        return Err(CalcError::StackUnderflow);
+    }
+    let len = c.stack.len();
+    let Value::Symbolic(Expr::Symbol(name)) = &c.stack[len - 1] else {
+        return Err(CalcError::NotASymbol);
+    };
+    let Value::Program(program) = &c.stack[len - 2] else {
+        return Err(CalcError::WrongType);
+    };
+    check_word_name(&c.aliases, name)?;
+
+    c.scripts.remove(name);
+    c.words.insert(name.clone(), program.clone());
+    c.stack.truncate(len - 2);
+    Ok(())
+}
+
+// `defscript name(a, b) ...` makes `name` run the script, replacing a word
+// of the same name
+fn define_script(c: &mut RpnCalc, script: &Script) -> Result<(), CalcError> {
+    check_word_name(&c.aliases, script.name())?;
+    c.words.remove(script.name());
+    c.scripts.insert(script.name().to_string(), script.clone());
+    Ok(())
+}
+
+// The names of the words can't be taken by aliases, which would hide them
+pub(super) fn alias(c: &mut RpnCalc, name: &str, command: &str) -> Result<(), CalcError> {
+    if c.words.contains_key(name) || c.scripts.contains_key(name) {
+        return Err(CalcError::ReservedName(name.to_string()));
+    }
+    c.aliases.set(name, command)
+}
+
+// `alias <name> <command>`, or `alias` alone listing the aliases
+fn alias_command(c: &mut RpnCalc, argument: &Argument) -> Result<(), CalcError> {
+    if let Argument::Alias(name, command) = argument {
+        return alias(c, name, command);
+    }
+    for (alias, command) in c.aliases.list() {
+        c.print(format!("{:<8}{}", alias, command));
+    }
+    Ok(())
+}
+
+// Words can't be named like the commands, nor like the aliases, which would
+// hide them, be they those of the calculator or the built-in ones programs
+// are read with
+fn check_word_name(aliases: &Aliases, name: &str) -> Result<(), CalcError> {
+    for aliases in [aliases, &DEFAULT_ALIASES] {
+        let oper = CliCmd::parse_individual_raw_command(name, aliases).oper;
+        if oper != CliOperation::Word(name.to_string()) {
+            return Err(CalcError::ReservedName(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn duplicate(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let top = c.stack.last().ok_or(CalcError::StackUnderflow)?.clone();
+    c.stack.push(top);
     print_top(c);
+    Ok(())
 }
 
-fn power(c: &mut RpnCalc) {
+fn swap(c: &mut RpnCalc) -> Result<(), CalcError> {
     if c.stack.len() < 2 {
-        return;
+        return Err(CalcError::StackUnderflow);
     }
+    let len = c.stack.len();
+    c.stack.swap(len - 2, len - 1);
+    print_top(c);
+    Ok(())
+}
 
-    let exponent = c.stack.pop().unwrap();
-    let base = c.stack.pop().unwrap();
+fn drop(c: &mut RpnCalc) -> Result<(), CalcError> {
+    c.stack.pop().ok_or(CalcError::StackUnderflow)?;
+    Ok(())
+}
 
-    if exponent == 0.0 && base == 0.0 {
-        println!("Error: 0 power 0 is undefined");
-        c.stack.push(0.0);
-        c.stack.push(0.0);
-        return;
+// `f guess solve` or `f a b solve`, f being a program or the name of a word
+// computing f(x) from x on the stack. `A b solve` is x of A*x = b, b being a
+// vector or a matrix of right-hand sides.
+fn solve(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let len = c.stack.len();
+    if let [.., Value::Matrix(_), ref b] = c.stack[..] {
+        if matches!(b, Value::Vector(_) | Value::Matrix(_)) {
+            return binary(c, |a, b| {
+                let a = matrix(a)?;
+                match b {
+                    Value::Vector(b) => {
+                        Ok(Value::Vector(a.solve(&Matrix::column(b)?)?.into_vector()))
+                    }
+                    b => Ok(Value::Matrix(a.solve(matrix(b)?)?)),
+                }
+            });
+        }
     }
-    c.stack.push(base.powf(exponent));
+    let (function, consumed) = match c.stack[..] {
+        [.., ref f, Value::Number(_), Value::Number(_)] if function_program(c, f).is_ok() => {
+            (function_program(c, f)?, 3)
+        }
+        [.., ref f, Value::Number(_)] => (function_program(c, f)?, 2),
+        [_, _, ..] => return Err(CalcError::WrongType),
+        _ => return Err(CalcError::StackUnderflow),
+    };
+
+    let f = |c: &mut RpnCalc, x| evaluate(c, &function, x);
+    let number = |i: usize| c.stack[i].number().unwrap();
+    let root = if consumed == 3 {
+        let (a, b) = (number(len - 2), number(len - 1));
+        solve::bracket(|x| f(c, x), a, b)?
+    } else {
+        let x = number(len - 1);
+        solve::guess(|x| f(c, x), x)?
+    };
+    c.stack.truncate(len - consumed);
+    c.stack.push(Value::Number(root));
     print_top(c);
+    Ok(())
 }
 
-fn add_all(c: &mut RpnCalc) {
-    if c.stack.is_empty() {
-        return;
+// `f a b integrate` pushes the integral of f from a to b, then its error
+fn integrate(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let len = c.stack.len();
+    if len < 3 {
+        return Err(CalcError::StackUnderflow);
+    }
+    let function = function_program(c, &c.stack[len - 3])?;
+    let (Some(a), Some(b)) = (c.stack[len - 2].number(), c.stack[len - 1].number()) else {
+        return Err(CalcError::WrongType);
+    };
+    if !a.is_finite() || !b.is_finite() {
+        return Err(CalcError::OutOfDomain("integrate"));
     }
 
-    let sum: f64 = c.stack.iter().sum();
-    clear(c);
-    c.stack.push(sum);
+    let (tolerance, max_evaluations) = (c.tolerance, c.max_evaluations);
+    let f = |x| evaluate(c, &function, x);
+    let (value, error) = integrate::integrate(f, a, b, tolerance, max_evaluations)?;
+    c.stack.truncate(len - 3);
+    c.stack.push(Value::Number(value));
+    c.stack.push(Value::Number(error));
     print_top(c);
+    Ok(())
 }
 
-fn mult_all(c: &mut RpnCalc) {
-    if c.stack.is_empty() {
-        return;
+// `p x polyval` is the polynomial p at x, an expression when x is symbolic
+fn poly_eval(c: &mut RpnCalc) -> Result<(), CalcError> {
+    binary(c, |p, x| {
+        let p = vector(p)?;
+        match x {
+            Value::Number(x) => Ok(Value::Number(poly::eval(p, *x))),
+            Value::Symbolic(x) => {
+                let degree = p.len().saturating_sub(1);
+                let terms = p.iter().enumerate().map(|(i, &a)| {
+                    let power = Expr::pow(x.clone(), Expr::Number((degree - i) as f64));
+                    Expr::Number(a) * power
+                });
+                Ok(Value::symbolic(
+                    terms.reduce(|a, b| a + b).unwrap_or(Expr::Number(0.0)),
+                ))
+            }
+            _ => Err(CalcError::WrongType),
+        }
+    })
+}
+
+fn binary_poly(c: &mut RpnCalc, f: fn(&[f64], &[f64]) -> Vec<f64>) -> Result<(), CalcError> {
+    binary(c, |p, q| Ok(Value::Vector(f(vector(p)?, vector(q)?))))
+}
+
+// `p q pdiv` pushes the quotient of p by q, then the remainder
+fn poly_divide(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let len = c.stack.len();
+    if len < 2 {
+        return Err(CalcError::StackUnderflow);
+    }
+    let (p, q) = (vector(&c.stack[len - 2])?, vector(&c.stack[len - 1])?);
+    let (quotient, remainder) = poly::div(p, q).ok_or(CalcError::ZeroDivision)?;
+    c.stack.truncate(len - 2);
+    c.stack.push(Value::Vector(quotient));
+    c.stack.push(Value::Vector(remainder));
+    print_top(c);
+    Ok(())
+}
+
+// Replaces a polynomial by the real parts of its roots, then their imaginary
+// parts: [1 0 1] roots is [0 0] [1 -1], the roots being i and -i
+fn roots(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let p = vector(c.stack.last().ok_or(CalcError::StackUnderflow)?)?;
+    let roots = poly::roots(p)?;
+    c.stack.pop();
+    c.stack
+        .push(Value::Vector(roots.iter().map(|r| r.0).collect()));
+    c.stack
+        .push(Value::Vector(roots.iter().map(|r| r.1).collect()));
+    print_top(c);
+    Ok(())
+}
+
+fn binary_vector<F>(c: &mut RpnCalc, f: F) -> Result<(), CalcError>
+where
+    F: Fn(&[f64], &[f64]) -> Result<Value, CalcError>,
+{
+    binary(c, |a, b| f(vector(a)?, vector(b)?))
+}
+
+fn unary_vector(c: &mut RpnCalc, f: fn(&[f64]) -> Value) -> Result<(), CalcError> {
+    unary(c, |a| Ok(f(vector(a)?)))
+}
+
+fn dot(a: &[f64], b: &[f64]) -> Result<f64, CalcError> {
+    if a.len() != b.len() {
+        return Err(CalcError::DimensionMismatch);
+    }
+    Ok(a.iter().zip(b.iter()).map(|(a, b)| a * b).sum())
+}
+
+fn cross(a: &[f64], b: &[f64]) -> Result<Value, CalcError> {
+    let ([a1, a2, a3], [b1, b2, b3]) = (a, b) else {
+        return Err(CalcError::DimensionMismatch);
+    };
+    Ok(Value::Vector(vec![
+        a2 * b3 - a3 * b2,
+        a3 * b1 - a1 * b3,
+        a1 * b2 - a2 * b1,
+    ]))
+}
+
+// `v i get` is the element i of v, counting from 1
+fn get(c: &mut RpnCalc) -> Result<(), CalcError> {
+    binary(c, |v, i| {
+        let (v, i) = (vector(v)?, i.number().ok_or(CalcError::WrongType)?);
+        if i.fract() != 0.0 || i < 1.0 || i > v.len() as f64 {
+            return Err(CalcError::IndexOutOfRange);
+        }
+        Ok(Value::Number(v[i as usize - 1]))
+    })
+}
+
+// `x1 ... xn n ->list` is the vector of the n numbers, the top being last
+fn to_list(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let n = c.stack.last().ok_or(CalcError::StackUnderflow)?;
+    let n = n.number().ok_or(CalcError::WrongType)?;
+    if n.fract() != 0.0 || n < 0.0 {
+        return Err(CalcError::OutOfDomain("->list"));
     }
-    let mut mult: f64 = 1.0;
-    for i in c.stack.iter() {
-        mult *= i;
+    let len = c.stack.len() - 1;
+    if len < n as usize {
+        return Err(CalcError::StackUnderflow);
     }
+    let n = n as usize;
+    let elements = numbers(&c.stack[len - n..len]).ok_or(CalcError::WrongType)?;
+    c.stack.truncate(len - n);
+    c.stack.push(Value::Vector(elements));
+    print_top(c);
+    Ok(())
+}
 
-    clear(c);
-    c.stack.push(mult);
+// Replaces a vector by its elements, the last one on top
+fn from_list(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let v = vector(c.stack.last().ok_or(CalcError::StackUnderflow)?)?.to_vec();
+    c.stack.pop();
+    c.stack.extend(v.into_iter().map(Value::Number));
     print_top(c);
+    Ok(())
 }
 
-fn print_top(c: &RpnCalc) {
-    if c.stack.is_empty() {
+// `n identity` is the identity matrix of size n, n being a positive integer
+fn identity(c: &mut RpnCalc) -> Result<(), CalcError> {
+    unary(c, |n| {
+        let n = n.number().ok_or(CalcError::WrongType)?;
+        if n.fract() != 0.0 || n < 1.0 {
+            return Err(CalcError::OutOfDomain("identity"));
+        }
+        Ok(Value::Matrix(Matrix::identity(n as usize)))
+    })
+}
+
+// Replaces a square matrix A by L, U and P such that P*A = L*U
+fn lu(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let a = matrix(c.stack.last().ok_or(CalcError::StackUnderflow)?)?;
+    let (l, u, p) = a.lu()?;
+    c.stack.pop();
+    c.stack.extend([l, u, p].map(Value::Matrix));
+    print_top(c);
+    Ok(())
+}
+
+// Replaces a matrix A by Q and R such that A = Q*R
+fn qr(c: &mut RpnCalc) -> Result<(), CalcError> {
+    let (q, r) = matrix(c.stack.last().ok_or(CalcError::StackUnderflow)?)?.qr();
+    c.stack.pop();
+    c.stack.extend([q, r].map(Value::Matrix));
+    print_top(c);
+    Ok(())
+}
+
+fn business_days(c: &mut RpnCalc) -> Result<(), CalcError> {
+    binary(c, |a, b| {
+        let days = date(a)?.business_days_until(date(b)?);
+        Ok(Value::Number(days as f64))
+    })
+}
+
+fn from_unix(c: &mut RpnCalc) -> Result<(), CalcError> {
+    unary(c, |a| {
+        let seconds = a.number().ok_or(CalcError::WrongType)?;
+        date_value(DateTime::from_unix(seconds))
+    })
+}
+
+// `date n addbd` is the date n weekdays later, n being an integer
+fn add_business_days(c: &mut RpnCalc) -> Result<(), CalcError> {
+    binary(c, |a, n| {
+        let (date, n) = (date(a)?, n.number().ok_or(CalcError::WrongType)?);
+        if n.fract() != 0.0 || n.abs() > 1e7 {
+            return Err(CalcError::OutOfDomain("addbd"));
+        }
+        date_value(date.add_business_days(n as i64))
+    })
+}
+
+fn date(value: &Value) -> Result<&DateTime, CalcError> {
+    match value {
+        Value::Date(date) => Ok(date),
+        _ => Err(CalcError::WrongType),
+    }
+}
+
+// Dates past the year 9999 and durations overflowing are out of range
+fn date_value(date: Option<DateTime>) -> Result<Value, CalcError> {
+    date.map(Value::Date).ok_or(CalcError::DateOutOfRange)
+}
+
+fn duration_value(duration: Option<Duration>) -> Result<Value, CalcError> {
+    duration
+        .map(Value::Duration)
+        .ok_or(CalcError::DateOutOfRange)
+}
+
+fn matrix(value: &Value) -> Result<&Matrix, CalcError> {
+    match value {
+        Value::Matrix(m) => Ok(m),
+        _ => Err(CalcError::WrongType),
+    }
+}
+
+fn vector(value: &Value) -> Result<&[f64], CalcError> {
+    match value {
+        Value::Vector(v) => Ok(v),
+        _ => Err(CalcError::WrongType),
+    }
+}
+
+// The program of a function argument, given as is or by a word name
+fn function_program(c: &RpnCalc, value: &Value) -> Result<Program, CalcError> {
+    match value {
+        Value::Program(program) => Ok(program.clone()),
+        Value::Symbolic(Expr::Symbol(name)) => {
+            c.words.get(name).cloned().ok_or(CalcError::UnknownCommand)
+        }
+        _ => Err(CalcError::WrongType),
+    }
+}
+
+// f(x), running the program on a stack holding only x
+fn evaluate(c: &mut RpnCalc, function: &Program, x: f64) -> Result<f64, CalcError> {
+    let stack = std::mem::replace(&mut c.stack, vec![Value::Number(x)]);
+    let exprs = c.exprs.take();
+    let echo = std::mem::replace(&mut c.echo, false);
+    let result = run(c, function);
+    let top = c.stack.pop();
+    c.stack = stack;
+    c.exprs = exprs;
+    c.echo = echo;
+    result?;
+    top.and_then(|top| top.number()).ok_or(CalcError::WrongType)
+}
+
+// Replaces the top two levels by the result of `f` on them, leaving the stack
+// unchanged on error
+fn binary<F>(c: &mut RpnCalc, f: F) -> Result<(), CalcError>
+where
+    F: Fn(&Value, &Value) -> Result<Value, CalcError>,
+{
+    if c.stack.len() < 2 {
+        return Err(CalcError::StackUnderflow);
+    }
+
+    let len = c.stack.len();
+    let result = f(&c.stack[len - 2], &c.stack[len - 1])?;
+    c.stack.truncate(len - 2);
+    c.stack.push(result);
+    print_top(c);
+    Ok(())
+}
+
+fn unary<F>(c: &mut RpnCalc, f: F) -> Result<(), CalcError>
+where
+    F: Fn(&Value) -> Result<Value, CalcError>,
+{
+    let Some(top) = c.stack.last() else {
+        return Err(CalcError::StackUnderflow);
+    };
+
+    let result = f(top)?;
+    c.stack.pop();
+    c.stack.push(result);
+    print_top(c);
+    Ok(())
+}
+
+// The stack as plain numbers, if it holds no expression
+fn numbers(stack: &[Value]) -> Option<Vec<f64>> {
+    stack.iter().map(Value::number).collect()
+}
+
+// The stack as Big numbers, if it holds one and otherwise only numbers
+fn bigs(stack: &[Value]) -> Option<Vec<BigFloat>> {
+    if !stack.iter().any(|value| matches!(value, Value::Big(_))) {
+        return None;
+    }
+    stack.iter().map(big).collect()
+}
+
+// Both operands as Big numbers, if one of them is and the other is a number
+fn big_operands(a: &Value, b: &Value) -> Option<(BigFloat, BigFloat)> {
+    if !matches!(a, Value::Big(_)) && !matches!(b, Value::Big(_)) {
+        return None;
+    }
+    big(a).zip(big(b))
+}
+
+fn big(value: &Value) -> Option<BigFloat> {
+    match value {
+        Value::Number(n) => BigFloat::from_f64(*n),
+        Value::Big(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+// Digits of the results on Big numbers
+fn digits(c: &RpnCalc) -> usize {
+    c.precision.unwrap_or(F64_DIGITS)
+}
+
+fn print_top(c: &mut RpnCalc) {
+    if !c.echo || c.stack.is_empty() {
         return;
     }
-    println!("{0}", c.stack.last().unwrap())
+    let top = c.stack.last().unwrap().to_string();
+    c.print(top);
 }
@@ -1,252 +1,735 @@
+use std::fmt;
 use std::io;
 use std::io::Write;
 
 use std::str::FromStr;
+use std::sync::Arc;
+
+use super::date::{DateTime, Duration};
+use super::editor::LineEditor;
+use super::error::CalcError;
+use super::infix;
+use super::matrix::Matrix;
+use super::registry::{self, Aliases, Builtin, Operation, Syntax, DEFAULT_ALIASES};
+use super::script::Script;
+use super::value::{Program, Value};
+use super::RpnCalc;
+
+// The help of what is typed other than commands
+pub(super) const SYNTAX_HELP: &[(&str, &str)] = &[
+    ("<number>", "Push a number to the stack"),
+    (
+        "'<name>",
+        "Push a symbol, e.g. 'x 2 ^ 3 'x * + is x^2 + 3*x",
+    ),
+    ("{ <commands> }", "Push a program, 'eval' runs it"),
+    (
+        "[<numbers>]",
+        "Push a vector, also a polynomial from its highest degree",
+    ),
+    ("", "+ - * / ^ sqrt neg apply elementwise, e.g. [1 2 3] 2 *"),
+    (
+        "[[<row>][<row>]]",
+        "Push a matrix, e.g. [[1 2][3 4]]; * is the matrix product",
+    ),
+    (
+        "<date> <duration>",
+        "Push a date or a duration, e.g. 2026-10-18 90d + or 12h30m",
+    ),
+    (
+        "",
+        "Dates are in UTC, with an optional time: 2026-10-18T12:30:00",
+    ),
+];
+
+pub const DEFAULT_PROMPT: &str = ">";
+
+// Literals with more significant digits are pushed as written, as are those
+// out of the range of f64
+const F64_DIGITS: usize = 15;
+
+// Largest number of digits of the precision mode
+pub(super) const MAX_PRECISION: usize = 10_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Notation {
+    Rpn,
+    Infix,
+}
 
 #[derive(Debug, PartialEq)]
 pub enum CliOperation {
     Push(f64),
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
-    SquareRoot,
-    Power,
-    AddAll,
-    MultAll,
-    Clear,
-    List,
-    Quit,
+    // A literal with more digits than a f64 holds, kept for the precision mode
+    PushDecimal(String),
+    PushSymbol(String),
+    PushProgram(String),
+    PushVector(Vec<f64>),
+    PushMatrix(Matrix),
+    PushDate(DateTime),
+    PushDuration(Duration),
+    Word(String),
+    // An operation registered by another crate
+    Registered(String),
+    // A built-in command, with what was read after its name
+    Builtin(&'static Builtin, Argument),
+    SyntaxError(String),
     Unknown,
-    Help,
-    ClearScreen,
     Empty,
 }
 
-pub struct CliCmd {
-    pub oper: CliOperation,
+impl CliOperation {
+    // The built-in command of that name, whatever the aliases of the user
+    pub(super) fn builtin(name: &str, argument: Argument) -> CliOperation {
+        let builtin = registry::command(name).expect("No built-in command of that name");
+        CliOperation::Builtin(builtin, argument)
+    }
+
+    // Whether this is the built-in command having that name
+    pub fn is_command(&self, name: &str) -> bool {
+        matches!(self, CliOperation::Builtin(builtin, _) if builtin.has_name(name))
+    }
 }
 
-impl CliCmd {
-    fn parse_individual_raw_command(s: &str) -> CliCmd {
-        if s.parse::<f64>().is_ok() {
-            return CliCmd::new_push_command(f64::from_str(&s).unwrap());
-        }
-        match s.to_lowercase().as_str() {
-            "+" | "a" | "add" => CliCmd::new_add_command(),
-            "-" | "s" | "sub" => CliCmd::new_subtract_command(),
-            "*" | "x" | "mul" => CliCmd::new_multiply_command(),
-            "/" | "d" | "div" => CliCmd::new_divide_command(),
-            "sqrt" => CliCmd::new_square_root_command(),
-            "^" | "pow" => CliCmd::new_power_command(),
-            "++" | "aa" => CliCmd::new_add_all_command(),
-            "**" | "xx" => CliCmd::new_mult_all_command(),
-            "c" | "clear" => CliCmd::new_clear_command(),
-            "p" | "print" => CliCmd::new_list_command(),
-            "h" | "help" => CliCmd::new_help_command(),
-            "q" | "quit" => CliCmd::new_quit_command(),
-            "cls" => CliCmd::new_clear_screen_command(),
-            _ => CliCmd::new_unknown_command(),
-        }
-    }
-
-    fn from_raw_command(s: String) -> Vec<CliCmd> {
-        if s.is_empty() {
-            // Its an EOF, Ctrl+D string
-            return vec![CliCmd::new_quit_command()];
+// The command as typed in RPN
+impl fmt::Display for CliOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliOperation::Push(number) => write!(f, "{}", number),
+            CliOperation::PushDecimal(literal) => write!(f, "{}", literal),
+            CliOperation::PushSymbol(name) => write!(f, "'{}", name),
+            CliOperation::PushProgram(source) => write!(f, "{}", Program::new(source)),
+            CliOperation::PushVector(v) => write!(f, "{}", Value::Vector(v.clone())),
+            CliOperation::PushMatrix(m) => write!(f, "{}", m),
+            CliOperation::PushDate(date) => write!(f, "{}", date),
+            CliOperation::PushDuration(duration) => write!(f, "{}", duration),
+            CliOperation::Word(name) | CliOperation::Registered(name) => write!(f, "{}", name),
+            CliOperation::Builtin(builtin, Argument::None) => write!(f, "{}", builtin.name()),
+            CliOperation::Builtin(builtin, argument) => {
+                write!(f, "{} {}", builtin.name(), argument)
+            }
+            CliOperation::SyntaxError(_) | CliOperation::Unknown => write!(f, "?"),
+            CliOperation::Empty => Ok(()),
         }
+    }
+}
 
-        let tokenized_command = s.split_whitespace().collect::<Vec<_>>();
+// What a built-in command read after its name
+#[derive(Debug, PartialEq, Clone)]
+pub enum Argument {
+    None,
+    // on or off
+    Switch(bool),
+    Number(f64),
+    Count(usize),
+    Word(String),
+    // A name and the command it stands for
+    Alias(String, String),
+    Script(Script),
+    // Text computed from the rest of the line, as the RPN of an expression
+    Text(String),
+}
 
-        if tokenized_command.is_empty() {
-            return vec![CliCmd::new_empty_command()];
+// Each command is given the argument its parser read, so a missing one is
+// only found when the command runs without being read
+impl Argument {
+    pub(super) fn is_on(&self) -> Result<bool, CalcError> {
+        match self {
+            Argument::Switch(on) => Ok(*on),
+            _ => Err(missing()),
         }
+    }
 
-        let mut commands: Vec<CliCmd> = vec![];
-        for raw_command in tokenized_command.iter() {
-            commands.push(CliCmd::parse_individual_raw_command(raw_command));
+    pub(super) fn number(&self) -> Result<f64, CalcError> {
+        match self {
+            Argument::Number(n) => Ok(*n),
+            _ => Err(missing()),
         }
-        return commands;
     }
 
-    fn new_push_command(number: f64) -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Push(number),
+    pub(super) fn count(&self) -> Result<usize, CalcError> {
+        match self {
+            Argument::Count(n) => Ok(*n),
+            _ => Err(missing()),
         }
     }
 
-    fn new_add_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Add,
+    pub(super) fn word(&self) -> Result<&str, CalcError> {
+        match self {
+            Argument::Word(word) => Ok(word),
+            _ => Err(missing()),
         }
     }
 
-    fn new_multiply_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Multiply,
+    pub(super) fn script(&self) -> Result<&Script, CalcError> {
+        match self {
+            Argument::Script(script) => Ok(script),
+            _ => Err(missing()),
         }
     }
 
-    fn new_divide_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Divide,
+    pub(super) fn text(&self) -> Result<&str, CalcError> {
+        match self {
+            Argument::Text(text) => Ok(text),
+            _ => Err(missing()),
         }
     }
+}
+
+fn missing() -> CalcError {
+    CalcError::Syntax("Missing argument".to_string())
+}
 
-    fn new_subtract_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Subtract,
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Argument::None => Ok(()),
+            Argument::Switch(true) => write!(f, "on"),
+            Argument::Switch(false) => write!(f, "off"),
+            Argument::Number(n) => write!(f, "{}", n),
+            Argument::Count(n) => write!(f, "{}", n),
+            Argument::Word(word) | Argument::Text(word) => write!(f, "{}", word),
+            Argument::Alias(name, command) => write!(f, "{} {}", name, command),
+            Argument::Script(script) => write!(f, "{}", script.source()),
         }
     }
+}
 
-    fn new_square_root_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::SquareRoot,
+// Where a command was read in its line
+#[derive(Debug, PartialEq, Clone)]
+pub struct Span {
+    pub line: Arc<str>,
+    // Characters before the command in the line
+    pub column: usize,
+    pub text: String,
+}
+
+impl Span {
+    fn new(line: &Arc<str>, start: usize, end: usize) -> Span {
+        Span {
+            line: line.clone(),
+            column: line[..start].chars().count(),
+            text: line[start..end].to_string(),
         }
     }
 
-    fn new_power_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Power,
-        }
+    // The line, then carets under the command, as in
+    //
+    //   1 2 foo +
+    //       ^^^
+    pub fn underline(&self) -> String {
+        // Tabs are kept for the carets to line up
+        let indent: String = self
+            .line
+            .chars()
+            .take(self.column)
+            .map(|c| if c == '\t' { c } else { ' ' })
+            .collect();
+        let carets = "^".repeat(self.text.chars().count().max(1));
+        format!("{}\n{}{}", self.line.trim_end(), indent, carets)
     }
+}
 
-    fn new_add_all_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::AddAll,
+pub struct CliCmd {
+    pub oper: CliOperation,
+    // None for the commands not typed as such, as those of infix expressions
+    pub span: Option<Span>,
+}
+
+impl CliCmd {
+    fn new(oper: CliOperation) -> CliCmd {
+        CliCmd { oper, span: None }
+    }
+
+    pub(super) fn parse_individual_raw_command(s: &str, aliases: &Aliases) -> CliCmd {
+        if let Ok(number) = f64::from_str(s) {
+            if !fits_f64(s, number) {
+                return CliCmd::new_push_decimal_command(s);
+            }
+            return CliCmd::new_push_command(number);
+        }
+        if let Some(date) = DateTime::parse(s) {
+            return CliCmd::new_push_date_command(date);
+        }
+        if let Some(duration) = Duration::parse(s) {
+            return CliCmd::new_push_duration_command(duration);
+        }
+        if let Some(name) = s.strip_prefix('\'').filter(|name| is_symbol_name(name)) {
+            return CliCmd::new_push_symbol_command(name);
+        }
+        let name = s.to_lowercase();
+        if let Some(builtin) = aliases.builtin(&name) {
+            let argument = match builtin.syntax {
+                Syntax::Plain(_) => Ok(Argument::None),
+                Syntax::Argument(parse, _) => parse(builtin.name(), None),
+                // Only read by the tokenizer, with the rest of the line
+                Syntax::Line(..) | Syntax::Commands(_) => {
+                    Err(format!("Expected {} after '{}'", builtin.argument, name))
+                }
+            };
+            return CliCmd::new_builtin_command(builtin, argument);
+        }
+        match aliases.registered(&name) {
+            Some(operation) => CliCmd::new_registered_command(operation.name()),
+            // Possibly a user word, only known when evaluated
+            None if is_symbol_name(s) => CliCmd::new_word_command(s),
+            None => CliCmd::new_unknown_command(),
         }
     }
 
-    fn new_mult_all_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::MultAll,
+    fn from_raw_command(s: String, notation: Notation, aliases: &Aliases) -> Vec<CliCmd> {
+        if s.is_empty() {
+            // Its an EOF, Ctrl+D string
+            return vec![CliCmd::new_quit_command()];
+        }
+
+        let commands = CliCmd::parse_line(&s, notation, aliases);
+
+        if commands.is_empty() {
+            return vec![CliCmd::new_empty_command()];
         }
+
+        commands
     }
 
-    fn new_clear_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Clear,
+    // In infix notation, lines that are not valid expressions but start with a
+    // command are still read as RPN, so 'q', 'p', 'save'... keep working.
+    pub(super) fn parse_line(s: &str, notation: Notation, aliases: &Aliases) -> Vec<CliCmd> {
+        if notation == Notation::Rpn || s.trim().is_empty() {
+            return CliCmd::tokenize_with(s, aliases);
         }
+
+        let error = match infix::parse(s, aliases) {
+            Ok(operations) => return CliCmd::from_operations(operations),
+            Err(e) => e,
+        };
+        let commands = CliCmd::tokenize_with(s, aliases);
+        match commands[0].oper {
+            CliOperation::Push(_)
+            | CliOperation::PushDecimal(_)
+            | CliOperation::Word(_)
+            | CliOperation::Unknown => {
+                vec![CliCmd::new_syntax_error_command(error)]
+            }
+            _ => commands,
+        }
+    }
+
+    fn from_operations(operations: Vec<CliOperation>) -> Vec<CliCmd> {
+        operations.into_iter().map(CliCmd::new).collect()
     }
 
-    fn new_list_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::List,
+    // Reads the commands by the names they have when no alias was changed
+    pub(super) fn tokenize(s: &str) -> Vec<CliCmd> {
+        CliCmd::tokenize_with(s, &DEFAULT_ALIASES)
+    }
+
+    pub(super) fn tokenize_with(s: &str, aliases: &Aliases) -> Vec<CliCmd> {
+        let line: Arc<str> = Arc::from(s);
+        let words = words(s);
+        let mut commands: Vec<CliCmd> = vec![];
+        let mut i = 0;
+        // Offset of the last command read, and its index in commands
+        let mut last = (0, 0);
+        while i < words.len() {
+            let (offset, token) = words[i];
+            last = (offset, commands.len());
+            // Read by the commands taking the rest of the line, as 'infix 1 + 2'
+            let rest = &s[offset + token.len()..];
+            let name = token.to_lowercase();
+            let command = match (name.as_str(), aliases.builtin(&name)) {
+                // Programs extend to the matching '}'
+                ("{", _) => match closing(&words, i, "{", "}") {
+                    Some(end) => {
+                        let source = &s[offset + 1..words[end].0];
+                        i = end;
+                        CliCmd::new_push_program_command(source)
+                    }
+                    None => {
+                        commands.push(CliCmd::new_syntax_error_command("Missing '}'".to_string()));
+                        break;
+                    }
+                },
+                ("}", _) => CliCmd::new_syntax_error_command("Unexpected '}'".to_string()),
+                // Vectors and matrices extend to the matching ']'
+                ("[", _) => match closing(&words, i, "[", "]") {
+                    Some(end) => {
+                        let elements = &words[i + 1..end];
+                        i = end;
+                        match elements.first() {
+                            Some((_, "[")) => CliCmd::new_push_matrix_command(elements),
+                            _ => CliCmd::new_push_vector_command(elements),
+                        }
+                    }
+                    None => {
+                        commands.push(CliCmd::new_syntax_error_command("Missing ']'".to_string()));
+                        break;
+                    }
+                },
+                ("]", _) => CliCmd::new_syntax_error_command("Unexpected ']'".to_string()),
+                (_, Some(builtin)) => match builtin.syntax {
+                    Syntax::Plain(_) => CliCmd::new_builtin_command(builtin, Ok(Argument::None)),
+                    // Commands taking an argument consume the following word
+                    Syntax::Argument(parse, _) => {
+                        i += 1;
+                        let word = words.get(i).map(|(_, word)| *word);
+                        CliCmd::new_builtin_command(builtin, parse(builtin.name(), word))
+                    }
+                    // Commands taking the rest of the line end it
+                    Syntax::Line(parse, _) => {
+                        let argument = parse(builtin.name(), rest, aliases);
+                        commands.push(CliCmd::new_builtin_command(builtin, argument));
+                        break;
+                    }
+                    Syntax::Commands(parse) => {
+                        match parse(rest, aliases) {
+                            Ok(operations) => commands.extend(CliCmd::from_operations(operations)),
+                            Err(e) => commands.push(CliCmd::new_syntax_error_command(e)),
+                        }
+                        break;
+                    }
+                },
+                _ => CliCmd::parse_individual_raw_command(token, aliases),
+            };
+            // Up to its last word, which may be missing after a command
+            let (end, word) = words[i.min(words.len() - 1)];
+            commands.push(CliCmd {
+                span: Some(Span::new(&line, offset, end + word.len())),
+                ..command
+            });
+            i += 1;
+        }
+        // The commands reading the rest of the line span up to its end
+        let (offset, first) = last;
+        for cmd in commands[first..]
+            .iter_mut()
+            .filter(|cmd| cmd.span.is_none())
+        {
+            cmd.span = Some(Span::new(&line, offset, s.trim_end().len()));
         }
+        commands
     }
 
-    fn new_help_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Help,
+    fn new_push_command(number: f64) -> CliCmd {
+        CliCmd::new(CliOperation::Push(number))
+    }
+
+    fn new_push_decimal_command(literal: &str) -> CliCmd {
+        CliCmd::new(CliOperation::PushDecimal(literal.to_string()))
+    }
+
+    fn new_push_symbol_command(name: &str) -> CliCmd {
+        CliCmd::new(CliOperation::PushSymbol(name.to_string()))
+    }
+
+    fn new_push_program_command(source: &str) -> CliCmd {
+        CliCmd::new(CliOperation::PushProgram(source.trim().to_string()))
+    }
+
+    fn new_push_vector_command(elements: &[(usize, &str)]) -> CliCmd {
+        match numbers(elements) {
+            Ok(vector) => CliCmd::new(CliOperation::PushVector(vector)),
+            Err(e) => CliCmd::new_syntax_error_command(e),
         }
     }
 
-    fn new_quit_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Quit,
+    // The rows of [[1 2][3 4]], each one a vector
+    fn new_push_matrix_command(elements: &[(usize, &str)]) -> CliCmd {
+        let mut rows = vec![];
+        let mut j = 0;
+        while j < elements.len() {
+            let row = match elements[j].1 {
+                "[" => closing(elements, j, "[", "]").map(|end| (&elements[j + 1..end], end)),
+                _ => None,
+            };
+            let Some((row, end)) = row else {
+                return CliCmd::new_syntax_error_command(format!(
+                    "Invalid matrix element '{}'",
+                    elements[j].1
+                ));
+            };
+            match numbers(row) {
+                Ok(row) => rows.push(row),
+                Err(e) => return CliCmd::new_syntax_error_command(e),
+            }
+            j = end + 1;
+        }
+        match Matrix::from_rows(&rows) {
+            Some(matrix) => CliCmd::new(CliOperation::PushMatrix(matrix)),
+            None => CliCmd::new_syntax_error_command(
+                "Matrix rows must have the same number of elements".to_string(),
+            ),
         }
     }
 
-    fn new_clear_screen_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::ClearScreen,
+    fn new_push_date_command(date: DateTime) -> CliCmd {
+        CliCmd::new(CliOperation::PushDate(date))
+    }
+
+    fn new_push_duration_command(duration: Duration) -> CliCmd {
+        CliCmd::new(CliOperation::PushDuration(duration))
+    }
+
+    fn new_word_command(name: &str) -> CliCmd {
+        CliCmd::new(CliOperation::Word(name.to_string()))
+    }
+
+    fn new_registered_command(name: &str) -> CliCmd {
+        CliCmd::new(CliOperation::Registered(name.to_string()))
+    }
+
+    fn new_builtin_command(
+        builtin: &'static Builtin,
+        argument: Result<Argument, String>,
+    ) -> CliCmd {
+        match argument {
+            Ok(argument) => CliCmd::new(CliOperation::Builtin(builtin, argument)),
+            Err(e) => CliCmd::new_syntax_error_command(e),
         }
     }
 
+    fn new_quit_command() -> CliCmd {
+        CliCmd::new(CliOperation::builtin("quit", Argument::None))
+    }
+
+    fn new_syntax_error_command(message: String) -> CliCmd {
+        CliCmd::new(CliOperation::SyntaxError(message))
+    }
+
     fn new_unknown_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Unknown,
-        }
+        CliCmd::new(CliOperation::Unknown)
     }
 
     fn new_empty_command() -> CliCmd {
-        CliCmd {
-            oper: CliOperation::Empty,
+        CliCmd::new(CliOperation::Empty)
+    }
+}
+
+// Whitespace separated words with their byte offset in `s`, braces and
+// brackets being words on their own
+fn words(s: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        let brace = matches!(c, '{' | '}' | '[' | ']');
+        if let (true, Some(st)) = (c.is_whitespace() || brace, start) {
+            words.push((st, &s[st..i]));
+            start = None;
+        }
+        if brace {
+            words.push((i, &s[i..i + 1]));
+        } else if !c.is_whitespace() && start.is_none() {
+            start = Some(i);
         }
     }
+    if let Some(st) = start {
+        words.push((st, &s[st..]));
+    }
+    words
+}
+
+// The elements of a vector
+fn numbers(elements: &[(usize, &str)]) -> Result<Vec<f64>, String> {
+    elements
+        .iter()
+        .map(|(_, element)| {
+            element
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid vector element '{}'", element))
+        })
+        .collect()
+}
+
+// Whether the f64 read from a number literal has all its digits, within the
+// range of normal f64 numbers
+fn fits_f64(literal: &str, number: f64) -> bool {
+    let mantissa = literal.split(['e', 'E']).next().unwrap();
+    let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    let digits = digits.trim_matches('0').len();
+    digits == 0 || (digits <= F64_DIGITS && number.is_normal())
+}
+
+// Symbols are named like identifiers: a letter or '_', then alphanumerics
+pub(super) fn is_symbol_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Index of the `right` delimiter closing the `left` one at `open`
+fn closing(words: &[(usize, &str)], open: usize, left: &str, right: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, (_, word)) in words.iter().enumerate().skip(open) {
+        if *word == left {
+            depth += 1;
+        } else if *word == right {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+// Parsers of the arguments of the built-in commands, given the name of the
+// command and the word following it
+
+pub(super) fn switch(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    match word.map(|s| s.to_lowercase()).as_deref() {
+        Some("on") => Ok(Argument::Switch(true)),
+        Some("off") => Ok(Argument::Switch(false)),
+        _ => Err(expected("'on' or 'off'", command)),
+    }
+}
+
+pub(super) fn alias_name(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    word.map(|word| Argument::Word(word.to_string()))
+        .ok_or_else(|| expected("a name", command))
+}
+
+pub(super) fn word_name(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    word.map(|word| Argument::Word(word.to_string()))
+        .ok_or_else(|| expected("a word", command))
+}
+
+// The file of save and load, the session file when missing
+pub(super) fn file(_: &str, word: Option<&str>) -> Result<Argument, String> {
+    Ok(word.map_or(Argument::None, |word| Argument::Word(word.to_string())))
+}
+
+pub(super) fn positive_number(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    match word.and_then(|n| n.parse::<f64>().ok()) {
+        Some(n) if n > 0.0 => Ok(Argument::Number(n)),
+        _ => Err(expected("a positive number", command)),
+    }
+}
+
+pub(super) fn positive_integer(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    match word.and_then(|n| n.parse::<usize>().ok()) {
+        Some(n) if n > 0 => Ok(Argument::Count(n)),
+        _ => Err(expected("a positive integer", command)),
+    }
+}
+
+// <digits> or off, for f64 numbers
+pub(super) fn digits(command: &str, word: Option<&str>) -> Result<Argument, String> {
+    let digits = word
+        .and_then(|d| d.parse::<usize>().ok())
+        .filter(|d| (1..=MAX_PRECISION).contains(d));
+    match (word, digits) {
+        (Some("off"), _) => Ok(Argument::Switch(false)),
+        (_, Some(digits)) => Ok(Argument::Count(digits)),
+        _ => Err(expected(
+            &format!("a number of digits up to {} or 'off'", MAX_PRECISION),
+            command,
+        )),
+    }
+}
+
+// Parsers of the rest of the line
+
+pub(super) fn script(_: &str, line: &str, _: &Aliases) -> Result<Argument, String> {
+    Script::parse(line).map(Argument::Script)
+}
+
+// A name and a command, or nothing to list the aliases
+pub(super) fn alias(command: &str, line: &str, _: &Aliases) -> Result<Argument, String> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => Ok(Argument::None),
+        [name, target] => Ok(Argument::Alias(name.to_string(), target.to_string())),
+        _ => Err(expected("a name and a command", command)),
+    }
+}
+
+pub(super) fn rpn(_: &str, line: &str, aliases: &Aliases) -> Result<Argument, String> {
+    infix::to_rpn(line, aliases).map(Argument::Text)
+}
+
+fn expected(what: &str, command: &str) -> String {
+    format!("Expected {} after '{}'", what, command)
 }
 
 pub struct Cli {
     keep_running: bool,
-    cursor_character: char,
+    prompt: String,
+    editor: Option<LineEditor>,
+    notation: Notation,
+    // Those of the calculator the commands are read for
+    aliases: Aliases,
 }
 
 impl Cli {
     pub fn new() -> Cli {
         Cli {
             keep_running: true,
-            cursor_character: '>',
+            prompt: DEFAULT_PROMPT.to_string(),
+            editor: None,
+            notation: Notation::Rpn,
+            aliases: Aliases::new(),
         }
     }
 
-    fn help_message(&self) {
-        println!("Commands:");
-        println!("  <number>\t\tPush a number to the stack");
-        println!("  + a add\t\tAdd the top two numbers from the stack");
-        println!("  - s sub\t\tSubtract the top two number from the stack");
-        println!("  * x mul\t\tMultiply the top two numbers from the stack");
-        println!("  / d div\t\tDivide the top two numbers from the stack");
-        println!("  sqrt\t\t\tCalculate the square root of the top of the stack");
-        println!("  ^ pow\t\t\tPower the top two numbers of the stack");
-        println!("  ++ aa\t\t\tSum all the stack");
-        println!("  ** xx\t\t\tMultiply all the stack");
-        println!("  c clear\t\tClear the stack");
-        println!("  p print\t\tDisplay the stack");
-        println!("  h help:\t\tDisplay this message");
-        println!("  cls:\t\t\tClear the cli screen");
-        println!("  q quit:\t\tQuit the program");
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_string();
     }
 
-    fn clear_screen(&self) {
-        print!("\x1B[2J\x1B[1;1H");
+    pub fn set_notation(&mut self, notation: Notation) {
+        self.notation = notation;
     }
 
-    fn display_command_output(&self, cmd: &CliCmd) {
-        match cmd.oper {
-            CliOperation::Unknown => {
-                println!("Unknown command - 'help' for a list of commands");
-            }
-            CliOperation::Quit => {
-                println!("Exiting");
-            }
-            CliOperation::ClearScreen => {
-                self.clear_screen();
-            }
-            CliOperation::Help => {
-                self.help_message();
-            }
-            _ => {}
-        }
+    // Reads the commands with `editor`, which expects the reader given to
+    // read_new_command to be a terminal.
+    pub fn enable_line_editor(&mut self, editor: LineEditor) {
+        self.editor = Some(editor);
     }
 
     pub fn keep_running(&self) -> bool {
         self.keep_running
     }
 
+    // Reads the next commands with the aliases of `calc`, completing its
+    // words and aliases too
+    pub fn sync_with(&mut self, calc: &RpnCalc) {
+        self.aliases = calc.aliases.clone();
+        if let Some(editor) = &mut self.editor {
+            editor.set_completions(calc.completions());
+        }
+    }
+
     pub fn read_new_command<R>(&mut self, reader: R) -> Vec<CliCmd>
     where
         R: io::BufRead,
     {
         self.display();
-        let cmds = CliCmd::from_raw_command(self.get_raw_cmd_from_user(reader));
-        if cmds[0].oper == CliOperation::Quit {
+        let line = self.get_raw_cmd_from_user(reader);
+        let cmds = CliCmd::from_raw_command(line, self.notation, &self.aliases);
+        if cmds.iter().any(|cmd| cmd.oper.is_command("quit")) {
+            println!("Exiting");
             self.keep_running = false;
         }
-
-        self.display_command_output(&cmds[0]);
-
         cmds
     }
 
     fn display(&self) {
-        print!("{} ", self.cursor_character);
+        if self.editor.is_some() {
+            // The line editor draws its own prompt
+            return;
+        }
+        print!("{} ", self.prompt);
         std::io::stdout().flush().unwrap();
     }
 
-    fn get_raw_cmd_from_user<R>(&self, mut reader: R) -> String
+    fn get_raw_cmd_from_user<R>(&mut self, mut reader: R) -> String
     where
         R: io::BufRead,
     {
+        if let Some(editor) = &mut self.editor {
+            return editor
+                .read_line(&self.prompt, reader)
+                .expect("Error reading command");
+        }
+
         let mut raw_cmd = String::new();
         reader
             .read_line(&mut raw_cmd)
@@ -0,0 +1,243 @@
+// Configuration file, read at startup from $XDG_CONFIG_HOME/rpn-calc/config
+// or the file given with --config:
+//
+//   prompt <text>
+//   display values|exprs
+//   angle rad|deg
+//   number f64|<digits>
+//   history <size>
+//   atomic on|off
+//   stack <values>
+//   alias <name> <command>
+//   unalias <name>
+//   startup <commands>
+//
+// Every setting is optional. `display exprs` shows the expression that
+// produced each stack level, as `exprs on` does, and `number <digits>`
+// computes with numbers of that many digits, as `prec` does. The modes and the
+// values of the `stack` lines are those the calculator starts with when no
+// session is restored. The `startup` lines run their commands in order, as in
+// `startup { dup * } 'sq def` or `startup defscript hyp(a, b) = sqrt(a^2 + b^2)`,
+// `alias` makes a name run a command, as in `alias m *`, and `unalias` removes
+// an alias, built-in ones included, as in `unalias x`. They apply in order.
+// `atomic on` undoes a line at its first error, as the `atomic` command does.
+// Blank lines and lines starting with '#' are ignored.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::calculator;
+use super::cli::{CliCmd, CliOperation, MAX_PRECISION};
+use super::error::CalcError;
+use super::expr::AngleMode;
+use super::value::Value;
+use super::RpnCalc;
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Io(String),
+    // The number of the line, from 1, and what is wrong with it
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::InvalidLine(n, e) => write!(f, "line {}: {}", n, e),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct Config {
+    prompt: Option<String>,
+    exprs: Option<bool>,
+    angle: Option<AngleMode>,
+    // Some(None) for f64 numbers
+    precision: Option<Option<usize>>,
+    history_size: Option<usize>,
+    atomic: Option<bool>,
+    // The lines with their number, read once the aliases are set
+    stack: Vec<(usize, String)>,
+    startup: Vec<(usize, String)>,
+    // The aliases with their command, None for those removed
+    aliases: Vec<(usize, String, Option<String>)>,
+}
+
+impl Config {
+    pub fn parse(content: &str) -> Result<Config, ConfigError> {
+        let lines = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let mut config = Config::default();
+        for (n, line) in lines {
+            let invalid = |e: String| ConfigError::InvalidLine(n, e);
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(invalid(format!("Expected a value after '{}'", key)));
+            }
+            match key {
+                "prompt" => config.prompt = Some(value.to_string()),
+                "display" => {
+                    config.exprs = Some(match value {
+                        "values" => false,
+                        "exprs" => true,
+                        _ => return Err(invalid(expected("'values' or 'exprs'", key))),
+                    })
+                }
+                "angle" => {
+                    config.angle = Some(match value {
+                        "rad" => AngleMode::Radians,
+                        "deg" => AngleMode::Degrees,
+                        _ => return Err(invalid(expected("'rad' or 'deg'", key))),
+                    })
+                }
+                "number" => {
+                    let digits = value
+                        .parse()
+                        .ok()
+                        .filter(|d| (1..=MAX_PRECISION).contains(d));
+                    config.precision = Some(match (value, digits) {
+                        ("f64", _) => None,
+                        (_, Some(digits)) => Some(digits),
+                        _ => {
+                            let numbers =
+                                format!("'f64' or a number of digits up to {}", MAX_PRECISION);
+                            return Err(invalid(expected(&numbers, key)));
+                        }
+                    })
+                }
+                "history" => match value.parse() {
+                    Ok(size) => config.history_size = Some(size),
+                    Err(_) => return Err(invalid(expected("a number of lines", key))),
+                },
+                "atomic" => {
+                    config.atomic = Some(match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(invalid(expected("'on' or 'off'", key))),
+                    })
+                }
+                "stack" => config.stack.push((n, value.to_string())),
+                "startup" => config.startup.push((n, value.to_string())),
+                "alias" => match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [name, command] => {
+                        let command = Some(command.to_string());
+                        config.aliases.push((n, name.to_string(), command))
+                    }
+                    _ => return Err(invalid(expected("a name and a command", key))),
+                },
+                "unalias" => match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [name] => config.aliases.push((n, name.to_string(), None)),
+                    _ => return Err(invalid(expected("a name", key))),
+                },
+                _ => return Err(invalid(format!("Unknown setting '{}'", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(format!("{}: {}", path.display(), e)))?;
+        Config::parse(&content)
+    }
+
+    pub fn prompt(&self) -> Option<&str> {
+        self.prompt.as_deref()
+    }
+
+    pub fn history_size(&self) -> Option<usize> {
+        self.history_size
+    }
+
+    // Sets the aliases, then the modes and pushes the values of the stack
+    // unless they come from a restored session, and runs the startup lines.
+    // It stops at the first line failing.
+    pub fn apply(&self, calc: &mut RpnCalc, restored: bool) -> Result<(), ConfigError> {
+        for (n, name, command) in self.aliases.iter() {
+            match command {
+                Some(command) => calculator::alias(calc, name, command),
+                None => calc.aliases.remove(name),
+            }
+            .map_err(|e| failed(*n, e))?;
+        }
+
+        if let Some(on) = self.atomic {
+            calc.atomic = on;
+        }
+        if !restored {
+            if let Some(on) = self.exprs {
+                calc.exprs = on.then(|| calc.stack.iter().map(Value::tracked_expr).collect());
+            }
+            if let Some(angle) = self.angle {
+                calc.angle = angle;
+            }
+            if let Some(digits) = self.precision {
+                calc.precision = digits;
+            }
+            for (n, line) in self.stack.iter() {
+                for cmd in CliCmd::tokenize_with(line, &calc.aliases) {
+                    if let CliOperation::SyntaxError(e) = cmd.oper {
+                        return Err(ConfigError::InvalidLine(*n, e));
+                    }
+                    if !is_value(&cmd.oper) {
+                        return Err(ConfigError::InvalidLine(
+                            *n,
+                            format!("Expected values after 'stack', found '{}'", cmd.oper),
+                        ));
+                    }
+                    calculator::process(calc, &cmd).map_err(|e| failed(*n, e))?;
+                }
+            }
+        }
+
+        // The startup lines print nothing
+        let echo = std::mem::replace(&mut calc.echo, false);
+        let result = self.startup.iter().try_for_each(|(n, line)| {
+            CliCmd::tokenize_with(line, &calc.aliases)
+                .iter()
+                .try_for_each(|cmd| match cmd.oper {
+                    CliOperation::Unknown => Err(CalcError::UnknownCommand),
+                    _ => calculator::process(calc, cmd),
+                })
+                .map_err(|e| failed(*n, e))
+        });
+        calc.echo = echo;
+        result
+    }
+}
+
+fn expected(what: &str, key: &str) -> String {
+    format!("Expected {} after '{}'", what, key)
+}
+
+fn failed(n: usize, e: CalcError) -> ConfigError {
+    ConfigError::InvalidLine(n, e.to_string())
+}
+
+// Whether the command only pushes a value
+fn is_value(oper: &CliOperation) -> bool {
+    matches!(
+        oper,
+        CliOperation::Push(_)
+            | CliOperation::PushDecimal(_)
+            | CliOperation::PushSymbol(_)
+            | CliOperation::PushProgram(_)
+            | CliOperation::PushVector(_)
+            | CliOperation::PushMatrix(_)
+            | CliOperation::PushDate(_)
+            | CliOperation::PushDuration(_)
+    )
+}
+
+// $XDG_CONFIG_HOME/rpn-calc/config, falling back to ~/.config
+pub fn default_config_file() -> Option<PathBuf> {
+    Some(super::config_dir()?.join("config"))
+}
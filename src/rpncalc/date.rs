@@ -0,0 +1,303 @@
+// Dates and durations, counted in seconds from the Unix epoch in UTC. Dates
+// are written 2026-10-18, or 2026-10-18T12:30:00 with a time of the day, and
+// range over the years 0 to 9999. Durations are written with the units w, d,
+// h, m and s, in this order, as in 3d or 12h30m.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+
+// 0000-01-01 and 9999-12-31T23:59:59
+const MIN_SECONDS: i64 = -719_528 * DAY;
+const MAX_SECONDS: i64 = 2_932_897 * DAY - 1;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DateTime {
+    seconds: i64,
+    // Whether the time of the day is written, a date alone being at midnight
+    time: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Duration {
+    seconds: i64,
+}
+
+impl DateTime {
+    fn new(seconds: i64, time: bool) -> Option<DateTime> {
+        (MIN_SECONDS..=MAX_SECONDS)
+            .contains(&seconds)
+            .then_some(DateTime { seconds, time })
+    }
+
+    pub fn parse(s: &str) -> Option<DateTime> {
+        let (date, time) = match s.split_once('T') {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None),
+        };
+
+        let fields = numbers(date, '-', &[4, 2, 2])?;
+        let (year, month, day) = (fields[0], fields[1], fields[2]);
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        let mut seconds = days_from_civil(year, month, day) * DAY;
+
+        if let Some(time) = time {
+            let fields = numbers(time, ':', &[2, 2, 2])
+                .or_else(|| numbers(time, ':', &[2, 2]).map(|f| [f[0], f[1], 0].to_vec()))?;
+            if fields[0] > 23 || fields[1] > 59 || fields[2] > 59 {
+                return None;
+            }
+            seconds += fields[0] * HOUR + fields[1] * MINUTE + fields[2];
+        }
+        DateTime::new(seconds, time.is_some())
+    }
+
+    pub fn now() -> DateTime {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        DateTime {
+            seconds: elapsed,
+            time: true,
+        }
+    }
+
+    pub fn today() -> DateTime {
+        DateTime {
+            seconds: DateTime::now().days() * DAY,
+            time: false,
+        }
+    }
+
+    // Seconds since 1970-01-01T00:00:00, fractions of a second being dropped
+    pub fn from_unix(seconds: f64) -> Option<DateTime> {
+        if !seconds.is_finite() {
+            return None;
+        }
+        let seconds = seconds.floor();
+        if seconds < MIN_SECONDS as f64 || seconds > MAX_SECONDS as f64 {
+            return None;
+        }
+        DateTime::new(seconds as i64, true)
+    }
+
+    pub fn unix(&self) -> i64 {
+        self.seconds
+    }
+
+    // Days since 1970-01-01
+    fn days(&self) -> i64 {
+        self.seconds.div_euclid(DAY)
+    }
+
+    // 1 for Monday to 7 for Sunday, as in ISO 8601
+    pub fn day_of_week(&self) -> i64 {
+        weekday(self.days()) + 1
+    }
+
+    // The time of the day is kept, and written once the date has one
+    pub fn add(&self, duration: Duration) -> Option<DateTime> {
+        let seconds = self.seconds.checked_add(duration.seconds)?;
+        DateTime::new(seconds, self.time || duration.seconds % DAY != 0)
+    }
+
+    pub fn since(&self, other: &DateTime) -> Duration {
+        Duration {
+            seconds: self.seconds - other.seconds,
+        }
+    }
+
+    // The date `n` weekdays later, or earlier when `n` is negative
+    pub fn add_business_days(&self, n: i64) -> Option<DateTime> {
+        let step = n.signum();
+        // Whole weeks have 5 weekdays, the last steps skipping the weekends
+        let (mut weeks, mut rest) = (n / 5, n % 5);
+        if n != 0 && rest == 0 {
+            weeks -= step;
+            rest = 5 * step;
+        }
+        let mut days = weeks.checked_mul(7)?;
+        while rest != 0 {
+            days += step;
+            if weekday(self.days() + days) < 5 {
+                rest -= step;
+            }
+        }
+        self.add(Duration {
+            seconds: days.checked_mul(DAY)?,
+        })
+    }
+
+    // Weekdays from this date included to `other` excluded, negative when
+    // `other` is before
+    pub fn business_days_until(&self, other: &DateTime) -> i64 {
+        let (start, end) = (self.days(), other.days());
+        if end < start {
+            return -other.business_days_until(self);
+        }
+        let days = end - start;
+        let rest = (0..days % 7).filter(|i| weekday(start + i) < 5).count() as i64;
+        days / 7 * 5 + rest
+    }
+}
+
+impl Duration {
+    pub fn parse(s: &str) -> Option<Duration> {
+        let (sign, mut rest) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s),
+        };
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut units = [
+            ('w', WEEK),
+            ('d', DAY),
+            ('h', HOUR),
+            ('m', MINUTE),
+            ('s', 1),
+        ]
+        .iter();
+        let mut seconds: i64 = 0;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            let count: i64 = rest[..digits].parse().ok()?;
+            let unit = rest[digits..].chars().next()?;
+            // Each unit at most once, from the largest to the smallest
+            let (_, length) = units.find(|(u, _)| *u == unit)?;
+            seconds = seconds.checked_add(count.checked_mul(*length)?)?;
+            rest = &rest[digits + 1..];
+        }
+        Some(Duration {
+            seconds: sign * seconds,
+        })
+    }
+
+    pub fn add(&self, other: Duration) -> Option<Duration> {
+        Some(Duration {
+            seconds: self.seconds.checked_add(other.seconds)?,
+        })
+    }
+
+    pub fn negate(&self) -> Duration {
+        Duration {
+            seconds: -self.seconds,
+        }
+    }
+
+    // Rounded to the second
+    pub fn scale(&self, factor: f64) -> Option<Duration> {
+        let seconds = (self.seconds as f64 * factor).round();
+        (seconds.is_finite() && seconds.abs() < i64::MAX as f64).then_some(Duration {
+            seconds: seconds as i64,
+        })
+    }
+
+    pub fn ratio(&self, other: &Duration) -> f64 {
+        self.seconds as f64 / other.seconds as f64
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.seconds == 0
+    }
+}
+
+// The fields of `s` separated by `separator`, with the given numbers of digits
+fn numbers(s: &str, separator: char, digits: &[usize]) -> Option<Vec<i64>> {
+    let fields: Vec<&str> = s.split(separator).collect();
+    if fields.len() != digits.len() {
+        return None;
+    }
+    fields
+        .iter()
+        .zip(digits)
+        .map(|(field, &n)| {
+            let valid = field.len() == n && field.bytes().all(|b| b.is_ascii_digit());
+            valid.then(|| field.parse().ok()).flatten()
+        })
+        .collect()
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// 0 for Monday to 6 for Sunday, 1970-01-01 being a Thursday
+fn weekday(days: i64) -> i64 {
+    (days + 3).rem_euclid(7)
+}
+
+// Days since 1970-01-01 of a date of the proleptic Gregorian calendar, from
+// the algorithms of Howard Hinnant, counting in eras of 400 years
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.days());
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+        if self.time {
+            let time = self.seconds.rem_euclid(DAY);
+            let (hours, minutes) = (time / HOUR, time % HOUR / MINUTE);
+            write!(f, "T{:02}:{:02}:{:02}", hours, minutes, time % MINUTE)?;
+        }
+        Ok(())
+    }
+}
+
+// The largest units first, without the weeks, as in 90d or -1d2h30m
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.seconds == 0 {
+            return write!(f, "0s");
+        }
+        if self.seconds < 0 {
+            write!(f, "-")?;
+        }
+        let mut rest = self.seconds.unsigned_abs();
+        for (unit, length) in [('d', DAY), ('h', HOUR), ('m', MINUTE), ('s', 1)] {
+            let count = rest / length as u64;
+            if count > 0 {
+                write!(f, "{}{}", count, unit)?;
+            }
+            rest %= length as u64;
+        }
+        Ok(())
+    }
+}
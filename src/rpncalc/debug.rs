@@ -0,0 +1,113 @@
+// Trace and step modes, run around each command by calculator::process.
+//
+// `trace on` prints every command run, those of the words and programs
+// included, with the stack before and after it. `step on` pauses after each
+// command of the following lines until a key is pressed, and a breakpoint set
+// with `break <word>` pauses before the word runs, then after each command:
+//
+//   s, space or Enter   run the next command
+//   c                   continue up to the next breakpoint
+//   q                   stop the line
+
+use std::collections::BTreeSet;
+
+use super::cli::{CliCmd, CliOperation};
+use super::editor::{self, Key};
+use super::error::CalcError;
+use super::value::Value;
+use super::RpnCalc;
+
+pub(super) struct Debugger {
+    pub(super) trace: bool,
+    pub(super) step: bool,
+    // Whether the commands pause, from a breakpoint or the step mode
+    pub(super) stepping: bool,
+    pub(super) breakpoints: BTreeSet<String>,
+    // Keys answering the pauses
+    pub(super) keys: Box<dyn FnMut() -> Option<Key>>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger {
+            trace: false,
+            step: false,
+            stepping: false,
+            breakpoints: BTreeSet::new(),
+            keys: Box::new(|| editor::read_single_key().ok().flatten()),
+        }
+    }
+}
+
+impl Debugger {
+    pub(super) fn is_on(&self) -> bool {
+        self.trace || self.stepping || !self.breakpoints.is_empty()
+    }
+
+    // Called before each line, which pauses from its start in the step mode
+    pub(super) fn start_line(&mut self) {
+        self.stepping = self.step;
+    }
+}
+
+// Runs the command with `run`, tracing it and pausing around it
+pub(super) fn process<F>(c: &mut RpnCalc, cmd: &CliCmd, run: F) -> Result<(), CalcError>
+where
+    F: FnOnce(&mut RpnCalc) -> Result<(), CalcError>,
+{
+    let indent = "  ".repeat(c.calls);
+    let text = match &cmd.span {
+        Some(span) => span.text.clone(),
+        None => cmd.oper.to_string(),
+    };
+
+    if let CliOperation::Word(ref name) = cmd.oper {
+        if c.debugger.breakpoints.contains(name) {
+            let line = format!("{}Break at '{}': {}", indent, name, stack(&c.stack));
+            c.print(line);
+            c.debugger.stepping = true;
+            pause(c)?;
+        }
+    }
+
+    let before = stack(&c.stack);
+    let result = run(c);
+    let after = match &result {
+        Ok(()) => stack(&c.stack),
+        Err(e) => format!("Error: {}", e),
+    };
+    if c.debugger.trace {
+        c.print(format!("{}{}  {} -> {}", indent, text, before, after));
+    }
+    if c.debugger.stepping && result.is_ok() {
+        if !c.debugger.trace {
+            c.print(format!("{}{}  {}", indent, text, after));
+        }
+        pause(c)?;
+    }
+    result
+}
+
+// Waits for a key telling how to go on
+fn pause(c: &mut RpnCalc) -> Result<(), CalcError> {
+    loop {
+        match (c.debugger.keys)() {
+            Some(Key::Char('s' | ' ') | Key::Enter) => return Ok(()),
+            Some(Key::Char('q') | Key::Interrupt) => {
+                c.debugger.stepping = false;
+                return Err(CalcError::Stopped);
+            }
+            // Running on without input
+            Some(Key::Char('c') | Key::Eof) | None => {
+                c.debugger.stepping = false;
+                return Ok(());
+            }
+            Some(_) => c.print("s: step, c: continue, q: stop"),
+        }
+    }
+}
+
+fn stack(stack: &[Value]) -> String {
+    let values: Vec<String> = stack.iter().map(Value::to_string).collect();
+    format!("[{}]", values.join(" "))
+}
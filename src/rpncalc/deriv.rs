@@ -0,0 +1,63 @@
+// Symbolic differentiation. The derivative is built with the textbook rules
+// and then simplified, so d/dx x^2 + 3*x is 2*x + 3.
+
+use super::expr::{Expr, Function};
+
+impl Expr {
+    // Derivative with respect to the symbol `x`
+    pub fn derivative(&self, x: &str) -> Expr {
+        self.differentiate(x).simplify()
+    }
+
+    fn differentiate(&self, x: &str) -> Expr {
+        let n = Expr::Number;
+        match self {
+            Expr::Number(_) => n(0.0),
+            Expr::Symbol(name) => n(if name == x { 1.0 } else { 0.0 }),
+            Expr::Neg(a) => -a.differentiate(x),
+            Expr::Add(a, b) => a.differentiate(x) + b.differentiate(x),
+            Expr::Sub(a, b) => a.differentiate(x) - b.differentiate(x),
+            Expr::Mul(a, b) => {
+                a.differentiate(x) * (**b).clone() + (**a).clone() * b.differentiate(x)
+            }
+            Expr::Div(a, b) => {
+                (a.differentiate(x) * (**b).clone() - (**a).clone() * b.differentiate(x))
+                    / Expr::pow((**b).clone(), n(2.0))
+            }
+            Expr::Pow(a, b) => {
+                let (a, b) = ((**a).clone(), (**b).clone());
+                if !b.contains(x) {
+                    // d/dx u^k = k*u^(k - 1)*u'
+                    let da = a.differentiate(x);
+                    b.clone() * Expr::pow(a, b - n(1.0)) * da
+                } else {
+                    // d/dx u^v = u^v*(v'*ln(u) + v*u'/u)
+                    let (da, db) = (a.differentiate(x), b.differentiate(x));
+                    let ln = Expr::call(Function::Ln, a.clone());
+                    Expr::pow(a.clone(), b.clone()) * (db * ln + b * da / a)
+                }
+            }
+            // Chain rule, d/dx f(u) = f'(u)*u'
+            Expr::Call(function, u) => {
+                function_derivative(*function, (**u).clone()) * u.differentiate(x)
+            }
+        }
+    }
+}
+
+// f'(u)
+fn function_derivative(function: Function, u: Expr) -> Expr {
+    let n = Expr::Number;
+    let call = |f: Function, u: Expr| Expr::call(f, u);
+    match function {
+        Function::Sqrt => n(1.0) / (n(2.0) * call(Function::Sqrt, u)),
+        Function::Exp => call(Function::Exp, u),
+        Function::Ln => n(1.0) / u,
+        Function::Sin => call(Function::Cos, u),
+        Function::Cos => -call(Function::Sin, u),
+        Function::Tan => n(1.0) / Expr::pow(call(Function::Cos, u), n(2.0)),
+        Function::Asin => n(1.0) / call(Function::Sqrt, n(1.0) - Expr::pow(u, n(2.0))),
+        Function::Acos => -(n(1.0) / call(Function::Sqrt, n(1.0) - Expr::pow(u, n(2.0)))),
+        Function::Atan => n(1.0) / (n(1.0) + Expr::pow(u, n(2.0))),
+    }
+}
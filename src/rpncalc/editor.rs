@@ -0,0 +1,573 @@
+use std::fs;
+use std::io;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Tab,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    Eof,
+    ReverseSearch,
+    Cancel,
+    ClearScreen,
+    Ignore,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Action {
+    Continue,
+    Submit(String),
+    Eof,
+    ClearScreen,
+    ShowCompletions(Vec<String>),
+}
+
+struct Search {
+    query: String,
+    // Index in the history of the current match
+    found: Option<usize>,
+}
+
+pub struct History {
+    entries: Vec<String>,
+    max_len: usize,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new(max_len: usize) -> History {
+        History {
+            entries: vec![],
+            max_len,
+            file: None,
+        }
+    }
+
+    // Loads the history from `file` and keeps saving new entries to it.
+    // A missing file is not an error: it is created on the first save.
+    pub fn with_file(max_len: usize, file: PathBuf) -> History {
+        let mut history = History::new(max_len);
+        if let Ok(content) = fs::read_to_string(&file) {
+            for line in content.lines() {
+                history.push(line);
+            }
+        }
+        history.file = Some(file);
+        history
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.entries.last().is_some_and(|l| l == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > self.max_len {
+            let excess = self.entries.len() - self.max_len;
+            self.entries.drain(..excess);
+        }
+    }
+
+    pub fn add(&mut self, line: &str) -> io::Result<()> {
+        self.push(line);
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        fs::write(file, content)
+    }
+}
+
+// $XDG_STATE_HOME/rpn-calc/history, falling back to ~/.local/state
+pub fn default_history_file() -> Option<PathBuf> {
+    Some(super::state_dir()?.join("history"))
+}
+
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+    history: History,
+    // Position while browsing the history with up/down, and the line that
+    // was being edited before browsing started
+    history_index: Option<usize>,
+    pending_line: Vec<char>,
+    search: Option<Search>,
+    completions: Vec<String>,
+}
+
+impl LineEditor {
+    pub fn new(history: History) -> LineEditor {
+        LineEditor {
+            buffer: vec![],
+            cursor: 0,
+            history,
+            history_index: None,
+            pending_line: vec![],
+            search: None,
+            completions: vec![],
+        }
+    }
+
+    pub fn set_completions(&mut self, words: Vec<String>) {
+        self.completions = words;
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    // Reads one line typed on the terminal, returning it with its trailing
+    // newline like `BufRead::read_line`, or an empty string on Ctrl+D.
+    pub fn read_line<R>(&mut self, prompt: &str, mut input: R) -> io::Result<String>
+    where
+        R: io::BufRead,
+    {
+        let Some(_raw_mode) = RawMode::enable() else {
+            print!("{} ", prompt);
+            io::stdout().flush()?;
+            let mut line = String::new();
+            input.read_line(&mut line)?;
+            return Ok(line);
+        };
+
+        let prompt = format!("{} ", prompt);
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => Key::Eof,
+            };
+
+            match self.feed(key) {
+                Action::Continue => {}
+                Action::Submit(line) => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    if let Err(e) = self.add_to_history(&line) {
+                        write!(stdout, "Error saving history: {}\r\n", e)?;
+                    }
+                    return Ok(line + "\n");
+                }
+                Action::Eof => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(String::new());
+                }
+                Action::ClearScreen => write!(stdout, "\x1B[2J\x1B[1;1H")?,
+                Action::ShowCompletions(words) => write!(stdout, "\r\n{}\r\n", words.join("  "))?,
+            }
+
+            write!(stdout, "{}", self.render(&prompt))?;
+            stdout.flush()?;
+        }
+    }
+
+    // Terminal escape sequence redrawing the current line
+    pub(super) fn render(&self, prompt: &str) -> String {
+        let (prefix, line, cursor) = match &self.search {
+            Some(search) => {
+                let found = search.found.map_or("", |i| &self.history.entries[i]);
+                let prefix = format!("(reverse-i-search)'{}': ", search.query);
+                (prefix, found.to_string(), found.chars().count())
+            }
+            None => (
+                prompt.to_string(),
+                self.buffer.iter().collect(),
+                self.cursor,
+            ),
+        };
+
+        let mut output = format!("\r{}{}\x1B[K", prefix, line);
+        let back = line.chars().count() - cursor;
+        if back > 0 {
+            output.push_str(&format!("\x1B[{}D", back));
+        }
+        output
+    }
+
+    pub(super) fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    // Whether nothing is typed, searching the history included
+    pub(super) fn is_empty(&self) -> bool {
+        self.buffer.is_empty() && self.search.is_none()
+    }
+
+    pub(super) fn add_to_history(&mut self, line: &str) -> io::Result<()> {
+        self.history.add(line)
+    }
+
+    pub(super) fn feed(&mut self, key: Key) -> Action {
+        if self.search.is_some() {
+            return self.feed_search(key);
+        }
+
+        match key {
+            Key::Char(ch) => {
+                self.buffer.insert(self.cursor, ch);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Submit(self.take_line()),
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Delete => {
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.buffer.len(),
+            Key::Up => self.history_previous(),
+            Key::Down => self.history_next(),
+            Key::Tab => return self.complete(),
+            Key::KillToEnd => self.buffer.truncate(self.cursor),
+            Key::KillToStart => {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Interrupt => {
+                self.take_line();
+                return Action::Submit(String::new());
+            }
+            Key::Eof => {
+                if self.buffer.is_empty() {
+                    return Action::Eof;
+                }
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::ReverseSearch => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                })
+            }
+            Key::ClearScreen => return Action::ClearScreen,
+            Key::PageUp | Key::PageDown | Key::Cancel | Key::Ignore => {}
+        }
+        Action::Continue
+    }
+
+    pub(super) fn take_line(&mut self) -> String {
+        let line = self.line();
+        self.buffer.clear();
+        self.cursor = 0;
+        self.history_index = None;
+        line
+    }
+
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.buffer = line;
+    }
+
+    fn history_previous(&mut self) {
+        let index = match self.history_index {
+            Some(0) => return,
+            Some(i) => i - 1,
+            None if self.history.entries.is_empty() => return,
+            None => {
+                self.pending_line = self.buffer.clone();
+                self.history.entries.len() - 1
+            }
+        };
+        self.history_index = Some(index);
+        self.set_line(self.history.entries[index].chars().collect());
+    }
+
+    fn history_next(&mut self) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+        if index + 1 < self.history.entries.len() {
+            self.history_index = Some(index + 1);
+            self.set_line(self.history.entries[index + 1].chars().collect());
+        } else {
+            self.history_index = None;
+            let line = std::mem::take(&mut self.pending_line);
+            self.set_line(line);
+        }
+    }
+
+    fn complete(&mut self) -> Action {
+        let start = self.buffer[..self.cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let word: String = self.buffer[start..self.cursor].iter().collect();
+        if word.is_empty() {
+            return Action::Continue;
+        }
+
+        let word = word.to_lowercase();
+        let mut candidates: Vec<String> = self
+            .completions
+            .iter()
+            .filter(|c| c.starts_with(&word))
+            .cloned()
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        let completion = match candidates.as_slice() {
+            [] => return Action::Continue,
+            [only] => format!("{} ", only),
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.clone(), |common, c| {
+                    common
+                        .chars()
+                        .zip(c.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a)
+                        .collect()
+                });
+                if common.len() == word.len() {
+                    return Action::ShowCompletions(candidates);
+                }
+                common
+            }
+        };
+
+        let completion: Vec<char> = completion.chars().skip(word.chars().count()).collect();
+        let inserted = completion.len();
+        self.buffer.splice(self.cursor..self.cursor, completion);
+        self.cursor += inserted;
+        Action::Continue
+    }
+
+    fn feed_search(&mut self, key: Key) -> Action {
+        let search = self.search.as_mut().unwrap();
+        match key {
+            Key::Char(ch) => {
+                search.query.push(ch);
+                search.found = find_backwards(&self.history.entries, &search.query, None);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.found = find_backwards(&self.history.entries, &search.query, None);
+            }
+            Key::ReverseSearch => {
+                let found = find_backwards(&self.history.entries, &search.query, search.found);
+                if found.is_some() {
+                    search.found = found;
+                }
+            }
+            Key::Cancel | Key::Interrupt => {
+                self.search = None;
+            }
+            Key::Ignore => {}
+            _ => {
+                let found = search.found;
+                self.search = None;
+                if let Some(i) = found {
+                    self.set_line(self.history.entries[i].chars().collect());
+                }
+                if key == Key::Enter {
+                    return Action::Submit(self.take_line());
+                }
+            }
+        }
+        Action::Continue
+    }
+}
+
+// Most recent history entry containing `query`, older than `before`
+fn find_backwards(entries: &[String], query: &str, before: Option<usize>) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    let end = before.unwrap_or(entries.len());
+    entries[..end].iter().rposition(|e| e.contains(query))
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+pub(super) fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7F | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x07 => Key::Cancel,
+        0x0B => Key::KillToEnd,
+        0x0C => Key::ClearScreen,
+        0x0E => Key::Down,
+        0x10 => Key::Up,
+        0x12 => Key::ReverseSearch,
+        0x15 => Key::KillToStart,
+        0x1B => read_escape_sequence(input)?,
+        0x00..=0x1F => Key::Ignore,
+        _ => read_utf8_char(byte, input)?,
+    };
+    Ok(Some(key))
+}
+
+fn read_escape_sequence<R: Read>(input: &mut R) -> io::Result<Key> {
+    let Some(kind) = read_byte(input)? else {
+        return Ok(Key::Cancel);
+    };
+    if kind != b'[' && kind != b'O' {
+        return Ok(Key::Cancel);
+    }
+
+    // Parameters are digits and ';', the sequence ends with a letter or '~'
+    let mut params = String::new();
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(Key::Ignore);
+        };
+        match byte {
+            b'0'..=b'9' | b';' => params.push(byte as char),
+            b'A' => return Ok(Key::Up),
+            b'B' => return Ok(Key::Down),
+            b'C' => return Ok(Key::Right),
+            b'D' => return Ok(Key::Left),
+            b'H' => return Ok(Key::Home),
+            b'F' => return Ok(Key::End),
+            b'~' => {
+                return Ok(match params.as_str() {
+                    "1" | "7" => Key::Home,
+                    "4" | "8" => Key::End,
+                    "3" => Key::Delete,
+                    "5" => Key::PageUp,
+                    "6" => Key::PageDown,
+                    _ => Key::Ignore,
+                })
+            }
+            _ => return Ok(Key::Ignore),
+        }
+    }
+}
+
+fn read_utf8_char<R: Read>(first: u8, input: &mut R) -> io::Result<Key> {
+    let len = match first {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .map_or(Key::Ignore, Key::Char))
+}
+
+// A key pressed without waiting for Enter, none when stdin isn't a terminal
+// so that piped input is left to the lines
+pub(super) fn read_single_key() -> io::Result<Option<Key>> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let _raw_mode = RawMode::enable();
+    read_key(&mut io::stdin().lock())
+}
+
+// Rows and columns of the terminal
+pub(super) fn terminal_size() -> Option<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let mut numbers = size.split_whitespace().map(|n| n.parse().ok());
+    Some((numbers.next()??, numbers.next()??))
+}
+
+// Puts the terminal in raw mode until dropped
+pub(super) struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub(super) fn enable() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Some(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new(History::new(DEFAULT_HISTORY_SIZE))
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+
+use super::session::SessionError;
+
+#[derive(Debug, PartialEq)]
+pub enum CalcError {
+    StackUnderflow,
+    ZeroDivision,
+    NegativeSquareRoot,
+    OutOfDomain(&'static str),
+    ZeroPowerZero,
+    UnknownCommand,
+    Syntax(String),
+    // A script failed while running
+    Script(String),
+    ExprsOff,
+    NotASymbol,
+    UnboundSymbol(String),
+    WrongType,
+    ReservedName(String),
+    TooManyNestedCalls,
+    NoSignChange,
+    NoConvergence,
+    DimensionMismatch,
+    IndexOutOfRange,
+    SingularMatrix,
+    DateOutOfRange,
+    // The line was stopped in the debugger
+    Stopped,
+    Session(SessionError),
+}
+
+impl CalcError {
+    // Stable identifier of the error for machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            CalcError::StackUnderflow => "stack_underflow",
+            CalcError::ZeroDivision => "zero_division",
+            CalcError::NegativeSquareRoot => "negative_square_root",
+            CalcError::OutOfDomain(_) => "out_of_domain",
+            CalcError::ZeroPowerZero => "zero_power_zero",
+            CalcError::UnknownCommand => "unknown_command",
+            CalcError::Syntax(_) => "syntax",
+            CalcError::Script(_) => "script",
+            CalcError::ExprsOff => "exprs_off",
+            CalcError::NotASymbol => "not_a_symbol",
+            CalcError::UnboundSymbol(_) => "unbound_symbol",
+            CalcError::WrongType => "wrong_type",
+            CalcError::ReservedName(_) => "reserved_name",
+            CalcError::TooManyNestedCalls => "too_many_nested_calls",
+            CalcError::NoSignChange => "no_sign_change",
+            CalcError::NoConvergence => "no_convergence",
+            CalcError::DimensionMismatch => "dimension_mismatch",
+            CalcError::IndexOutOfRange => "index_out_of_range",
+            CalcError::SingularMatrix => "singular_matrix",
+            CalcError::DateOutOfRange => "date_out_of_range",
+            CalcError::Stopped => "stopped",
+            CalcError::Session(_) => "session",
+        }
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::StackUnderflow => write!(f, "Not enough numbers on the stack"),
+            CalcError::ZeroDivision => write!(f, "Zero division"),
+            CalcError::NegativeSquareRoot => write!(f, "Negative number square root"),
+            CalcError::OutOfDomain(function) => {
+                write!(f, "Number out of the domain of {}", function)
+            }
+            CalcError::ZeroPowerZero => write!(f, "0 power 0 is undefined"),
+            CalcError::UnknownCommand => write!(f, "Unknown command"),
+            CalcError::Syntax(e) => write!(f, "{}", e),
+            CalcError::Script(e) => write!(f, "{}", e),
+            CalcError::ExprsOff => write!(f, "Expressions are not tracked, 'exprs on' to enable"),
+            CalcError::NotASymbol => write!(f, "Expected a symbol on the top of the stack"),
+            CalcError::UnboundSymbol(name) => write!(f, "Symbol '{}' has no value", name),
+            CalcError::WrongType => write!(f, "Wrong type of value on the stack"),
+            CalcError::ReservedName(name) => write!(f, "'{}' is the name of a command", name),
+            CalcError::TooManyNestedCalls => write!(f, "Too many nested calls"),
+            CalcError::NoSignChange => write!(f, "The function has the same sign at both bounds"),
+            CalcError::NoConvergence => write!(f, "No convergence"),
+            CalcError::DimensionMismatch => write!(f, "Dimensions don't match"),
+            CalcError::IndexOutOfRange => write!(f, "Index out of range"),
+            CalcError::SingularMatrix => write!(f, "Singular matrix"),
+            CalcError::DateOutOfRange => write!(f, "Date out of the years 0 to 9999"),
+            CalcError::Stopped => write!(f, "Stopped in the debugger"),
+            CalcError::Session(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SessionError> for CalcError {
+    fn from(e: SessionError) -> Self {
+        CalcError::Session(e)
+    }
+}
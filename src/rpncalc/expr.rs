@@ -0,0 +1,314 @@
+use std::fmt;
+use std::ops;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Function {
+    Sqrt,
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+}
+
+impl Function {
+    pub const ALL: [Function; 9] = [
+        Function::Sqrt,
+        Function::Exp,
+        Function::Ln,
+        Function::Sin,
+        Function::Cos,
+        Function::Tan,
+        Function::Asin,
+        Function::Acos,
+        Function::Atan,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Function> {
+        Function::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Sqrt => "sqrt",
+            Function::Exp => "exp",
+            Function::Ln => "ln",
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Asin => "asin",
+            Function::Acos => "acos",
+            Function::Atan => "atan",
+        }
+    }
+
+    // Angles are in radians
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Function::Sqrt => x.sqrt(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Asin => x.asin(),
+            Function::Acos => x.acos(),
+            Function::Atan => x.atan(),
+        }
+    }
+
+    // `apply` with the angles in degrees, exact at the multiples of 90
+    pub fn apply_degrees(&self, x: f64) -> f64 {
+        match self {
+            Function::Sin | Function::Cos | Function::Tan => {
+                if let Some(value) = self.right_angle(x) {
+                    return value;
+                }
+                self.apply(x.to_radians())
+            }
+            Function::Asin | Function::Acos | Function::Atan => self.apply(x).to_degrees(),
+            _ => self.apply(x),
+        }
+    }
+
+    // Sine, cosine or tangent of a multiple of 90 degrees, except the infinite
+    // tangents
+    pub fn right_angle(&self, degrees: f64) -> Option<f64> {
+        let quarter = degrees / 90.0;
+        if quarter.fract() != 0.0 || !quarter.is_finite() {
+            return None;
+        }
+        let quarter = quarter.rem_euclid(4.0) as usize;
+        match self {
+            Function::Sin => Some([0.0, 1.0, 0.0, -1.0][quarter]),
+            Function::Cos => Some([1.0, 0.0, -1.0, 0.0][quarter]),
+            Function::Tan if quarter.is_multiple_of(2) => Some(0.0),
+            _ => None,
+        }
+    }
+
+    // Whether the function has a real value at x
+    pub fn in_domain(&self, x: f64) -> bool {
+        match self {
+            Function::Sqrt => x >= 0.0,
+            Function::Ln => x > 0.0,
+            Function::Asin | Function::Acos => (-1.0..=1.0).contains(&x),
+            _ => true,
+        }
+    }
+}
+
+// Unit of the angles of the trigonometric functions on numbers, expressions
+// being in radians
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AngleMode {
+    Radians,
+    Degrees,
+}
+
+impl AngleMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AngleMode::Radians => "rad",
+            AngleMode::Degrees => "deg",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Number(f64),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+// Binding strength when rendering, higher binds tighter
+const PREC_SUM: u8 = 1;
+const PREC_PRODUCT: u8 = 2;
+const PREC_NEG: u8 = 3;
+const PREC_POW: u8 = 4;
+const PREC_ATOM: u8 = 5;
+
+impl Expr {
+    pub fn pow(a: Expr, b: Expr) -> Expr {
+        Expr::Pow(Box::new(a), Box::new(b))
+    }
+
+    pub fn call(function: Function, a: Expr) -> Expr {
+        Expr::Call(function, Box::new(a))
+    }
+
+    // Numeric value, or the name of the first symbol without one
+    pub fn eval(&self) -> Result<f64, String> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Symbol(name) => return Err(name.clone()),
+            Expr::Neg(a) => -a.eval()?,
+            Expr::Add(a, b) => a.eval()? + b.eval()?,
+            Expr::Sub(a, b) => a.eval()? - b.eval()?,
+            Expr::Mul(a, b) => a.eval()? * b.eval()?,
+            Expr::Div(a, b) => a.eval()? / b.eval()?,
+            Expr::Pow(a, b) => a.eval()?.powf(b.eval()?),
+            Expr::Call(function, a) => function.apply(a.eval()?),
+        })
+    }
+
+    // Whether the symbol `name` appears in the expression
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Symbol(symbol) => symbol == name,
+            Expr::Neg(a) | Expr::Call(_, a) => a.contains(name),
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Pow(a, b) => a.contains(name) || b.contains(name),
+        }
+    }
+
+    // Replaces every occurrence of the symbol `name` by `value`
+    pub fn substitute(&self, name: &str, value: &Expr) -> Expr {
+        let sub = |e: &Expr| Box::new(e.substitute(name, value));
+        match self {
+            Expr::Symbol(symbol) if symbol == name => value.clone(),
+            Expr::Number(_) | Expr::Symbol(_) => self.clone(),
+            Expr::Neg(a) => Expr::Neg(sub(a)),
+            Expr::Add(a, b) => Expr::Add(sub(a), sub(b)),
+            Expr::Sub(a, b) => Expr::Sub(sub(a), sub(b)),
+            Expr::Mul(a, b) => Expr::Mul(sub(a), sub(b)),
+            Expr::Div(a, b) => Expr::Div(sub(a), sub(b)),
+            Expr::Pow(a, b) => Expr::Pow(sub(a), sub(b)),
+            Expr::Call(function, a) => Expr::Call(*function, sub(a)),
+        }
+    }
+
+    // The RPN commands building the expression, with numbers written so they
+    // read back exactly
+    pub fn to_rpn(&self) -> String {
+        let binary = |a: &Expr, b: &Expr, op: &str| format!("{} {} {}", a.to_rpn(), b.to_rpn(), op);
+        match self {
+            Expr::Number(n) => format!("{:?}", n),
+            Expr::Symbol(name) => format!("'{}", name),
+            Expr::Neg(a) => format!("{} neg", a.to_rpn()),
+            Expr::Add(a, b) => binary(a, b, "+"),
+            Expr::Sub(a, b) => binary(a, b, "-"),
+            Expr::Mul(a, b) => binary(a, b, "*"),
+            Expr::Div(a, b) => binary(a, b, "/"),
+            Expr::Pow(a, b) => binary(a, b, "^"),
+            Expr::Call(function, a) => format!("{} {}", a.to_rpn(), function.name()),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Number(n) if n.is_sign_negative() => PREC_NEG,
+            Expr::Number(_) | Expr::Symbol(_) | Expr::Call(..) => PREC_ATOM,
+            Expr::Neg(_) => PREC_NEG,
+            Expr::Add(..) | Expr::Sub(..) => PREC_SUM,
+            Expr::Mul(..) | Expr::Div(..) => PREC_PRODUCT,
+            Expr::Pow(..) => PREC_POW,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    // Operators are left associative except '^', so the right operand needs
+    // parentheses at the same precedence unless the operation is associative:
+    // a - (b - c) and a / (b * c), but a + b - c and a * b / c.
+    fn fmt_binary(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        a: &Expr,
+        b: &Expr,
+        op: &str,
+        associative: bool,
+    ) -> fmt::Result {
+        let prec = self.precedence();
+        a.fmt_operand(f, a.precedence() < prec)?;
+        write!(f, "{}", op)?;
+        let right_parens = b.precedence() < prec
+            || (b.precedence() == prec && !associative)
+            || (prec == PREC_SUM && b.precedence() == PREC_NEG);
+        b.fmt_operand(f, right_parens)
+    }
+}
+
+impl ops::Add for Expr {
+    type Output = Expr;
+
+    fn add(self, rhs: Expr) -> Expr {
+        Expr::Add(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl ops::Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, rhs: Expr) -> Expr {
+        Expr::Sub(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl ops::Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, rhs: Expr) -> Expr {
+        Expr::Mul(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl ops::Div for Expr {
+    type Output = Expr;
+
+    fn div(self, rhs: Expr) -> Expr {
+        Expr::Div(Box::new(self), Box::new(rhs))
+    }
+}
+
+impl ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::Neg(Box::new(self))
+    }
+}
+
+// Infix rendering with the minimal parentheses
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Neg(a) => {
+                write!(f, "-")?;
+                a.fmt_operand(f, a.precedence() <= PREC_NEG)
+            }
+            Expr::Add(a, b) => self.fmt_binary(f, a, b, " + ", true),
+            Expr::Sub(a, b) => self.fmt_binary(f, a, b, " - ", false),
+            Expr::Mul(a, b) => self.fmt_binary(f, a, b, "*", true),
+            Expr::Div(a, b) => self.fmt_binary(f, a, b, "/", false),
+            Expr::Pow(a, b) => {
+                a.fmt_operand(f, a.precedence() <= PREC_POW)?;
+                write!(f, "^")?;
+                b.fmt_operand(f, b.precedence() < PREC_NEG)
+            }
+            Expr::Call(function, a) => write!(f, "{}({})", function.name(), a),
+        }
+    }
+}
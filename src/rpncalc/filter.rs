@@ -0,0 +1,196 @@
+use std::io;
+
+use super::cli::{CliCmd, Notation};
+use super::error::CalcError;
+use super::json;
+use super::value::Value;
+use super::{error_message, Output, RpnCalc};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StackMode {
+    // Every input line starts from an empty stack
+    PerLine,
+    // The stack is kept between input lines
+    Continuous,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    // The top of the stack on stdout, errors on stderr
+    Text,
+    // One JSON object per line on stdout
+    Json,
+}
+
+pub struct Filter {
+    // Read on every line, with the aliases of the calculator
+    program: String,
+    stack_mode: StackMode,
+    format: OutputFormat,
+    notation: Notation,
+}
+
+impl Filter {
+    pub fn new(program: &str, stack_mode: StackMode) -> Filter {
+        Filter {
+            program: program.to_string(),
+            stack_mode,
+            format: OutputFormat::Text,
+            notation: Notation::Rpn,
+        }
+    }
+
+    // Notation of the input lines, the filter program is always RPN
+    pub fn set_notation(&mut self, notation: Notation) {
+        self.notation = notation;
+    }
+
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    // Evaluates every line of `input` followed by the filter program, writing
+    // the results to `out` and, in text format, the errors to `err`.
+    // Returns false if any line failed.
+    pub fn run<R, W, E>(
+        &self,
+        calc: &mut RpnCalc,
+        input: R,
+        mut out: W,
+        mut err: E,
+    ) -> io::Result<bool>
+    where
+        R: io::BufRead,
+        W: io::Write,
+        E: io::Write,
+    {
+        let echo = calc.echo;
+        calc.echo = false;
+        // Only the results are written, not the text of commands as `p`
+        let output = std::mem::replace(&mut calc.output, Output::Discard);
+
+        let mut success = true;
+        for (index, line) in input.lines().enumerate() {
+            let line = line?;
+            let cmds = CliCmd::parse_line(&line, self.notation, &calc.aliases);
+            if cmds.is_empty() {
+                continue;
+            }
+
+            if self.stack_mode == StackMode::PerLine {
+                calc.stack.clear();
+            }
+
+            let result = self.evaluate_line(calc, cmds);
+            if matches!(result, Ok(false)) {
+                break;
+            }
+            success &= result.is_ok();
+
+            match self.format {
+                OutputFormat::Text => match result {
+                    // As in the calculator, with carets under the command failing,
+                    // which may be one of the program
+                    Err((e, cmd)) => {
+                        let place = match &cmd.span {
+                            Some(span) if *span.line != *line => ", program",
+                            _ => "",
+                        };
+                        let message = error_message(&e, &cmd);
+                        writeln!(err, "line {}{}: {}", index + 1, place, message)?;
+                        if let Some(span) = &cmd.span {
+                            writeln!(err, "{}", span.underline())?;
+                        }
+                    }
+                    Ok(_) => {
+                        if let Some(top) = calc.stack.last() {
+                            writeln!(out, "{}", top)?;
+                        }
+                    }
+                },
+                OutputFormat::Json => {
+                    let error = result.err().map(|(e, _)| e);
+                    writeln!(out, "{}", self.json_line(calc, index + 1, &line, error))?;
+                }
+            }
+        }
+
+        calc.echo = echo;
+        calc.output = output;
+        Ok(success)
+    }
+
+    fn json_line(
+        &self,
+        calc: &RpnCalc,
+        number: usize,
+        input: &str,
+        error: Option<CalcError>,
+    ) -> String {
+        let top = calc.stack.last().map_or(json::NULL.to_string(), json_value);
+        let error = match error {
+            Some(e) => json::object(&[
+                ("code", json::string(e.code())),
+                ("message", json::string(&e.to_string())),
+            ]),
+            None => json::NULL.to_string(),
+        };
+        let stack_mode = match self.stack_mode {
+            StackMode::PerLine => "per-line",
+            StackMode::Continuous => "continuous",
+        };
+        let notation = match self.notation {
+            Notation::Rpn => "rpn",
+            Notation::Infix => "infix",
+        };
+        let mut modes = vec![
+            ("stack", json::string(stack_mode)),
+            ("notation", json::string(notation)),
+        ];
+        for (name, value) in calc.modes() {
+            modes.push((name, json::string(&value)));
+        }
+
+        json::object(&[
+            ("line", number.to_string()),
+            ("input", json::string(input)),
+            ("stack", json::array(calc.stack.iter().map(json_value))),
+            ("top", top),
+            ("error", error),
+            ("modes", json::object(&modes)),
+        ])
+    }
+
+    // Runs the line followed by the program as the calculator runs its lines,
+    // giving the first error with its command. Returns Ok(false) when a quit
+    // command was found, the commands before it being run.
+    fn evaluate_line(
+        &self,
+        calc: &mut RpnCalc,
+        mut cmds: Vec<CliCmd>,
+    ) -> Result<bool, (CalcError, Box<CliCmd>)> {
+        cmds.extend(CliCmd::tokenize_with(&self.program, &calc.aliases));
+        let quit = cmds.iter().position(|cmd| cmd.oper.is_command("quit"));
+        if let Some(i) = quit {
+            cmds.truncate(i);
+        }
+        match calc.run_line(&cmds).into_iter().next() {
+            Some((i, e)) => Err((e, Box::new(cmds.swap_remove(i)))),
+            None => Ok(quit.is_none()),
+        }
+    }
+}
+
+// Numbers as JSON numbers, other values as strings of their display
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Number(n) => json::number(*n),
+        // All the digits of the precision mode, a valid JSON number
+        Value::Big(n) => n.to_string(),
+        Value::Vector(v) => json::array(v.iter().map(|n| json::number(*n))),
+        Value::Matrix(m) => json::array(
+            (0..m.rows()).map(|i| json::array(m.row(i).iter().map(|n| json::number(*n)))),
+        ),
+        value => json::string(&value.to_string()),
+    }
+}
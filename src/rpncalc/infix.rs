@@ -0,0 +1,308 @@
+// Infix expression parser producing the same operations as the RPN tokenizer.
+//
+// Precedence, from the loosest to the tightest binding:
+//   + -        left associative
+//   * /        left associative
+//   - +        unary
+//   ^          right associative, so -2^2 is -(2^2) and 2^3^2 is 2^(3^2)
+//   'x         symbols
+//   f(a, ...)  function calls, with the name of any RPN command
+//              operating on a fixed number of arguments
+
+use super::cli::{is_symbol_name, Argument, CliCmd, CliOperation};
+use super::registry::{Aliases, Exprs, Operation};
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+// Binding power of the unary operators
+const PREFIX_BP: u8 = 5;
+
+// Nesting of parentheses, operators and calls, past which the expression is
+// rejected rather than overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+fn infix_binding_power(op: char) -> Option<(u8, u8)> {
+    match op {
+        '+' | '-' => Some((1, 2)),
+        '*' | '/' => Some((3, 4)),
+        '^' => Some((8, 7)),
+        _ => None,
+    }
+}
+
+fn binary_operation(op: char) -> CliOperation {
+    let name = match op {
+        '+' => "+",
+        '-' => "-",
+        '*' => "*",
+        '/' => "/",
+        _ => "^",
+    };
+    CliOperation::builtin(name, Argument::None)
+}
+
+// Operation and number of arguments of the functions callable from infix
+fn function(name: &str, aliases: &Aliases) -> Option<(CliOperation, usize)> {
+    let operation = CliCmd::parse_individual_raw_command(name, aliases).oper;
+    let arity = match operation {
+        CliOperation::Registered(ref name) => aliases.registered(name)?.arity(),
+        CliOperation::Builtin(builtin, _) => match builtin.exprs {
+            Exprs::Unary(_) => 1,
+            Exprs::Binary(_) => 2,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((operation, arity))
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '0'..='9' | '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // Exponent, only if followed by digits: "2e3" but not "2e"
+                if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                    let mut j = i + 1;
+                    if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                        j += 1;
+                    }
+                    if j < chars.len() && chars[j].is_ascii_digit() {
+                        i = j;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                match text.parse::<f64>() {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => {
+                        return Err(format!("Invalid number '{}' at column {}", text, start + 1))
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            '\'' => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let name: String = chars[start + 1..i].iter().collect();
+                if !is_symbol_name(&name) {
+                    return Err(format!("Invalid symbol at column {}", start + 1));
+                }
+                Token::Symbol(name)
+            }
+            '+' | '-' | '*' | '/' | '^' => {
+                i += 1;
+                Token::Op(c)
+            }
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            _ => return Err(format!("Unexpected '{}' at column {}", c, start + 1)),
+        };
+        tokens.push((start + 1, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    // The names functions are called by
+    aliases: &'a Aliases,
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    depth: usize,
+    output: Vec<CliOperation>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, t)| t.clone());
+        self.position += 1;
+        token
+    }
+
+    fn unexpected(&self) -> String {
+        match self.tokens.get(self.position.saturating_sub(1)) {
+            Some((column, token)) if self.position <= self.tokens.len() => {
+                let text = match token {
+                    Token::Number(n) => n.to_string(),
+                    Token::Ident(name) => name.clone(),
+                    Token::Symbol(name) => format!("'{}", name),
+                    Token::Op(op) => op.to_string(),
+                    Token::LParen => "(".to_string(),
+                    Token::RParen => ")".to_string(),
+                    Token::Comma => ",".to_string(),
+                };
+                format!("Unexpected '{}' at column {}", text, column)
+            }
+            _ => "Unexpected end of expression".to_string(),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn expression(&mut self, min_bp: u8) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("Expression nested too deeply".to_string());
+        }
+        match self.next() {
+            Some(Token::Number(n)) => self.output.push(CliOperation::Push(n)),
+            Some(Token::Symbol(name)) => self.output.push(CliOperation::PushSymbol(name)),
+            Some(Token::Op('+')) => self.expression(PREFIX_BP)?,
+            Some(Token::Op('-')) => {
+                let start = self.output.len();
+                self.expression(PREFIX_BP)?;
+                // Fold the negation of a literal into the pushed number
+                let literal = self.output.len() == start + 1;
+                match self.output.last_mut() {
+                    Some(CliOperation::Push(n)) if literal => *n = -*n,
+                    _ => self
+                        .output
+                        .push(CliOperation::builtin("neg", Argument::None)),
+                }
+            }
+            Some(Token::LParen) => {
+                self.expression(0)?;
+                self.expect(Token::RParen)?;
+            }
+            Some(Token::Ident(name)) => self.call(&name)?,
+            _ => return Err(self.unexpected()),
+        }
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            let (l_bp, r_bp) = infix_binding_power(op).unwrap();
+            if l_bp < min_bp {
+                break;
+            }
+            self.next();
+            self.expression(r_bp)?;
+            self.output.push(binary_operation(op));
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str) -> Result<(), String> {
+        let function = function(&name.to_lowercase(), self.aliases);
+        if self.peek() != Some(&Token::LParen) {
+            return Err(match function {
+                // Named after an operator, as x is after *, so likely meant
+                // as a symbol
+                Some((CliOperation::Builtin(builtin, _), _))
+                    if !builtin.name().starts_with(char::is_alphabetic) =>
+                {
+                    format!(
+                        "Unknown identifier '{}', symbols are written '{}",
+                        name, name
+                    )
+                }
+                Some(_) => format!("Missing '(' after function '{}'", name),
+                None => format!("Unknown function '{}'", name),
+            });
+        }
+        let Some((operation, arity)) = function else {
+            return Err(format!("Unknown function '{}'", name));
+        };
+        self.next();
+        let mut args = 0;
+        if self.peek() == Some(&Token::RParen) {
+            self.next();
+        } else {
+            loop {
+                self.expression(0)?;
+                args += 1;
+                match self.next() {
+                    Some(Token::Comma) => continue,
+                    Some(Token::RParen) => break,
+                    _ => return Err(self.unexpected()),
+                }
+            }
+        }
+
+        if args != arity {
+            return Err(format!(
+                "Function '{}' takes {} argument(s), {} given",
+                name, arity, args
+            ));
+        }
+        self.output.push(operation);
+        Ok(())
+    }
+}
+
+pub fn parse(s: &str, aliases: &Aliases) -> Result<Vec<CliOperation>, String> {
+    let mut parser = Parser {
+        aliases,
+        tokens: tokenize(s)?,
+        position: 0,
+        depth: 0,
+        output: vec![],
+    };
+    if parser.tokens.is_empty() {
+        return Err("Empty expression".to_string());
+    }
+
+    parser.expression(0)?;
+    if parser.position < parser.tokens.len() {
+        parser.next();
+        return Err(parser.unexpected());
+    }
+    Ok(parser.output)
+}
+
+// The RPN token stream equivalent to the infix expression
+pub fn to_rpn(s: &str, aliases: &Aliases) -> Result<String, String> {
+    let tokens = parse(s, aliases)?
+        .iter()
+        .map(|op| op.to_string())
+        .collect::<Vec<_>>();
+    Ok(tokens.join(" "))
+}
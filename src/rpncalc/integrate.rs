@@ -0,0 +1,131 @@
+// Adaptive Gauss-Kronrod quadrature. Every interval is integrated with the
+// 15 point Kronrod rule, the difference with the embedded 7 point Gauss rule
+// being its error estimate, and the interval with the largest error is split
+// in two until the total error is within the tolerance.
+
+use super::error::CalcError;
+
+pub const DEFAULT_TOLERANCE: f64 = 1e-10;
+pub const DEFAULT_MAX_EVALUATIONS: usize = 10_000;
+
+// Kronrod nodes on [-1, 1], the odd ones being the Gauss nodes, and weights
+const KRONROD_NODES: [f64; 8] = [
+    0.9914553711208126,
+    0.9491079123427585,
+    0.8648644233597691,
+    0.7415311855993945,
+    0.5860872354676911,
+    0.4058451513773972,
+    0.20778495500789848,
+    0.0,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022935322010529224,
+    0.06309209262997856,
+    0.10479001032225019,
+    0.14065325971552592,
+    0.1690047266392679,
+    0.19035057806478542,
+    0.20443294007529889,
+    0.20948214108472782,
+];
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.1294849661688697,
+    0.27970539148927664,
+    0.3818300505051189,
+    0.4179591836734694,
+];
+const EVALUATIONS_PER_INTERVAL: usize = 15;
+
+struct Interval {
+    a: f64,
+    b: f64,
+    value: f64,
+    error: f64,
+}
+
+// The integral of f from a to b and its error estimate. The tolerance is
+// relative, and absolute for integrals smaller than 1.
+pub fn integrate<F>(
+    mut f: F,
+    a: f64,
+    b: f64,
+    tolerance: f64,
+    max_evaluations: usize,
+) -> Result<(f64, f64), CalcError>
+where
+    F: FnMut(f64) -> Result<f64, CalcError>,
+{
+    let mut intervals = vec![kronrod(&mut f, a, b)?];
+    let mut evaluations = EVALUATIONS_PER_INTERVAL;
+    loop {
+        let value: f64 = intervals.iter().map(|i| i.value).sum();
+        let error: f64 = intervals.iter().map(|i| i.error).sum();
+        if !value.is_finite() || !error.is_finite() {
+            return Err(CalcError::NoConvergence);
+        }
+        if error <= tolerance * value.abs().max(1.0) {
+            return Ok((value, error));
+        }
+        if evaluations + 2 * EVALUATIONS_PER_INTERVAL > max_evaluations {
+            return Err(CalcError::NoConvergence);
+        }
+
+        let worst = (0..intervals.len())
+            .max_by(|&i, &j| intervals[i].error.total_cmp(&intervals[j].error))
+            .unwrap();
+        let Interval { a, b, .. } = intervals.swap_remove(worst);
+        let middle = 0.5 * (a + b);
+        // No more precision to split the interval
+        if middle == a || middle == b {
+            return Err(CalcError::NoConvergence);
+        }
+        intervals.push(kronrod(&mut f, a, middle)?);
+        intervals.push(kronrod(&mut f, middle, b)?);
+        evaluations += 2 * EVALUATIONS_PER_INTERVAL;
+    }
+}
+
+fn kronrod<F>(f: &mut F, a: f64, b: f64) -> Result<Interval, CalcError>
+where
+    F: FnMut(f64) -> Result<f64, CalcError>,
+{
+    let center = 0.5 * (a + b);
+    let half = 0.5 * (b - a);
+
+    let f_center = f(center)?;
+    let mut pairs = [(0.0, 0.0); 7];
+    for (j, pair) in pairs.iter_mut().enumerate() {
+        let x = half * KRONROD_NODES[j];
+        *pair = (f(center - x)?, f(center + x)?);
+    }
+
+    let mut kronrod = f_center * KRONROD_WEIGHTS[7];
+    let mut gauss = f_center * GAUSS_WEIGHTS[3];
+    for (j, (f1, f2)) in pairs.iter().enumerate() {
+        kronrod += KRONROD_WEIGHTS[j] * (f1 + f2);
+        if j % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[j / 2] * (f1 + f2);
+        }
+    }
+
+    // |K - G| overestimates the error of K by far on smooth functions, it is
+    // scaled down as in QUADPACK by its ratio to the variation of f
+    let mean = 0.5 * kronrod;
+    let mut variation = KRONROD_WEIGHTS[7] * (f_center - mean).abs();
+    for (j, (f1, f2)) in pairs.iter().enumerate() {
+        variation += KRONROD_WEIGHTS[j] * ((f1 - mean).abs() + (f2 - mean).abs());
+    }
+    let variation = variation * half.abs();
+    let mut error = ((kronrod - gauss) * half).abs();
+    if variation != 0.0 && error != 0.0 {
+        error = variation * (200.0 * error / variation).powf(1.5).min(1.0);
+    }
+
+    Ok(Interval {
+        a,
+        b,
+        value: kronrod * half,
+        error,
+    })
+}
@@ -0,0 +1,46 @@
+// Minimal JSON writer for the machine-readable output
+
+pub fn string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// NaN and infinities have no JSON representation, they are written as the
+// strings "NaN", "inf" and "-inf".
+pub fn number(n: f64) -> String {
+    if n.is_finite() {
+        format!("{:?}", n)
+    } else {
+        string(&format!("{:?}", n))
+    }
+}
+
+pub fn array<I>(items: I) -> String
+where
+    I: IntoIterator<Item = String>,
+{
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", string(key), value))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(","))
+}
+
+pub const NULL: &str = "null";
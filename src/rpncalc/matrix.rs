@@ -0,0 +1,349 @@
+// Dense matrices of numbers, stored row by row. The decompositions use
+// partial pivoting (LU) and Householder reflections (QR), with pivots below
+// a tolerance relative to the largest element taken as zero.
+
+use std::fmt;
+
+use super::error::CalcError;
+
+#[derive(Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    // None for no rows, empty rows or rows of different lengths
+    pub fn from_rows(rows: &[Vec<f64>]) -> Option<Matrix> {
+        let cols = rows.first()?.len();
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        Some(Matrix {
+            rows: rows.len(),
+            cols,
+            data: rows.concat(),
+        })
+    }
+
+    pub fn identity(n: usize) -> Matrix {
+        let mut m = Matrix::zeros(n, n);
+        for i in 0..n {
+            m[(i, i)] = 1.0;
+        }
+        m
+    }
+
+    fn zeros(rows: usize, cols: usize) -> Matrix {
+        Matrix {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn row(&self, i: usize) -> &[f64] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    pub fn try_map<F>(&self, f: F) -> Result<Matrix, CalcError>
+    where
+        F: Fn(f64) -> Result<f64, CalcError>,
+    {
+        Ok(Matrix {
+            data: self.data.iter().map(|a| f(*a)).collect::<Result<_, _>>()?,
+            ..*self
+        })
+    }
+
+    // Elementwise, on matrices of the same dimensions
+    pub fn try_zip<F>(&self, other: &Matrix, f: F) -> Result<Matrix, CalcError>
+    where
+        F: Fn(f64, f64) -> Result<f64, CalcError>,
+    {
+        self.same_dimensions(other)?;
+        let data = self.data.iter().zip(other.data.iter());
+        Ok(Matrix {
+            data: data.map(|(a, b)| f(*a, *b)).collect::<Result<_, _>>()?,
+            ..*self
+        })
+    }
+
+    fn same_dimensions(&self, other: &Matrix) -> Result<(), CalcError> {
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            return Err(CalcError::DimensionMismatch);
+        }
+        Ok(())
+    }
+
+    fn square(&self) -> Result<usize, CalcError> {
+        if self.rows != self.cols {
+            return Err(CalcError::DimensionMismatch);
+        }
+        Ok(self.rows)
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut t = Matrix::zeros(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                t[(j, i)] = self[(i, j)];
+            }
+        }
+        t
+    }
+
+    pub fn mul(&self, other: &Matrix) -> Result<Matrix, CalcError> {
+        if self.cols != other.rows {
+            return Err(CalcError::DimensionMismatch);
+        }
+        let mut product = Matrix::zeros(self.rows, other.cols);
+        for i in 0..self.rows {
+            for j in 0..other.cols {
+                product[(i, j)] = (0..self.cols).map(|k| self[(i, k)] * other[(k, j)]).sum();
+            }
+        }
+        Ok(product)
+    }
+
+    // The vector as a single column, and back
+    pub fn column(v: &[f64]) -> Result<Matrix, CalcError> {
+        Matrix::from_rows(&v.iter().map(|a| vec![*a]).collect::<Vec<_>>())
+            .ok_or(CalcError::DimensionMismatch)
+    }
+
+    pub fn into_vector(self) -> Vec<f64> {
+        self.data
+    }
+
+    // Largest element in absolute value, the scale of the zero tolerance
+    fn max_abs(&self) -> f64 {
+        self.data.iter().fold(0.0, |m, a| m.max(a.abs()))
+    }
+
+    fn tolerance(&self) -> f64 {
+        f64::EPSILON * self.rows.max(self.cols) as f64 * self.max_abs()
+    }
+
+    // L, U and P such that P*A = L*U, L having a unit diagonal
+    pub fn lu(&self) -> Result<(Matrix, Matrix, Matrix), CalcError> {
+        let lu = Lu::new(self)?;
+        let n = lu.a.rows;
+        let (mut l, mut u, mut p) = (
+            Matrix::identity(n),
+            Matrix::zeros(n, n),
+            Matrix::zeros(n, n),
+        );
+        for i in 0..n {
+            for j in 0..n {
+                if j < i {
+                    l[(i, j)] = lu.a[(i, j)];
+                } else {
+                    u[(i, j)] = lu.a[(i, j)];
+                }
+            }
+            p[(i, lu.permutation[i])] = 1.0;
+        }
+        Ok((l, u, p))
+    }
+
+    pub fn det(&self) -> Result<f64, CalcError> {
+        let lu = Lu::new(self)?;
+        Ok((0..lu.a.rows).fold(lu.sign, |det, i| det * lu.a[(i, i)]))
+    }
+
+    pub fn inverse(&self) -> Result<Matrix, CalcError> {
+        self.solve(&Matrix::identity(self.square()?))
+    }
+
+    // X such that A*X = B, one column of X per column of B
+    pub fn solve(&self, b: &Matrix) -> Result<Matrix, CalcError> {
+        let lu = Lu::new(self)?;
+        let n = lu.a.rows;
+        if b.rows != n {
+            return Err(CalcError::DimensionMismatch);
+        }
+        if lu.singular {
+            return Err(CalcError::SingularMatrix);
+        }
+
+        let mut x = Matrix::zeros(n, b.cols);
+        for col in 0..b.cols {
+            // L*y = P*b, then U*x = y
+            let mut y: Vec<f64> = lu.permutation.iter().map(|&i| b[(i, col)]).collect();
+            for i in 0..n {
+                y[i] -= (0..i).map(|k| lu.a[(i, k)] * y[k]).sum::<f64>();
+            }
+            for i in (0..n).rev() {
+                let sum: f64 = (i + 1..n).map(|k| lu.a[(i, k)] * x[(k, col)]).sum();
+                x[(i, col)] = (y[i] - sum) / lu.a[(i, i)];
+            }
+        }
+        Ok(x)
+    }
+
+    // Number of pivots of the row echelon form
+    pub fn rank(&self) -> usize {
+        let (mut a, tolerance) = (self.clone(), self.tolerance());
+        let mut rank = 0;
+        for col in 0..a.cols {
+            if rank == a.rows {
+                break;
+            }
+            let pivot = (rank..a.rows)
+                .max_by(|&i, &j| a[(i, col)].abs().total_cmp(&a[(j, col)].abs()))
+                .unwrap();
+            if a[(pivot, col)].abs() <= tolerance {
+                continue;
+            }
+            a.swap_rows(rank, pivot);
+            for i in rank + 1..a.rows {
+                let factor = a[(i, col)] / a[(rank, col)];
+                for j in col..a.cols {
+                    a[(i, j)] -= factor * a[(rank, j)];
+                }
+            }
+            rank += 1;
+        }
+        rank
+    }
+
+    // Q orthogonal and R upper triangular such that A = Q*R
+    pub fn qr(&self) -> (Matrix, Matrix) {
+        let (m, n) = (self.rows, self.cols);
+        let (mut q, mut r) = (Matrix::identity(m), self.clone());
+        for k in 0..n.min(m.saturating_sub(1)) {
+            // Reflection by v mapping column k below the diagonal to zeros
+            let mut v: Vec<f64> = (k..m).map(|i| r[(i, k)]).collect();
+            let norm = v.iter().map(|a| a * a).sum::<f64>().sqrt();
+            if norm == 0.0 {
+                continue;
+            }
+            v[0] += norm.copysign(v[0]);
+            let v_norm2: f64 = v.iter().map(|a| a * a).sum();
+
+            for j in 0..n {
+                let dot: f64 = (k..m).map(|i| v[i - k] * r[(i, j)]).sum();
+                let factor = 2.0 * dot / v_norm2;
+                for i in k..m {
+                    r[(i, j)] -= factor * v[i - k];
+                }
+            }
+            for i in 0..m {
+                let dot: f64 = (k..m).map(|j| q[(i, j)] * v[j - k]).sum();
+                let factor = 2.0 * dot / v_norm2;
+                for j in k..m {
+                    q[(i, j)] -= factor * v[j - k];
+                }
+            }
+            for i in k + 1..m {
+                r[(i, k)] = 0.0;
+            }
+        }
+        (q, r)
+    }
+
+    fn write<F>(&self, f: &mut fmt::Formatter<'_>, element: F) -> fmt::Result
+    where
+        F: Fn(&mut fmt::Formatter<'_>, f64) -> fmt::Result,
+    {
+        write!(f, "[")?;
+        for i in 0..self.rows {
+            write!(f, "[")?;
+            for (j, a) in self.row(i).iter().enumerate() {
+                if j > 0 {
+                    write!(f, " ")?;
+                }
+                element(f, *a)?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "]")
+    }
+
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        for col in 0..self.cols {
+            self.data.swap(i * self.cols + col, j * self.cols + col);
+        }
+    }
+}
+
+// Both triangular factors in one matrix, the unit diagonal of L left out
+struct Lu {
+    a: Matrix,
+    permutation: Vec<usize>,
+    sign: f64,
+    singular: bool,
+}
+
+impl Lu {
+    fn new(matrix: &Matrix) -> Result<Lu, CalcError> {
+        let n = matrix.square()?;
+        let (mut a, tolerance) = (matrix.clone(), matrix.tolerance());
+        let mut lu = Lu {
+            a: Matrix::zeros(0, 0),
+            permutation: (0..n).collect(),
+            sign: 1.0,
+            singular: false,
+        };
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&i, &j| a[(i, k)].abs().total_cmp(&a[(j, k)].abs()))
+                .unwrap();
+            if pivot != k {
+                a.swap_rows(k, pivot);
+                lu.permutation.swap(k, pivot);
+                lu.sign = -lu.sign;
+            }
+            if a[(k, k)].abs() <= tolerance {
+                lu.singular = true;
+                continue;
+            }
+            for i in k + 1..n {
+                a[(i, k)] /= a[(k, k)];
+                for j in k + 1..n {
+                    a[(i, j)] -= a[(i, k)] * a[(k, j)];
+                }
+            }
+        }
+        lu.a = a;
+        Ok(lu)
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &f64 {
+        &self.data[i * self.cols + j]
+    }
+}
+
+impl std::ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut f64 {
+        &mut self.data[i * self.cols + j]
+    }
+}
+
+// The rows in brackets, as in [[1 2][3 4]], Debug writing the elements as
+// f64: [[1.0 2.0][3.0 4.0]]
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, |f, a| write!(f, "{}", a))
+    }
+}
+
+impl fmt::Debug for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, |f, a| write!(f, "{:?}", a))
+    }
+}
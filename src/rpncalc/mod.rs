@@ -1,26 +1,240 @@
+mod bigfloat;
 mod calculator;
-mod cli;
+pub mod cli;
+pub mod config;
+mod date;
+mod debug;
+mod deriv;
+pub mod editor;
+pub mod error;
+pub mod expr;
+pub mod filter;
+mod infix;
+mod integrate;
+mod json;
+mod matrix;
+pub mod observer;
+pub mod plugin;
+mod poly;
+pub mod registry;
+mod script;
+pub mod session;
+mod simplify;
+mod solve;
+pub mod tui;
+pub mod value;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 // Public API
 
 pub struct RpnCalc {
-    stack: Vec<f64>,
+    stack: Vec<value::Value>,
+    echo: bool,
+    // Expression that produced each stack level, when tracked
+    exprs: Option<Vec<expr::Expr>>,
+    // Programs run by user defined words
+    words: BTreeMap<String, value::Program>,
+    // Scripts run by the words defined with defscript
+    scripts: BTreeMap<String, script::Script>,
+    // Names given to the commands by the user, or removed
+    aliases: registry::Aliases,
+    // Depth of the word and program calls being run
+    calls: usize,
+    // Settings of integrate
+    tolerance: f64,
+    max_evaluations: usize,
+    // Digits of the numbers in the precision mode, None for f64 numbers
+    precision: Option<usize>,
+    angle: expr::AngleMode,
+    // Whether a line failing is undone
+    atomic: bool,
+    debugger: debug::Debugger,
+    observers: Vec<Box<dyn observer::Observer>>,
+    output: Output,
+}
+
+// Where the commands showing text, as `p` or `help`, write it
+#[derive(Debug, PartialEq)]
+enum Output {
+    Stdout,
+    // Dropped in filter mode, whose stdout only holds the results
+    Discard,
+    // Kept for the full-screen interface to show them in its history
+    Captured(Vec<String>),
 }
 
 impl RpnCalc {
     pub fn new() -> RpnCalc {
-        RpnCalc { stack: vec![] }
+        RpnCalc {
+            stack: vec![],
+            echo: true,
+            exprs: None,
+            words: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+            aliases: registry::Aliases::new(),
+            calls: 0,
+            tolerance: integrate::DEFAULT_TOLERANCE,
+            max_evaluations: integrate::DEFAULT_MAX_EVALUATIONS,
+            precision: None,
+            angle: expr::AngleMode::Radians,
+            atomic: false,
+            debugger: debug::Debugger::default(),
+            observers: vec![],
+            output: Output::Stdout,
+        }
     }
 
     pub fn cli() -> cli::Cli {
         cli::Cli::new()
     }
 
+    pub fn tui(editor: editor::LineEditor) -> tui::Tui {
+        tui::Tui::new(editor)
+    }
+
+    pub fn filter(program: &str, stack_mode: filter::StackMode) -> filter::Filter {
+        filter::Filter::new(program, stack_mode)
+    }
+
     pub fn process(&mut self, cmds: Vec<cli::CliCmd>) {
-        for cmd in cmds.iter() {
-            calculator::process(self, cmd);
+        let errors = self.run_line(&cmds);
+        for (i, e) in errors.iter() {
+            report(e, &cmds[*i]);
+        }
+        if self.atomic && !errors.is_empty() {
+            println!("The line was undone");
+        }
+    }
+
+    // Runs the commands of a line, giving the errors with the index of their
+    // command. The line stops at the first one in the atomic mode.
+    fn run_line(&mut self, cmds: &[cli::CliCmd]) -> Vec<(usize, error::CalcError)> {
+        self.debugger.start_line();
+        if self.atomic {
+            return calculator::process_line(self, cmds)
+                .err()
+                .into_iter()
+                .collect();
+        }
+        let mut errors = vec![];
+        for (i, cmd) in cmds.iter().enumerate() {
+            if let Err(e) = calculator::process(self, cmd) {
+                let stopped = e == error::CalcError::Stopped;
+                errors.push((i, e));
+                // The rest of the line is stopped too
+                if stopped {
+                    break;
+                }
+            }
+        }
+        errors
+    }
+
+    // The stack from its bottom, for the registered operations
+    pub fn stack(&self) -> &[value::Value] {
+        &self.stack
+    }
+
+    pub fn push(&mut self, value: value::Value) {
+        self.stack.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<value::Value> {
+        self.stack.pop()
+    }
+
+    // Calls the observer around each command run
+    pub fn add_observer<O: observer::Observer + 'static>(&mut self, observer: O) {
+        self.observers.push(Box::new(observer));
+    }
+
+    // Undo the lines failing, and skip the rest of them
+    pub fn set_atomic(&mut self, on: bool) {
+        self.atomic = on;
+    }
+
+    // The names of the commands and of the words defined, for completion
+    pub fn completions(&self) -> Vec<String> {
+        let mut names = self.aliases.names();
+        names.extend(self.words.keys().cloned());
+        names.extend(self.scripts.keys().cloned());
+        names
+    }
+
+    // Name and value of the calculator modes
+    pub fn modes(&self) -> Vec<(&'static str, String)> {
+        let switch = |on: bool| if on { "on" } else { "off" }.to_string();
+        let precision = self.precision.map_or("off".to_string(), |d| d.to_string());
+        vec![
+            ("exprs", switch(self.exprs.is_some())),
+            ("angle", self.angle.name().to_string()),
+            ("prec", precision),
+            ("atomic", switch(self.atomic)),
+        ]
+    }
+
+    // Writes a line of the text shown by a command
+    fn print(&mut self, line: impl std::fmt::Display) {
+        match self.output {
+            Output::Stdout => println!("{}", line),
+            Output::Discard => {}
+            Output::Captured(ref mut lines) => lines.push(line.to_string()),
         }
     }
+
+    pub fn save_session(&self, path: &Path) -> Result<(), error::CalcError> {
+        Ok(session::Session::from_calc(self).save(path)?)
+    }
+
+    // The calculator is left untouched if the session file can't be loaded
+    pub fn load_session(&mut self, path: &Path) -> Result<(), error::CalcError> {
+        session::Session::load(path)?.restore(self);
+        Ok(())
+    }
+}
+
+// $XDG_STATE_HOME/rpn-calc, falling back to ~/.local/state/rpn-calc
+pub(crate) fn state_dir() -> Option<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join("rpn-calc"))
+}
+
+// The error, with the line it was in and carets under the command failing
+fn report(e: &error::CalcError, cmd: &cli::CliCmd) {
+    println!("{}", error_message(e, cmd));
+    if let Some(span) = &cmd.span {
+        println!("{}", span.underline());
+    }
+    if *e == error::CalcError::UnknownCommand {
+        println!("'help' for a list of commands");
+    }
+}
+
+// The error with the command failing and its column
+fn error_message(e: &error::CalcError, cmd: &cli::CliCmd) -> String {
+    match &cmd.span {
+        Some(span) => format!(
+            "Error at '{}', column {}: {}",
+            span.text,
+            span.column + 1,
+            e
+        ),
+        None => format!("Error: {}", e),
+    }
+}
+
+// $XDG_CONFIG_HOME/rpn-calc, falling back to ~/.config/rpn-calc
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("rpn-calc"))
 }
 
 impl Default for RpnCalc {
@@ -39,45 +253,46 @@ mod tests {
     fn process_command(calc: &mut RpnCalc, cmd: &str) {
         let mut cli = RpnCalc::cli();
         let command = std::io::Cursor::new(cmd);
+        cli.sync_with(calc);
         calc.process(cli.read_new_command(command));
     }
 
     #[test]
     fn start_new_calculator() {
         let calc = RpnCalc::new();
-        assert_eq!(true, calc.stack.is_empty());
+        assert!(calc.stack.is_empty());
         let cli = RpnCalc::cli();
-        assert_eq!(cli.keep_running(), true);
+        assert!(cli.keep_running());
     }
 
     #[test]
     fn cli_exit_calculator() {
         let mut cli = RpnCalc::cli();
-        assert_eq!(cli.keep_running(), true);
+        assert!(cli.keep_running());
         let command = std::io::Cursor::new(b"quit");
         cli.read_new_command(command);
-        assert_eq!(cli.keep_running(), false);
+        assert!(!cli.keep_running());
 
         let mut cli = RpnCalc::cli();
-        assert_eq!(cli.keep_running(), true);
+        assert!(cli.keep_running());
         let command = std::io::Cursor::new(b"q");
         cli.read_new_command(command);
-        assert_eq!(cli.keep_running(), false);
+        assert!(!cli.keep_running());
 
         let mut cli = RpnCalc::cli();
-        assert_eq!(cli.keep_running(), true);
+        assert!(cli.keep_running());
         let command = std::io::Cursor::new(b"q q q q q q q q");
         cli.read_new_command(command);
-        assert_eq!(cli.keep_running(), false);
+        assert!(!cli.keep_running());
     }
 
     #[test]
     fn cli_exit_calculator_using_eof() {
         let mut cli = RpnCalc::cli();
-        assert_eq!(cli.keep_running(), true);
+        assert!(cli.keep_running());
         let command = std::io::Cursor::new(b"");
         cli.read_new_command(command);
-        assert_eq!(cli.keep_running(), false);
+        assert!(!cli.keep_running());
     }
 
     #[test]
@@ -124,14 +339,14 @@ mod tests {
         process_command(&mut calc, "10-1");
         process_command(&mut calc, "foo");
         process_command(&mut calc, "++1");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
     fn cli_single_line_mode_do_not_push_invalid_numbers() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "pi e log10 zero x xx 1,1 1e 1- 10-1 foo ++1");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -143,7 +358,7 @@ mod tests {
         process_command(&mut calc, "1");
         assert_eq!(calc.stack, [1.0, 1.0, 1.0, 1.0]);
         process_command(&mut calc, "c");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
 
         process_command(&mut calc, "1");
         process_command(&mut calc, "1");
@@ -151,7 +366,7 @@ mod tests {
         process_command(&mut calc, "1");
         assert_eq!(calc.stack, [1.0, 1.0, 1.0, 1.0]);
         process_command(&mut calc, "clear");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
 
         process_command(&mut calc, "1");
         process_command(&mut calc, "1");
@@ -159,7 +374,7 @@ mod tests {
         process_command(&mut calc, "1");
         assert_eq!(calc.stack, [1.0, 1.0, 1.0, 1.0]);
         process_command(&mut calc, "C");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -184,7 +399,7 @@ mod tests {
     fn cli_add_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "+");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -242,7 +457,7 @@ mod tests {
     fn cli_sub_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "-");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -300,7 +515,7 @@ mod tests {
     fn cli_mult_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "*");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -358,7 +573,7 @@ mod tests {
     fn cli_div_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "/");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -459,7 +674,7 @@ mod tests {
     fn cli_add_all_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "++");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -519,7 +734,7 @@ mod tests {
     fn cli_mult_all_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "**");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -566,7 +781,7 @@ mod tests {
     fn cli_single_line_mode_multiple_operation() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "+ + - - x + a / * ++ xx");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -612,7 +827,7 @@ mod tests {
     fn cli_sqrt_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "sqrt");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -676,7 +891,7 @@ mod tests {
     fn cli_pow_empty_stack() {
         let mut calc = RpnCalc::new();
         process_command(&mut calc, "pow");
-        assert_eq!(calc.stack, []);
+        assert!(calc.stack.is_empty());
     }
 
     #[test]
@@ -691,4 +906,2936 @@ mod tests {
         process_command(&mut calc, "pow ^");
         assert_eq!(calc.stack, [1.0]);
     }
+
+    #[track_caller]
+    fn run_filter(
+        program: &str,
+        stack_mode: filter::StackMode,
+        input: &str,
+    ) -> (bool, String, String) {
+        run_filter_with_format(program, stack_mode, filter::OutputFormat::Text, input)
+    }
+
+    #[track_caller]
+    fn run_filter_with_format(
+        program: &str,
+        stack_mode: filter::StackMode,
+        format: filter::OutputFormat,
+        input: &str,
+    ) -> (bool, String, String) {
+        let mut calc = RpnCalc::new();
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let mut filter = RpnCalc::filter(program, stack_mode);
+        filter.set_output_format(format);
+        let success = filter
+            .run(&mut calc, std::io::Cursor::new(input), &mut out, &mut err)
+            .unwrap();
+        (
+            success,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn filter_transform_each_line() {
+        let (success, out, err) =
+            run_filter("1.8 * 32 +", filter::StackMode::PerLine, "0\n100\n-40\n");
+        assert!(success);
+        assert_eq!(out, "32\n212\n-40\n");
+        assert_eq!(err, "");
+    }
+
+    #[test]
+    fn filter_without_program() {
+        let (success, out, _) = run_filter("", filter::StackMode::PerLine, "1 2 +\n3 4 *\n");
+        assert!(success);
+        assert_eq!(out, "3\n12\n");
+    }
+
+    #[test]
+    fn filter_skips_blank_lines() {
+        let (success, out, _) = run_filter("2 *", filter::StackMode::PerLine, "1\n\n   \n2\n");
+        assert!(success);
+        assert_eq!(out, "2\n4\n");
+    }
+
+    #[test]
+    fn filter_continuous_stack() {
+        let (success, out, _) = run_filter("", filter::StackMode::Continuous, "1\n2\n+\n");
+        assert!(success);
+        assert_eq!(out, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn filter_errors_report_line_number() {
+        let (success, out, err) = run_filter(
+            "/",
+            filter::StackMode::PerLine,
+            "10 2\n10 0\n10\nfoo 1\n9 3\n",
+        );
+        assert!(!success);
+        assert_eq!(out, "5\n3\n");
+        assert_eq!(
+            err,
+            "line 2, program: Error at '/', column 1: Zero division\n/\n^\n\
+             line 3, program: Error at '/', column 1: Not enough numbers on the stack\n/\n^\n\
+             line 4: Error at 'foo', column 1: Unknown command\nfoo 1\n^^^\n"
+        );
+    }
+
+    #[test]
+    fn filter_atomic_lines() {
+        let mut calc = RpnCalc::new();
+        calc.set_atomic(true);
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let filter = RpnCalc::filter("", filter::StackMode::Continuous);
+        let input = std::io::Cursor::new("1 2\n3 0 / 4\n+\n");
+        assert!(!filter.run(&mut calc, input, &mut out, &mut err).unwrap());
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n3\n");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "line 2: Error at '/', column 5: Zero division\n3 0 / 4\n    ^\n"
+        );
+    }
+
+    #[test]
+    fn filter_stops_on_quit() {
+        let (success, out, _) = run_filter("", filter::StackMode::PerLine, "1\nq\n2\n");
+        assert!(success);
+        assert_eq!(out, "1\n");
+    }
+
+    fn feed_keys(ed: &mut editor::LineEditor, keys: &str) -> editor::Action {
+        let mut input = std::io::Cursor::new(keys.as_bytes());
+        let mut action = editor::Action::Continue;
+        while let Some(key) = editor::read_key(&mut input).unwrap() {
+            action = ed.feed(key);
+        }
+        action
+    }
+
+    fn editor_with_history(lines: &[&str]) -> editor::LineEditor {
+        let mut history = editor::History::new(editor::DEFAULT_HISTORY_SIZE);
+        for line in lines {
+            history.add(line).unwrap();
+        }
+        editor::LineEditor::new(history)
+    }
+
+    #[test]
+    fn editor_decode_keys() {
+        let mut input =
+            std::io::Cursor::new(b"a\x1b[A\x1b[B\x1b[C\x1b[D\x1b[3~\x1bOH\x7f\t\r\x12\xc3\xa9");
+        let mut keys = vec![];
+        while let Some(key) = editor::read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            keys,
+            [
+                editor::Key::Char('a'),
+                editor::Key::Up,
+                editor::Key::Down,
+                editor::Key::Right,
+                editor::Key::Left,
+                editor::Key::Delete,
+                editor::Key::Home,
+                editor::Key::Backspace,
+                editor::Key::Tab,
+                editor::Key::Enter,
+                editor::Key::ReverseSearch,
+                editor::Key::Char('é'),
+            ]
+        );
+    }
+
+    #[test]
+    fn editor_cursor_movement() {
+        let mut ed = editor::LineEditor::default();
+        // Type "1 +", go home, insert "2 ", go to the end, delete the "+"
+        feed_keys(&mut ed, "1 +\x1b[H2 \x1b[F\x7f");
+        assert_eq!(ed.line(), "2 1 ");
+        feed_keys(&mut ed, "\x1b[D\x1b[D\x1b[3~");
+        assert_eq!(ed.line(), "2  ");
+        assert_eq!(
+            feed_keys(&mut ed, "\x1b[D\x1b[D\x1b[Cx\r"),
+            editor::Action::Submit("2x  ".to_string())
+        );
+        assert_eq!(ed.line(), "");
+    }
+
+    #[test]
+    fn editor_eof_on_empty_line() {
+        let mut ed = editor::LineEditor::default();
+        assert_eq!(feed_keys(&mut ed, "1\x04"), editor::Action::Continue);
+        assert_eq!(feed_keys(&mut ed, "\x7f\x04"), editor::Action::Eof);
+    }
+
+    #[test]
+    fn editor_history_navigation() {
+        let mut ed = editor_with_history(&["1 2 +", "3 4 *"]);
+        feed_keys(&mut ed, "5");
+        feed_keys(&mut ed, "\x1b[A");
+        assert_eq!(ed.line(), "3 4 *");
+        feed_keys(&mut ed, "\x1b[A\x1b[A");
+        assert_eq!(ed.line(), "1 2 +");
+        feed_keys(&mut ed, "\x1b[B");
+        assert_eq!(ed.line(), "3 4 *");
+        feed_keys(&mut ed, "\x1b[B");
+        assert_eq!(ed.line(), "5");
+    }
+
+    #[test]
+    fn editor_history_skips_duplicates_and_blank_lines() {
+        let ed = editor_with_history(&["1", "1", "  ", "2", "1"]);
+        assert_eq!(ed.history().entries(), ["1", "2", "1"]);
+
+        let mut history = editor::History::new(2);
+        for line in ["1", "2", "3"] {
+            history.add(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["2", "3"]);
+    }
+
+    #[test]
+    fn editor_history_persists_to_file() {
+        let file = std::env::temp_dir()
+            .join(format!("rpn-calc-test-{}", std::process::id()))
+            .join("history");
+        let mut history = editor::History::with_file(10, file.clone());
+        history.add("1 2 +").unwrap();
+        history.add("sqrt").unwrap();
+
+        let history = editor::History::with_file(10, file.clone());
+        assert_eq!(history.entries(), ["1 2 +", "sqrt"]);
+        std::fs::remove_dir_all(file.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn editor_reverse_search() {
+        let mut ed = editor_with_history(&["8 8 * 6 6 * + sqrt", "1 2 +", "81 sqrt"]);
+        feed_keys(&mut ed, "\x12sq");
+        assert!(ed.render("> ").contains("(reverse-i-search)'sq': 81 sqrt"));
+        feed_keys(&mut ed, "\x12");
+        assert!(ed.render("> ").contains("8 8 * 6 6 * + sqrt"));
+        feed_keys(&mut ed, "\x1b[C");
+        assert_eq!(ed.line(), "8 8 * 6 6 * + sqrt");
+
+        assert_eq!(
+            feed_keys(&mut ed, "\x15\x12+\r"),
+            editor::Action::Submit("1 2 +".to_string())
+        );
+
+        feed_keys(&mut ed, "9\x12sq\x07");
+        assert_eq!(ed.line(), "9");
+    }
+
+    #[test]
+    fn editor_tab_completion() {
+        let mut ed = editor::LineEditor::default();
+        ed.set_completions(RpnCalc::new().completions());
+
+        feed_keys(&mut ed, "4 sq\t");
+        assert_eq!(ed.line(), "4 sqrt ");
+
+        feed_keys(&mut ed, "\x15Pri\t");
+        assert_eq!(ed.line(), "Print ");
+
+        assert_eq!(
+            feed_keys(&mut ed, "\x15c\t"),
+            editor::Action::ShowCompletions(vec![
+                "c".to_string(),
+                "chs".to_string(),
+                "clear".to_string(),
+                "cls".to_string(),
+                "cos".to_string(),
+                "cross".to_string()
+            ])
+        );
+        assert_eq!(ed.line(), "c");
+
+        feed_keys(&mut ed, "\x15foo\t");
+        assert_eq!(ed.line(), "foo");
+
+        // The words and aliases defined are completed too
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ dup * } 'square def alias twice dup");
+        process_command(&mut calc, "defscript hyp(a, b) = sqrt(a^2 + b^2)");
+        ed.set_completions(calc.completions());
+        feed_keys(&mut ed, "\x15squ\t");
+        assert_eq!(ed.line(), "square ");
+        feed_keys(&mut ed, "\x15tw\t");
+        assert_eq!(ed.line(), "twice ");
+        feed_keys(&mut ed, "\x15hy\t");
+        assert_eq!(ed.line(), "hyp ");
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rpn-calc-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn session_round_trip_special_values() {
+        let mut calc = RpnCalc::new();
+        calc.stack = vec![
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            -0.0,
+            0.1 + 0.2,
+            5e-324,
+            f64::MAX,
+        ]
+        .into_iter()
+        .map(value::Value::Number)
+        .collect();
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             stack NaN\n\
+             stack inf\n\
+             stack -inf\n\
+             stack -0.0\n\
+             stack 0.30000000000000004\n\
+             stack 5e-324\n\
+             stack 1.7976931348623157e308\n\
+             end\n"
+        );
+
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack.len(), calc.stack.len());
+        assert!(restored.stack[0].number().unwrap().is_nan());
+        for (a, b) in restored.stack.iter().zip(calc.stack.iter()).skip(1) {
+            assert_eq!(a.number().unwrap().to_bits(), b.number().unwrap().to_bits());
+        }
+    }
+
+    #[test]
+    fn session_ignores_comments_and_blank_lines() {
+        let session = session::Session::parse(
+            "# saved by hand\n\nrpn-calc session 1\n  stack 1.5\n# top\nstack 2\nend\n",
+        )
+        .unwrap();
+        let mut calc = RpnCalc::new();
+        session.restore(&mut calc);
+        assert_eq!(calc.stack, [1.5, 2.0]);
+    }
+
+    #[test]
+    fn session_rejects_corrupted_files() {
+        use session::{Session, SessionError};
+
+        assert_eq!(Session::parse(""), Err(SessionError::MissingHeader));
+        assert_eq!(
+            Session::parse("stack 1\nend\n"),
+            Err(SessionError::MissingHeader)
+        );
+        assert_eq!(
+            Session::parse("rpn-calc session 99\nend\n"),
+            Err(SessionError::UnsupportedVersion("99".to_string()))
+        );
+        assert_eq!(
+            Session::parse("rpn-calc session 1\nstack 1\nstack 1,5\nend\n"),
+            Err(SessionError::InvalidLine(3, "stack 1,5".to_string()))
+        );
+        assert_eq!(
+            Session::parse("rpn-calc session 1\nfoo 1\nend\n"),
+            Err(SessionError::InvalidLine(2, "foo 1".to_string()))
+        );
+        // Aliases are checked as the alias and unalias commands do
+        for line in [
+            "alias 2 dup",
+            "alias sqrt dup",
+            "alias m nothing",
+            "unalias sqrt",
+            "unalias nothing",
+        ] {
+            assert_eq!(
+                Session::parse(&format!("rpn-calc session 2\n{}\nend\n", line)),
+                Err(SessionError::InvalidLine(2, line.to_string()))
+            );
+        }
+        assert_eq!(
+            Session::parse("rpn-calc session 1\nstack 1\nstack 2"),
+            Err(SessionError::MissingEnd)
+        );
+    }
+
+    #[test]
+    fn cli_save_and_load_session() {
+        let file = temp_file("session");
+        let file_name = file.to_str().unwrap();
+
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, &format!("1 2 -0 save {} 3", file_name));
+        assert_eq!(calc.stack, [1.0, 2.0, 0.0, 3.0]);
+
+        let mut restored = RpnCalc::new();
+        process_command(&mut restored, &format!("10 LOAD {} +", file_name));
+        assert_eq!(restored.stack, [1.0, 2.0]);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn cli_load_invalid_session_keeps_stack() {
+        let file = temp_file("corrupted-session");
+        std::fs::write(&file, "rpn-calc session 1\nstack 1\n").unwrap();
+
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "5 6");
+        assert_eq!(
+            calc.load_session(&file),
+            Err(error::CalcError::Session(session::SessionError::MissingEnd))
+        );
+        process_command(&mut calc, &format!("load {}", file.to_str().unwrap()));
+        assert_eq!(calc.stack, [5.0, 6.0]);
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(matches!(
+            calc.load_session(&file),
+            Err(error::CalcError::Session(session::SessionError::Io(_)))
+        ));
+        assert_eq!(calc.stack, [5.0, 6.0]);
+    }
+
+    #[test]
+    fn cli_save_and_load_take_an_optional_file() {
+        let cmds = cli::CliCmd::tokenize("save load 1");
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(
+            cmds[0].oper,
+            cli::CliOperation::builtin("save", cli::Argument::Word("load".to_string()))
+        );
+        assert_eq!(cmds[1].oper, cli::CliOperation::Push(1.0));
+
+        let cmds = cli::CliCmd::tokenize("1 save");
+        assert_eq!(
+            cmds[1].oper,
+            cli::CliOperation::builtin("save", cli::Argument::None)
+        );
+    }
+
+    #[test]
+    fn filter_json_output() {
+        let (success, out, err) = run_filter_with_format(
+            "",
+            filter::StackMode::Continuous,
+            filter::OutputFormat::Json,
+            "1 2 +\n\n4 0 /\nc\n",
+        );
+        assert!(!success);
+        assert_eq!(err, "");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"line":1,"input":"1 2 +","stack":[3.0],"top":3.0,"error":null,"modes":{"stack":"continuous","notation":"rpn","exprs":"off","angle":"rad","prec":"off","atomic":"off"}}"#,
+                r#"{"line":3,"input":"4 0 /","stack":[3.0,4.0,0.0],"top":0.0,"error":{"code":"zero_division","message":"Zero division"},"modes":{"stack":"continuous","notation":"rpn","exprs":"off","angle":"rad","prec":"off","atomic":"off"}}"#,
+                r#"{"line":4,"input":"c","stack":[],"top":null,"error":null,"modes":{"stack":"continuous","notation":"rpn","exprs":"off","angle":"rad","prec":"off","atomic":"off"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_trace_kept_out_of_results() {
+        let (success, out, _) =
+            run_filter("", filter::StackMode::Continuous, "trace on 1 2 +\n3 *\n");
+        assert!(success);
+        assert_eq!(out, "3\n9\n");
+    }
+
+    #[test]
+    fn filter_json_modes_and_shown_text() {
+        // The text shown by commands as p or rpn is not written among the results
+        let (success, out, _) = run_filter_with_format(
+            "",
+            filter::StackMode::Continuous,
+            filter::OutputFormat::Json,
+            "prec 20 atomic on 2 p\nrpn 1 + 2\n",
+        );
+        assert!(success);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(
+            r#""modes":{"stack":"continuous","notation":"rpn","exprs":"off","angle":"rad","prec":"20","atomic":"on"}}"#
+        ));
+    }
+
+    #[test]
+    fn filter_json_output_with_program() {
+        let (success, out, _) = run_filter_with_format(
+            "sqrt",
+            filter::StackMode::PerLine,
+            filter::OutputFormat::Json,
+            "16\n-1\n",
+        );
+        assert!(!success);
+        assert_eq!(
+            out,
+            "{\"line\":1,\"input\":\"16\",\"stack\":[4.0],\"top\":4.0,\"error\":null,\"modes\":{\"stack\":\"per-line\",\"notation\":\"rpn\",\"exprs\":\"off\",\"angle\":\"rad\",\"prec\":\"off\",\"atomic\":\"off\"}}\n\
+             {\"line\":2,\"input\":\"-1\",\"stack\":[-1.0],\"top\":-1.0,\"error\":{\"code\":\"negative_square_root\",\"message\":\"Negative number square root\"},\"modes\":{\"stack\":\"per-line\",\"notation\":\"rpn\",\"exprs\":\"off\",\"angle\":\"rad\",\"prec\":\"off\",\"atomic\":\"off\"}}\n"
+        );
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(
+            json::string("a \"b\" \\ \t\u{1}"),
+            r#""a \"b\" \\ \t\u0001""#
+        );
+        assert_eq!(json::number(1.0), "1.0");
+        assert_eq!(json::number(-0.0), "-0.0");
+        assert_eq!(json::number(1e300), "1e300");
+        assert_eq!(json::number(f64::NAN), r#""NaN""#);
+        assert_eq!(json::number(f64::NEG_INFINITY), r#""-inf""#);
+        assert_eq!(json::array(vec![]), "[]");
+        assert_eq!(
+            json::object(&[
+                ("a", json::NULL.to_string()),
+                ("b", json::array(vec!["1".to_string()]))
+            ]),
+            r#"{"a":null,"b":[1]}"#
+        );
+    }
+
+    #[test]
+    fn cli_negate() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "neg 3 neg 4 chs");
+        assert_eq!(calc.stack, [-3.0, -4.0]);
+        process_command(&mut calc, "neg");
+        assert_eq!(calc.stack, [-3.0, 4.0]);
+    }
+
+    #[test]
+    fn infix_to_rpn() {
+        assert_eq!(
+            infix::to_rpn("(8*8 + 6*6)^0.5", &registry::DEFAULT_ALIASES),
+            Ok("8 8 * 6 6 * + 0.5 ^".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1 + 2 * 3", &registry::DEFAULT_ALIASES),
+            Ok("1 2 3 * +".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("(1 + 2) * 3", &registry::DEFAULT_ALIASES),
+            Ok("1 2 + 3 *".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("10 - 4 - 3", &registry::DEFAULT_ALIASES),
+            Ok("10 4 - 3 -".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("64 / 4 / 2", &registry::DEFAULT_ALIASES),
+            Ok("64 4 / 2 /".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("2^3^2", &registry::DEFAULT_ALIASES),
+            Ok("2 3 2 ^ ^".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("-2^2", &registry::DEFAULT_ALIASES),
+            Ok("2 2 ^ neg".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("2^-1", &registry::DEFAULT_ALIASES),
+            Ok("2 -1 ^".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("-(1+2)", &registry::DEFAULT_ALIASES),
+            Ok("1 2 + neg".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("--3 + +4", &registry::DEFAULT_ALIASES),
+            Ok("3 4 +".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("2 * -3", &registry::DEFAULT_ALIASES),
+            Ok("2 -3 *".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1.5e3+.5", &registry::DEFAULT_ALIASES),
+            Ok("1500 0.5 +".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("sqrt(8*8 + 6*6)", &registry::DEFAULT_ALIASES),
+            Ok("8 8 * 6 6 * + sqrt".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("POW(2, 1 + 2) - neg(1)", &registry::DEFAULT_ALIASES),
+            Ok("2 1 2 + ^ 1 neg -".to_string())
+        );
+    }
+
+    #[test]
+    fn infix_syntax_errors() {
+        assert_eq!(
+            infix::to_rpn("", &registry::DEFAULT_ALIASES),
+            Err("Empty expression".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1 +", &registry::DEFAULT_ALIASES),
+            Err("Unexpected end of expression".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("(1 + 2", &registry::DEFAULT_ALIASES),
+            Err("Unexpected end of expression".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("(1 + 2))", &registry::DEFAULT_ALIASES),
+            Err("Unexpected ')' at column 8".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1 2", &registry::DEFAULT_ALIASES),
+            Err("Unexpected '2' at column 3".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1 * * 2", &registry::DEFAULT_ALIASES),
+            Err("Unexpected '*' at column 5".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1 % 2", &registry::DEFAULT_ALIASES),
+            Err("Unexpected '%' at column 3".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("1.2.3", &registry::DEFAULT_ALIASES),
+            Err("Invalid number '1.2.3' at column 1".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("foo(1)", &registry::DEFAULT_ALIASES),
+            Err("Unknown function 'foo'".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("sqrt 4", &registry::DEFAULT_ALIASES),
+            Err("Missing '(' after function 'sqrt'".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("sqrt(1, 2)", &registry::DEFAULT_ALIASES),
+            Err("Function 'sqrt' takes 1 argument(s), 2 given".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("2*x + 1", &registry::DEFAULT_ALIASES),
+            Err("Unknown identifier 'x', symbols are written 'x".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("x(2, 3)", &registry::DEFAULT_ALIASES),
+            Ok("2 3 *".to_string())
+        );
+        for deep in [
+            "(".repeat(10000) + "1",
+            "-".repeat(10000) + "1",
+            "2^".repeat(10000) + "2",
+        ] {
+            assert_eq!(
+                infix::to_rpn(&deep, &registry::DEFAULT_ALIASES),
+                Err("Expression nested too deeply".to_string())
+            );
+        }
+        let nested = "(".repeat(100) + "1" + &")".repeat(100);
+        assert_eq!(
+            infix::to_rpn(&nested, &registry::DEFAULT_ALIASES),
+            Ok("1".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_infix_command() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "infix (8*8 + 6*6)^0.5");
+        assert_eq!(calc.stack, [10.0]);
+        process_command(&mut calc, "INFIX 2 * -(3 + 1)");
+        assert_eq!(calc.stack, [10.0, -8.0]);
+        process_command(&mut calc, "infix 2 *");
+        assert_eq!(calc.stack, [10.0, -8.0]);
+        process_command(&mut calc, "rpn 1 + 2");
+        assert_eq!(calc.stack, [10.0, -8.0]);
+    }
+
+    #[test]
+    fn cli_infix_notation() {
+        let parse = |line| {
+            cli::CliCmd::parse_line(line, cli::Notation::Infix, &registry::DEFAULT_ALIASES)
+                .into_iter()
+                .map(|cmd| cmd.oper)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            parse("sqrt(16) - 1"),
+            [
+                cli::CliOperation::Push(16.0),
+                cli::CliOperation::builtin("sqrt", cli::Argument::None),
+                cli::CliOperation::Push(1.0),
+                cli::CliOperation::builtin("-", cli::Argument::None),
+            ]
+        );
+        assert_eq!(parse("- 3"), [cli::CliOperation::Push(-3.0)]);
+        // Commands are still available
+        assert_eq!(
+            parse("q"),
+            [cli::CliOperation::builtin("quit", cli::Argument::None)]
+        );
+        assert_eq!(
+            parse("sqrt"),
+            [cli::CliOperation::builtin("sqrt", cli::Argument::None)]
+        );
+        assert_eq!(
+            parse("p c"),
+            [
+                cli::CliOperation::builtin("p", cli::Argument::None),
+                cli::CliOperation::builtin("clear", cli::Argument::None)
+            ]
+        );
+        assert_eq!(
+            parse("rpn 1+2"),
+            [cli::CliOperation::builtin(
+                "rpn",
+                cli::Argument::Text("1 2 +".to_string())
+            )]
+        );
+        assert_eq!(
+            parse("1 2 +"),
+            [cli::CliOperation::SyntaxError(
+                "Unexpected '2' at column 3".to_string()
+            )]
+        );
+        assert_eq!(
+            parse("foo"),
+            [cli::CliOperation::SyntaxError(
+                "Unknown function 'foo'".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn filter_infix_notation() {
+        let mut calc = RpnCalc::new();
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let mut filter = RpnCalc::filter("2 *", filter::StackMode::PerLine);
+        filter.set_notation(cli::Notation::Infix);
+        let input = "(8*8 + 6*6)^0.5\n1 +\n2^3^2\n";
+        let success = filter
+            .run(&mut calc, std::io::Cursor::new(input), &mut out, &mut err)
+            .unwrap();
+        assert!(!success);
+        assert_eq!(String::from_utf8(out).unwrap(), "20\n1024\n");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "line 2: Error: Unexpected end of expression\n"
+        );
+    }
+
+    fn exprs(calc: &RpnCalc) -> Vec<String> {
+        calc.exprs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn cli_track_expressions() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on 8 8 * 6 6 * + sqrt");
+        assert_eq!(calc.stack, [10.0]);
+        assert_eq!(exprs(&calc), ["sqrt(8*8 + 6*6)"]);
+
+        process_command(&mut calc, "c 1 2 3 - - 4 5 + 6 / 2 3 ^ 2 ^ neg");
+        assert_eq!(exprs(&calc), ["1 - (2 - 3)", "(4 + 5)/6", "-(2^3)^2"]);
+
+        process_command(&mut calc, "aa");
+        assert_eq!(exprs(&calc), ["1 - (2 - 3) + (4 + 5)/6 + (-(2^3)^2)"]);
+    }
+
+    #[test]
+    fn cli_track_expressions_starts_from_stack_values() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "1 2 3 exprs on xx 10 0 /");
+        assert_eq!(exprs(&calc), ["1*2*3", "10", "0"]);
+        process_command(&mut calc, "exprs off");
+        assert_eq!(calc.exprs, None);
+        process_command(&mut calc, "exprs maybe");
+        assert_eq!(calc.exprs, None);
+    }
+
+    #[test]
+    fn cli_track_expressions_of_infix() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on infix (8*8 + 6*6)^0.5 - -2^2");
+        assert_eq!(exprs(&calc), ["(8*8 + 6*6)^0.5 - (-2^2)"]);
+        process_command(&mut calc, "c infix 2^-1*3 - 4/(5*6) + 2*(3 - 1)");
+        assert_eq!(exprs(&calc), ["2^-1*3 - 4/(5*6) + 2*(3 - 1)"]);
+    }
+
+    #[test]
+    fn expr_minimal_parentheses() {
+        use expr::Expr;
+        let n = Expr::Number;
+        assert_eq!((n(1.0) - n(-2.0)).to_string(), "1 - (-2)");
+        assert_eq!((n(1.0) * n(-2.0)).to_string(), "1*-2");
+        assert_eq!(Expr::pow(n(-2.0), n(2.0)).to_string(), "(-2)^2");
+        assert_eq!(
+            Expr::pow(n(2.0), Expr::pow(n(3.0), n(2.0))).to_string(),
+            "2^3^2"
+        );
+        assert_eq!((-(-n(1.0))).to_string(), "-(-1)");
+        assert_eq!(
+            (n(1.0) * n(2.0) / (n(3.0) / n(4.0))).to_string(),
+            "1*2/(3/4)"
+        );
+        assert_eq!(((n(1.0) + n(2.0)) * -n(3.0)).to_string(), "(1 + 2)*-3");
+    }
+
+    #[test]
+    fn cli_show_expression_requires_tracking() {
+        let mut calc = RpnCalc::new();
+        let cmds = cli::CliCmd::tokenize("expr");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::ExprsOff)
+        );
+        process_command(&mut calc, "exprs on");
+        assert_eq!(calculator::process(&mut calc, &cmds[0]), Ok(()));
+    }
+
+    #[test]
+    fn session_keeps_expression_tracking_mode() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on 2 3 +");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\nmode exprs on\nstack 5.0\nend\n"
+        );
+
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(exprs(&restored), ["5"]);
+        assert_eq!(
+            restored.modes(),
+            [
+                ("exprs", "on".to_string()),
+                ("angle", "rad".to_string()),
+                ("prec", "off".to_string()),
+                ("atomic", "off".to_string())
+            ]
+        );
+    }
+
+    fn top(calc: &RpnCalc) -> String {
+        calc.stack.last().unwrap().to_string()
+    }
+
+    #[test]
+    fn cli_symbolic_expressions() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "'x 2 ^ 3 'x * +");
+        assert_eq!(top(&calc), "x^2 + 3*x");
+
+        let cases = [
+            ("'x 'x +", "2*x"),
+            ("'x 2 * 'x 3 * + 'x -", "4*x"),
+            ("2 3 * 'x + 1 -", "x + 5"),
+            ("'y 'x * 'x 'y * +", "2*x*y"),
+            ("'x 'x * 'y /", "x^2/y"),
+            ("'x 2 ^ 'x /", "x"),
+            ("'x 1 + 2 *", "2*x + 2"),
+            ("'x 'y - neg", "-x + y"),
+            ("'x 2 * 3 ^", "8*x^3"),
+            ("'x 1 + 2 ^", "(x + 1)^2"),
+            ("4 sqrt 'x *", "2*x"),
+            ("'x sqrt 4 sqrt *", "2*sqrt(x)"),
+        ];
+        for (program, expected) in cases {
+            process_command(&mut calc, &format!("c {}", program));
+            assert_eq!(top(&calc), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn cli_symbolic_identities() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "'x 0 + 1 * 1 ^ 1 /");
+        assert_eq!(top(&calc), "x");
+        process_command(&mut calc, "c 'x 0 * 'x 0 ^ 'x 'x -");
+        assert_eq!(calc.stack, [0.0, 1.0, 0.0]);
+        assert!(calc.stack.iter().all(|v| v.number().is_some()));
+    }
+
+    #[test]
+    fn cli_symbolic_errors_leave_the_stack_unchanged() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "'x 0 /");
+        assert_eq!(calc.stack.len(), 2);
+        process_command(&mut calc, "c 'x -4 sqrt");
+        assert_eq!(calc.stack.len(), 2);
+        process_command(&mut calc, "c 'x 1 2 subst");
+        assert_eq!(calc.stack.len(), 3);
+    }
+
+    #[test]
+    fn cli_substitute_and_evaluate() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "'x 2 ^ 3 'x * + 5 'x subst");
+        assert_eq!(calc.stack, [40.0]);
+
+        process_command(&mut calc, "c 'x 'y * 'x + 'y 1 + 'y subst");
+        assert_eq!(top(&calc), "x*(y + 1) + x");
+        process_command(&mut calc, "2 'x subst");
+        assert_eq!(top(&calc), "2*y + 4");
+
+        let cmds = cli::CliCmd::tokenize("eval");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::UnboundSymbol("y".to_string()))
+        );
+        process_command(&mut calc, "0.5 'y subst eval");
+        assert_eq!(calc.stack, [5.0]);
+    }
+
+    #[test]
+    fn expr_evaluate_and_substitute() {
+        use expr::Expr;
+        let x = || Expr::Symbol("x".to_string());
+        let expr = Expr::pow(x(), Expr::Number(2.0)) + x();
+        assert_eq!(expr.eval(), Err("x".to_string()));
+        let expr = expr.substitute("x", &Expr::Number(3.0));
+        assert_eq!(expr.to_string(), "3^2 + 3");
+        assert_eq!(expr.eval(), Ok(12.0));
+    }
+
+    #[test]
+    fn infix_symbols() {
+        assert_eq!(
+            infix::to_rpn("'x^2 + 3*'x", &registry::DEFAULT_ALIASES),
+            Ok("'x 2 ^ 3 'x * +".to_string())
+        );
+        assert_eq!(
+            infix::to_rpn("'1x", &registry::DEFAULT_ALIASES),
+            Err("Invalid symbol at column 1".to_string())
+        );
+
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "infix sqrt('x)*2 - 'x/'x");
+        assert_eq!(top(&calc), "2*sqrt(x) - 1");
+    }
+
+    #[test]
+    fn session_round_trip_symbolic_values() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "1 'x 2 ^ 3 'x * + 'y neg sqrt");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             stack 1.0\n\
+             symbolic 'x 2.0 ^ 3.0 'x * +\n\
+             symbolic 'y neg sqrt\n\
+             end\n"
+        );
+
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack, calc.stack);
+
+        assert_eq!(
+            session::Session::parse("rpn-calc session 1\nsymbolic 1 save\nend\n"),
+            Err(session::SessionError::InvalidLine(
+                2,
+                "symbolic 1 save".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn filter_json_symbolic_values() {
+        let (success, out, _) = run_filter_with_format(
+            "",
+            filter::StackMode::PerLine,
+            filter::OutputFormat::Json,
+            "'x 1 +\n",
+        );
+        assert!(success);
+        assert!(
+            out.contains(r#""stack":["x + 1"],"top":"x + 1""#),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn cli_elementary_functions() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "1 exp ln 0 sin 0 cos 1 atan 4 *");
+        assert_eq!(calc.stack, [1.0, 0.0, 1.0, std::f64::consts::PI]);
+
+        let cmds = cli::CliCmd::tokenize("0 ln -2 asin");
+        calculator::process(&mut calc, &cmds[0]).unwrap();
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[1]),
+            Err(error::CalcError::OutOfDomain("ln"))
+        );
+        calculator::process(&mut calc, &cmds[2]).unwrap();
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[3]),
+            Err(error::CalcError::OutOfDomain("asin"))
+        );
+
+        process_command(&mut calc, "c infix exp('x)*sin(0)+cos('x)");
+        assert_eq!(top(&calc), "cos(x)");
+    }
+
+    #[test]
+    fn cli_derivative() {
+        let mut calc = RpnCalc::new();
+        let cases = [
+            ("'x 2 ^ 3 'x * +", "2*x + 3"),
+            ("'x 'y * 'y +", "y"),
+            ("'x 'x sin *", "sin(x) + x*cos(x)"),
+            ("1 'x /", "-1/x^2"),
+            ("'x 1 + 'x 1 - /", "-2/(x - 1)^2"),
+            ("'x 1 + 3 ^", "3*(x + 1)^2"),
+            ("'x 'x ^", "x^x*(ln(x) + 1)"),
+            ("'x 2 ^ sin", "2*x*cos(x^2)"),
+            ("'x cos", "-sin(x)"),
+            ("'x tan", "1/cos(x)^2"),
+            ("'x sqrt", "0.5/sqrt(x)"),
+            ("'x exp", "exp(x)"),
+            ("'x ln", "1/x"),
+            ("'x atan", "1/(x^2 + 1)"),
+            ("'x asin", "1/sqrt(-x^2 + 1)"),
+            ("'x acos", "-1/sqrt(-x^2 + 1)"),
+        ];
+        for (program, expected) in cases {
+            process_command(&mut calc, &format!("c {} 'x deriv", program));
+            assert_eq!(top(&calc), expected, "{}", program);
+        }
+
+        process_command(&mut calc, "c 5 'x deriv 'y 'x deriv");
+        assert_eq!(calc.stack, [0.0, 0.0]);
+        process_command(&mut calc, "c 'x 3 ^ 'x deriv 'x deriv 2 'x subst");
+        assert_eq!(calc.stack, [12.0]);
+    }
+
+    #[test]
+    fn cli_derivative_requires_a_symbol() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "'x 2 ^ 2");
+        let cmds = cli::CliCmd::tokenize("deriv");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NotASymbol)
+        );
+        assert_eq!(calc.stack.len(), 2);
+    }
+
+    #[test]
+    fn cli_stack_manipulation() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "1 2 swap dup");
+        assert_eq!(calc.stack, [2.0, 1.0, 1.0]);
+        process_command(&mut calc, "drop drop drop drop swap");
+        assert!(calc.stack.is_empty());
+    }
+
+    #[test]
+    fn cli_programs_and_words() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{dup *} 3 swap");
+        assert_eq!(top(&calc), "{ dup * }");
+        process_command(&mut calc, "eval");
+        assert_eq!(calc.stack, [9.0]);
+
+        process_command(&mut calc, "c { dup * } 'sq def { sq sq } 'quad def 2 quad");
+        assert_eq!(calc.stack, [16.0]);
+        assert_eq!(calc.words.len(), 2);
+
+        process_command(&mut calc, "c { 1 { 2 } } ");
+        assert_eq!(top(&calc), "{ 1 { 2 } }");
+    }
+
+    #[test]
+    fn cli_word_errors() {
+        let mut calc = RpnCalc::new();
+        let error = |calc: &mut RpnCalc, line: &str| {
+            let cmds = cli::CliCmd::tokenize(line);
+            let (last, first) = cmds.split_last().unwrap();
+            for cmd in first {
+                calculator::process(calc, cmd).unwrap();
+            }
+            calculator::process(calc, last).unwrap_err()
+        };
+        assert_eq!(error(&mut calc, "foo"), error::CalcError::UnknownCommand);
+        assert_eq!(
+            error(&mut calc, "{ 1 } 'add def"),
+            error::CalcError::ReservedName("add".to_string())
+        );
+        assert_eq!(
+            error(&mut calc, "c { f } 'f def f"),
+            error::CalcError::TooManyNestedCalls
+        );
+        assert_eq!(
+            error(&mut calc, "c { 1 +"),
+            error::CalcError::Syntax("Missing '}'".to_string())
+        );
+        assert_eq!(
+            error(&mut calc, "c 1 'x + 2 'y def"),
+            error::CalcError::WrongType
+        );
+        assert_eq!(error(&mut calc, "c { 1 } 2 +"), error::CalcError::WrongType);
+    }
+
+    struct Hypot;
+
+    impl registry::Operation for Hypot {
+        fn name(&self) -> &str {
+            "hypot"
+        }
+
+        fn aliases(&self) -> &[&str] {
+            &["hyp"]
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn help(&self) -> &str {
+            "Length of the hypotenuse of two sides"
+        }
+
+        fn execute(&self, calc: &mut RpnCalc) -> Result<(), error::CalcError> {
+            let b = calc.pop().and_then(|b| b.number());
+            let a = calc.pop().and_then(|a| a.number());
+            let (a, b) = a.zip(b).ok_or(error::CalcError::WrongType)?;
+            calc.push(value::Value::Number(a.hypot(b)));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registry_operations() {
+        registry::register(Hypot).unwrap();
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on 3 4 hypot 6 8 HYP");
+        assert_eq!(calc.stack, [5.0, 10.0]);
+        process_command(&mut calc, "c infix hypot(5, 12)");
+        assert_eq!(calc.stack, [13.0]);
+
+        // Checked arity, and the stack restored on errors
+        let cmds = cli::CliCmd::tokenize("hypot");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::StackUnderflow)
+        );
+        process_command(&mut calc, "'x");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::WrongType)
+        );
+        assert_eq!(calc.stack.len(), 2);
+
+        process_command(&mut calc, "c { 1 } 'hyp def");
+        assert_eq!(calc.stack.len(), 2);
+        assert!(RpnCalc::new()
+            .aliases
+            .names()
+            .contains(&"hypot".to_string()));
+        let help = RpnCalc::new().aliases.help();
+        assert!(help.contains(&(
+            "hypot hyp".to_string(),
+            "Length of the hypotenuse of two sides".to_string()
+        )));
+        assert!(help.contains(&(
+            "save [file]".to_string(),
+            "Save the session to file (default: session file)".to_string()
+        )));
+    }
+
+    #[test]
+    fn registry_name_conflicts() {
+        struct Named(&'static str);
+
+        impl registry::Operation for Named {
+            fn name(&self) -> &str {
+                self.0
+            }
+
+            fn arity(&self) -> usize {
+                0
+            }
+
+            fn help(&self) -> &str {
+                ""
+            }
+
+            fn execute(&self, _: &mut RpnCalc) -> Result<(), error::CalcError> {
+                Ok(())
+            }
+        }
+
+        let reserved = |name: &str| Err(error::CalcError::ReservedName(name.to_string()));
+        assert_eq!(registry::register(Named("dup")), reserved("dup"));
+        assert_eq!(registry::register(Named("infix")), reserved("infix"));
+        assert_eq!(registry::register(Named("twice")), Ok(()));
+        assert_eq!(registry::register(Named("twice")), reserved("twice"));
+        for name in ["", "two words", "Upper", "'x", "1.5", "{"] {
+            let invalid = format!("Invalid operation name '{}'", name);
+            assert_eq!(
+                registry::register(Named(name)),
+                Err(error::CalcError::Syntax(invalid))
+            );
+        }
+    }
+
+    #[test]
+    fn plugin_words() {
+        let dir = temp_file("plugins");
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugin = "# Temperatures\n\
+                      rpn-calc plugin 1\n\
+                      requires 0.1.0\n\
+                      word f2c 1 32 - 5 * 9 /\n\
+                      help Convert Fahrenheit to Celsius\n\
+                      word c2f 1 9 * 5 / 32 +\n";
+        std::fs::write(dir.join("temperature.rpn"), plugin).unwrap();
+        std::fs::write(dir.join("notes.txt"), "Not a plugin").unwrap();
+        std::fs::write(dir.join("broken.rpn"), "rpn-calc plugin 2\n").unwrap();
+        std::fs::write(
+            dir.join("reserved.rpn"),
+            "rpn-calc plugin 1\nword k 0 1\nword dup 0 1\n",
+        )
+        .unwrap();
+
+        let loaded = plugin::load_plugins(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let results: Vec<_> = loaded.iter().map(|(_, result)| result).collect();
+        assert_eq!(
+            results,
+            [
+                &Err(plugin::PluginError::UnsupportedVersion("2".to_string())),
+                &Err(plugin::PluginError::Register(
+                    error::CalcError::ReservedName("dup".to_string())
+                )),
+                &Ok(2),
+            ]
+        );
+
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on 212 f2c 100 c2f");
+        assert_eq!(calc.stack, [100.0, 212.0]);
+        assert_eq!(calc.exprs.as_ref().unwrap().len(), 2);
+        let cmds = cli::CliCmd::tokenize("c 'x f2c");
+        calculator::process(&mut calc, &cmds[0]).unwrap();
+        calculator::process(&mut calc, &cmds[1]).unwrap();
+        assert_eq!(calculator::process(&mut calc, &cmds[2]), Ok(()));
+        assert_eq!(top(&calc), "0.5555555555555556*x - 17.77777777777778");
+        process_command(&mut calc, "c");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[2]),
+            Err(error::CalcError::StackUnderflow)
+        );
+
+        // No word of a plugin failing to load is added
+        assert!(!RpnCalc::new().aliases.names().contains(&"k".to_string()));
+        let help = RpnCalc::new().aliases.help();
+        assert!(help.contains(&(
+            "f2c".to_string(),
+            "Convert Fahrenheit to Celsius".to_string()
+        )));
+    }
+
+    #[test]
+    fn programs_ignore_aliases() {
+        let dir = temp_file("alias-plugins");
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugin = "rpn-calc plugin 1\nword sqx 1 dup x\n";
+        std::fs::write(dir.join("square.rpn"), plugin).unwrap();
+        let loaded = plugin::load_plugins(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded[0].1, Ok(1));
+
+        // Words and plugin words run the built-in commands they were written
+        // with, whatever the aliases
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ dup x } 'sq def alias x +");
+        process_command(&mut calc, "3 sqx 3 sq");
+        assert_eq!(calc.stack, [9.0, 9.0]);
+        process_command(&mut calc, "c unalias x");
+        process_command(&mut calc, "3 sqx 3 sq");
+        assert_eq!(calc.stack, [9.0, 9.0]);
+
+        // Nor can a word take a built-in name removed
+        let cmds = cli::CliCmd::tokenize_with("{ } 'x def", &calc.aliases);
+        calculator::process(&mut calc, &cmds[0]).unwrap();
+        calculator::process(&mut calc, &cmds[1]).unwrap();
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[2]),
+            Err(error::CalcError::ReservedName("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn plugin_rejects_invalid_files() {
+        use plugin::{Plugin, PluginError};
+
+        assert!(Plugin::parse("rpn-calc plugin 1\n").is_ok());
+        assert_eq!(
+            Plugin::parse("word f 1 dup\n"),
+            Err(PluginError::MissingHeader)
+        );
+        assert_eq!(
+            Plugin::parse("rpn-calc plugin 1\nrequires 99.0.0\n"),
+            Err(PluginError::RequiresVersion("99.0.0".to_string()))
+        );
+        assert_eq!(
+            PluginError::RequiresVersion("99.0.0".to_string()).to_string(),
+            "Requires rpn-calc 99.0.0 or later, this is 0.1.0"
+        );
+        for (content, n, line) in [
+            ("requires 1.0", 2, "requires 1.0"),
+            ("word f dup", 2, "word f dup"),
+            ("help Before any word", 2, "help Before any word"),
+            ("word f 1 dup\nhelp Once\nhelp Twice", 4, "help Twice"),
+            ("words f 1 dup", 2, "words f 1 dup"),
+        ] {
+            assert_eq!(
+                Plugin::parse(&format!("rpn-calc plugin 1\n{}\n", content)),
+                Err(PluginError::InvalidLine(n, line.to_string()))
+            );
+        }
+
+        // The commands of the words are read when loaded, the words they call
+        // being possibly defined later
+        assert!(Plugin::parse(
+            "rpn-calc plugin 1
+word f 1 g dup
+"
+        )
+        .is_ok());
+        for (content, e) in [
+            ("word f 1 { dup", "Invalid word at line 3: Missing '}'"),
+            ("word f 1 1 ] +", "Invalid word at line 3: Unexpected ']'"),
+            ("word f 1 dup @", "Invalid word at line 3: Unknown command '@'"),
+            ("word f 0 prec x", "Invalid word at line 3: Expected a number of digits up to 10000 or 'off' after 'prec'"),
+        ] {
+            let plugin = format!("rpn-calc plugin 1\n\n{}\n", content);
+            assert_eq!(Plugin::parse(&plugin).unwrap_err().to_string(), e);
+        }
+    }
+
+    #[test]
+    fn cli_scripts() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "defscript pyth(a, b) = sqrt(a^2 + b^2)");
+        process_command(&mut calc, "1 3 4 pyth");
+        assert_eq!(calc.stack, [1.0, 5.0]);
+
+        let fact = "defscript fact(n) { let p = 1; while n > 1 { p = p * n; n = n - 1 } return p }";
+        process_command(&mut calc, fact);
+        process_command(&mut calc, "c 10 fact");
+        assert_eq!(calc.stack, [3628800.0]);
+
+        // Any number of results, -2^2 being -(2^2)
+        let divmod = "defscript divmod(a, b) { if b == 0 { return } else if a < 0 || b < 0 \
+                      { return -1 } return floor(a / b), a % b, -2^2 }";
+        process_command(&mut calc, divmod);
+        process_command(&mut calc, "c 17 5 divmod");
+        assert_eq!(calc.stack, [3.0, 2.0, -4.0]);
+        process_command(&mut calc, "c 17 0 divmod");
+        assert!(calc.stack.is_empty());
+        process_command(&mut calc, "c 17 -5 divmod");
+        assert_eq!(calc.stack, [-1.0]);
+
+        // Words and scripts replace each other
+        process_command(&mut calc, "c { 2 * } 'pyth def 4 pyth");
+        assert_eq!(calc.stack, [8.0]);
+        process_command(&mut calc, "defscript pyth(a) = a + 1");
+        process_command(&mut calc, "pyth");
+        assert_eq!(calc.stack, [9.0]);
+        assert!(!calc.words.contains_key("pyth"));
+
+        process_command(&mut calc, "c exprs on 'x 2 3 pyth");
+        assert_eq!(calc.exprs.as_ref().unwrap().len(), 3);
+        process_command(&mut calc, "prec 30 1 3 divmod");
+        assert_eq!(
+            calc.stack[3],
+            value::Value::Big(bigfloat::BigFloat::parse("0").unwrap())
+        );
+
+        // Angles are in the unit of the angle mode
+        process_command(
+            &mut calc,
+            "prec off exprs off defscript ang(a) = sin(a) + asin(1)",
+        );
+        process_command(&mut calc, "c deg 30 ang");
+        assert_eq!(calc.stack, [90.5]);
+        process_command(&mut calc, "c rad 0 ang");
+        assert_eq!(calc.stack, [std::f64::consts::FRAC_PI_2]);
+    }
+
+    #[test]
+    fn cli_script_errors() {
+        let mut calc = RpnCalc::new();
+        let error = |calc: &mut RpnCalc, line: &str| {
+            let cmds = cli::CliCmd::tokenize(line);
+            let (last, first) = cmds.split_last().unwrap();
+            for cmd in first {
+                calculator::process(calc, cmd).unwrap();
+            }
+            calculator::process(calc, last).unwrap_err()
+        };
+        let syntax = |e: &str| error::CalcError::Syntax(e.to_string());
+        assert_eq!(
+            error(&mut calc, "defscript"),
+            syntax("Unexpected end of the script")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a, a) = a"),
+            syntax("Parameter 'a' is repeated")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) = a +"),
+            syntax("Unexpected end of the script")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) = a $ 1"),
+            syntax("Unexpected '$' at column 10")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) { return a"),
+            syntax("Expected '}' at the end of the script")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) { return a ) }"),
+            syntax("Expected a name at column 17, found ')'")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) = a 1"),
+            syntax("Unexpected '1' after the script")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) = load(a)"),
+            syntax("Unknown function 'load'")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript f(a) = min(a)"),
+            syntax("'min' takes 2 arguments")
+        );
+        assert_eq!(
+            error(&mut calc, "defscript dup(a) = a"),
+            error::CalcError::ReservedName("dup".to_string())
+        );
+        assert_eq!(
+            error(&mut calc, "defscript infix(a) = a"),
+            error::CalcError::ReservedName("infix".to_string())
+        );
+        let nested = [
+            "(".repeat(10000) + "a",
+            "-".repeat(10000) + "a",
+            "a+".repeat(10000) + "a",
+            "2^".repeat(10000) + "a",
+            "f(".repeat(10000) + "a",
+        ];
+        for body in nested {
+            assert_eq!(
+                error(&mut calc, &format!("defscript f(a) = {}", body)),
+                syntax("Script nested too deeply")
+            );
+        }
+        let ifs = "if a { ".repeat(10000);
+        assert_eq!(
+            error(&mut calc, &format!("defscript f(a) {{ {} }}", ifs)),
+            syntax("Script nested too deeply")
+        );
+        let body = "(".repeat(127) + "a" + &")".repeat(127);
+        process_command(&mut calc, &format!("defscript f(a) = {}", body));
+        process_command(&mut calc, "2 f");
+        assert_eq!(calc.stack, [2.0]);
+        process_command(&mut calc, "c");
+
+        // The stack is unchanged when a script fails
+        process_command(&mut calc, "defscript f(a) { b = a }");
+        assert_eq!(
+            error(&mut calc, "1 f"),
+            error::CalcError::Script("Unknown variable 'b'".to_string())
+        );
+        process_command(&mut calc, "defscript f(a) { while 1 { } }");
+        assert_eq!(
+            error(&mut calc, "f"),
+            error::CalcError::Script("Stopped after 1000000 instructions".to_string())
+        );
+        process_command(&mut calc, "defscript f(a) = ln(a) / (a - 1)");
+        assert_eq!(
+            error(&mut calc, "c -1 f"),
+            error::CalcError::OutOfDomain("ln")
+        );
+        assert_eq!(error(&mut calc, "c 1 f"), error::CalcError::ZeroDivision);
+        assert_eq!(error(&mut calc, "c 'x f"), error::CalcError::WrongType);
+        assert_eq!(error(&mut calc, "c f"), error::CalcError::StackUnderflow);
+        assert!(calc.stack.is_empty());
+    }
+
+    #[test]
+    fn session_scripts() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "defscript pyth(a, b) = sqrt(a^2 + b^2)");
+        let saved = session::Session::from_calc(&calc).to_string();
+        assert!(saved.contains("\nscript pyth(a, b) = sqrt(a^2 + b^2)\n"));
+
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&saved)
+            .unwrap()
+            .restore(&mut restored);
+        process_command(&mut restored, "6 8 pyth");
+        assert_eq!(restored.stack, [10.0]);
+        assert_eq!(
+            session::Session::parse("rpn-calc session 1\nscript f(a) = \nend\n"),
+            Err(session::SessionError::InvalidLine(
+                2,
+                "script f(a) =".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn cli_solve() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ 2 ^ 2 - } 1 solve");
+        assert!((calc.stack[0].number().unwrap() - 2f64.sqrt()).abs() < 1e-15);
+
+        process_command(&mut calc, "c { dup * 2 - } 'f def 'f -3 0 solve");
+        assert!((calc.stack[0].number().unwrap() + 2f64.sqrt()).abs() < 1e-15);
+
+        process_command(&mut calc, "c 7 { dup cos swap - } 0 solve");
+        assert_eq!(calc.stack.len(), 2);
+        assert!((calc.stack[1].number().unwrap() - 0.7390851332151607).abs() < 1e-15);
+
+        process_command(&mut calc, "c { exp 10 - } 0 solve");
+        assert!((calc.stack[0].number().unwrap() - 10f64.ln()).abs() < 1e-14);
+    }
+
+    #[test]
+    fn cli_solve_failures() {
+        let mut calc = RpnCalc::new();
+        let cmds = cli::CliCmd::tokenize("solve");
+        process_command(&mut calc, "{ 2 ^ 1 + } 0 2");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoSignChange)
+        );
+        process_command(&mut calc, "drop");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoConvergence)
+        );
+        assert_eq!(calc.stack.len(), 2);
+
+        process_command(&mut calc, "c { 1 swap / } 1");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoConvergence)
+        );
+        process_command(&mut calc, "c { 0 / } 1");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::ZeroDivision)
+        );
+        process_command(&mut calc, "c 1 2");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::WrongType)
+        );
+    }
+
+    #[test]
+    fn session_round_trip_programs_and_words() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ dup * } 'sq def { sq 2 - }");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             word sq dup *\n\
+             program sq 2 -\n\
+             end\n"
+        );
+
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        process_command(&mut restored, "3 swap eval");
+        assert_eq!(restored.stack, [7.0]);
+    }
+
+    #[test]
+    fn cli_integrate() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ sin } 0 3.141592653589793 integrate");
+        let (value, error) = (
+            calc.stack[0].number().unwrap(),
+            calc.stack[1].number().unwrap(),
+        );
+        assert!((value - 2.0).abs() < 1e-14);
+        assert!(error < 1e-10);
+
+        process_command(&mut calc, "c { dup * } 'sq def 'sq 1 0 integrate drop");
+        assert!((calc.stack[0].number().unwrap() + 1.0 / 3.0).abs() < 1e-15);
+
+        process_command(&mut calc, "c { 2 ^ neg exp } -10 10 integrate drop");
+        let gauss = std::f64::consts::PI.sqrt();
+        assert!((calc.stack[0].number().unwrap() - gauss).abs() < 1e-12);
+
+        process_command(&mut calc, "c { ln } 0 1 integrate drop");
+        assert!((calc.stack[0].number().unwrap() + 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn cli_integrate_settings() {
+        let mut calc = RpnCalc::new();
+        let cmds = cli::CliCmd::tokenize("integrate");
+
+        process_command(&mut calc, "{ sqrt } 0 1");
+        process_command(&mut calc, "maxevals 50");
+        assert_eq!(calc.max_evaluations, 50);
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoConvergence)
+        );
+        assert_eq!(calc.stack.len(), 3);
+
+        process_command(&mut calc, "tolerance 1e-4 maxevals 1000 integrate");
+        assert_eq!(calc.tolerance, 1e-4);
+        let error = calc.stack[1].number().unwrap();
+        assert!(error > 1e-10 && error < 1e-4);
+
+        process_command(&mut calc, "tolerance -1 maxevals 0.5");
+        assert_eq!(calc.tolerance, 1e-4);
+        assert_eq!(calc.max_evaluations, 1000);
+    }
+
+    #[test]
+    fn cli_integrate_failures() {
+        let mut calc = RpnCalc::new();
+        let cmds = cli::CliCmd::tokenize("integrate");
+        process_command(&mut calc, "{ 1 swap / } 0 1");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoConvergence)
+        );
+        process_command(&mut calc, "c { } 0 'x");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::WrongType)
+        );
+        process_command(&mut calc, "c 0 1");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::StackUnderflow)
+        );
+    }
+
+    // Real then imaginary parts of the roots, rounded to 1e-9
+    fn roots(calc: &mut RpnCalc, p: &str) -> Vec<(f64, f64)> {
+        process_command(calc, &format!("c {} roots", p));
+        let round = |n: f64| (n * 1e9).round() / 1e9 + 0.0;
+        match &calc.stack[..] {
+            [value::Value::Vector(re), value::Value::Vector(im)] => re
+                .iter()
+                .zip(im.iter())
+                .map(|(&re, &im)| (round(re), round(im)))
+                .collect(),
+            stack => panic!("Not roots: {:?}", stack),
+        }
+    }
+
+    #[test]
+    fn cli_polynomials() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[1 -3 2] 5 polyval");
+        assert_eq!(calc.stack, [12.0]);
+        process_command(&mut calc, "c [1 -3 2] 'x polyval");
+        assert_eq!(top(&calc), "x^2 - 3*x + 2");
+
+        process_command(&mut calc, "c [1 -3 2] [1 1] padd");
+        assert_eq!(top(&calc), "[1 -2 3]");
+        process_command(&mut calc, "c [1 1] [1 1] psub");
+        assert_eq!(top(&calc), "[]");
+        process_command(&mut calc, "c [1 -1] [1 1] pmul");
+        assert_eq!(top(&calc), "[1 0 -1]");
+        process_command(&mut calc, "c [1 0 0 -2] [1 -1] pdiv");
+        assert_eq!(format!("{:?}", calc.stack), "[[1.0 1.0 1.0], [-1.0]]");
+        process_command(&mut calc, "c [1 0 -1] [1 -1] pdiv");
+        assert_eq!(format!("{:?}", calc.stack), "[[1.0 1.0], []]");
+        process_command(&mut calc, "c [2 -3 0 5] pder");
+        assert_eq!(top(&calc), "[6 -6 0]");
+
+        let cmds = cli::CliCmd::tokenize("pdiv");
+        process_command(&mut calc, "c [1 2] [0]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::ZeroDivision)
+        );
+        process_command(&mut calc, "c [1 2] 3");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::WrongType)
+        );
+        assert_eq!(calc.stack.len(), 2);
+
+        let cmds = cli::CliCmd::tokenize("[1 x] [1 2");
+        assert_eq!(
+            cmds[0].oper,
+            cli::CliOperation::SyntaxError("Invalid vector element 'x'".to_string())
+        );
+        assert_eq!(
+            cmds[1].oper,
+            cli::CliOperation::SyntaxError("Missing ']'".to_string())
+        );
+    }
+
+    #[test]
+    fn cli_polynomial_roots() {
+        let mut calc = RpnCalc::new();
+        assert_eq!(roots(&mut calc, "[2 -4]"), [(2.0, 0.0)]);
+        assert_eq!(roots(&mut calc, "[1 -3 2]"), [(2.0, 0.0), (1.0, 0.0)]);
+        assert_eq!(roots(&mut calc, "[1 0 1]"), [(0.0, 1.0), (0.0, -1.0)]);
+        assert_eq!(roots(&mut calc, "[1 2 5]"), [(-1.0, 2.0), (-1.0, -2.0)]);
+        assert_eq!(
+            roots(&mut calc, "[1 -6 11 -6]"),
+            [(3.0, 0.0), (2.0, 0.0), (1.0, 0.0)]
+        );
+        assert_eq!(
+            roots(&mut calc, "[1 0 0 -8]"),
+            [(2.0, 0.0), (-1.0, 1.732050808), (-1.0, -1.732050808)]
+        );
+        assert_eq!(
+            roots(&mut calc, "[1 0 0 0 -1]"),
+            [(1.0, 0.0), (0.0, 1.0), (0.0, -1.0), (-1.0, 0.0)]
+        );
+        assert_eq!(
+            roots(&mut calc, "[1 -15 85 -225 274 -120]"),
+            [(5.0, 0.0), (4.0, 0.0), (3.0, 0.0), (2.0, 0.0), (1.0, 0.0)]
+        );
+        assert_eq!(roots(&mut calc, "[0 0 3]"), []);
+        // The multiple roots are only found within the rounding errors
+        let multiple = roots(&mut calc, "[1 -4 6 -4 1]");
+        assert_eq!(multiple.len(), 4);
+        assert!(multiple.iter().all(|(re, im)| (re - 1.0).hypot(*im) < 1e-3));
+
+        // b^2 would overflow
+        process_command(&mut calc, "c [1 1e200 1] roots drop");
+        match &calc.stack[..] {
+            [value::Value::Vector(v)] => {
+                assert!((v[0] / -1e-200 - 1.0).abs() < 1e-12);
+                assert!((v[1] / -1e200 - 1.0).abs() < 1e-12);
+            }
+            stack => panic!("Not roots: {:?}", stack),
+        }
+
+        let cmds = cli::CliCmd::tokenize("roots");
+        process_command(&mut calc, "c [0 0]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::OutOfDomain("roots"))
+        );
+        process_command(&mut calc, "c [1 0 0 0 1e300]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::NoConvergence)
+        );
+    }
+
+    #[test]
+    fn session_vectors() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[1 -0.5 inf] []");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             vector 1.0 -0.5 inf\n\
+             vector\n\
+             end\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack, calc.stack);
+    }
+
+    #[test]
+    fn cli_vector_broadcasting() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[1 2 3] [4 5 6] +");
+        assert_eq!(top(&calc), "[5 7 9]");
+        process_command(&mut calc, "2 *");
+        assert_eq!(top(&calc), "[10 14 18]");
+        process_command(&mut calc, "c 1 [1 2 4] /");
+        assert_eq!(top(&calc), "[1 0.5 0.25]");
+        process_command(&mut calc, "c [1 2 3] 2 ^ neg");
+        assert_eq!(top(&calc), "[-1 -4 -9]");
+        process_command(&mut calc, "c [4 9] sqrt [1 1] -");
+        assert_eq!(top(&calc), "[1 2]");
+
+        let cmds = cli::CliCmd::tokenize("+ / sqrt ^");
+        process_command(&mut calc, "c [1 2] [1 2 3]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::DimensionMismatch)
+        );
+        process_command(&mut calc, "c [1 2] [1 0]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[1]),
+            Err(error::CalcError::ZeroDivision)
+        );
+        process_command(&mut calc, "c [1 -2]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[2]),
+            Err(error::CalcError::NegativeSquareRoot)
+        );
+        process_command(&mut calc, "c [0 1] 0");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[3]),
+            Err(error::CalcError::ZeroPowerZero)
+        );
+        process_command(&mut calc, "c [1 2] 'x");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::WrongType)
+        );
+        assert_eq!(calc.stack.len(), 2);
+    }
+
+    #[test]
+    fn cli_vector_commands() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[1 2 3] [4 5 6] dot");
+        assert_eq!(calc.stack, [32.0]);
+        process_command(&mut calc, "c [1 0 0] [0 1 0] cross");
+        assert_eq!(top(&calc), "[0 0 1]");
+        process_command(&mut calc, "c [3 4] norm");
+        assert_eq!(calc.stack, [5.0]);
+        process_command(&mut calc, "c [3 4 5] len");
+        assert_eq!(calc.stack, [3.0]);
+        process_command(&mut calc, "c [3 4 5] sum [3 4 5] prod [] sum [] prod");
+        assert_eq!(calc.stack, [12.0, 60.0, 0.0, 1.0]);
+        process_command(&mut calc, "c [7 8 9] 3 get");
+        assert_eq!(calc.stack, [9.0]);
+
+        process_command(&mut calc, "c 10 1 2 3 3 ->list");
+        assert_eq!(format!("{:?}", calc.stack), "[10.0, [1.0 2.0 3.0]]");
+        process_command(&mut calc, "0 ->list drop list->");
+        assert_eq!(calc.stack, [10.0, 1.0, 2.0, 3.0]);
+
+        let cmds = cli::CliCmd::tokenize("get cross dot ->list");
+        for index in ["0", "4", "1.5"] {
+            process_command(&mut calc, &format!("c [7 8 9] {}", index));
+            assert_eq!(
+                calculator::process(&mut calc, &cmds[0]),
+                Err(error::CalcError::IndexOutOfRange)
+            );
+        }
+        process_command(&mut calc, "c [1 2] [3 4]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[1]),
+            Err(error::CalcError::DimensionMismatch)
+        );
+        process_command(&mut calc, "c [1 2] 3");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[2]),
+            Err(error::CalcError::WrongType)
+        );
+        process_command(&mut calc, "c 1 'x 2 3");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[3]),
+            Err(error::CalcError::WrongType)
+        );
+        process_command(&mut calc, "c 1 2 3");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[3]),
+            Err(error::CalcError::StackUnderflow)
+        );
+        for n in ["1.5", "-1"] {
+            process_command(&mut calc, &format!("c 1 2 {}", n));
+            assert_eq!(
+                calculator::process(&mut calc, &cmds[3]),
+                Err(error::CalcError::OutOfDomain("->list"))
+            );
+        }
+    }
+
+    #[test]
+    fn cli_vector_exprs() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "exprs on 'x 1 2 2 ->list 2 *");
+        assert_eq!(exprs(&calc), ["x", "[1 2]*2"]);
+        process_command(&mut calc, "list->");
+        assert_eq!(exprs(&calc), ["x", "2", "4"]);
+        process_command(&mut calc, "[1 2 3] len");
+        assert_eq!(exprs(&calc), ["x", "2", "4", "3"]);
+    }
+
+    // The top of the stack with the elements rounded to 1e-9
+    fn rounded_top(calc: &RpnCalc) -> String {
+        let round = |n: f64| (n * 1e9).round() / 1e9 + 0.0;
+        match calc.stack.last().unwrap() {
+            value::Value::Number(n) => round(*n).to_string(),
+            value::Value::Vector(v) => {
+                value::Value::Vector(v.iter().map(|n| round(*n)).collect()).to_string()
+            }
+            value::Value::Matrix(m) => {
+                let rows: Vec<Vec<f64>> = (0..m.rows())
+                    .map(|i| m.row(i).iter().map(|n| round(*n)).collect())
+                    .collect();
+                matrix::Matrix::from_rows(&rows).unwrap().to_string()
+            }
+            value => value.to_string(),
+        }
+    }
+
+    #[test]
+    fn cli_matrix_arithmetic() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[[1 2][3 4]] [[5 6][7 8]] +");
+        assert_eq!(top(&calc), "[[6 8][10 12]]");
+        process_command(&mut calc, "[[1 0][0 1]] - 2 /");
+        assert_eq!(top(&calc), "[[2.5 4][5 5.5]]");
+        process_command(&mut calc, "c [[1 2][3 4]] [[5 6][7 8]] *");
+        assert_eq!(top(&calc), "[[19 22][43 50]]");
+        process_command(&mut calc, "c [[1 2 3][4 5 6]] [1 1 1] *");
+        assert_eq!(top(&calc), "[6 15]");
+        process_command(&mut calc, "c [1 1] [[1 2 3][4 5 6]] * 2 * neg");
+        assert_eq!(top(&calc), "[-10 -14 -18]");
+        process_command(&mut calc, "c [[1 2 3][4 5 6]] trn");
+        assert_eq!(top(&calc), "[[1 4][2 5][3 6]]");
+        process_command(&mut calc, "c 3 identity");
+        assert_eq!(top(&calc), "[[1 0 0][0 1 0][0 0 1]]");
+
+        let cmds = cli::CliCmd::tokenize("* + sqrt / det");
+        let failures = [
+            (
+                "[[1 2 3][4 5 6]] [[1 2][3 4]]",
+                0,
+                error::CalcError::DimensionMismatch,
+            ),
+            (
+                "[[1 2 3][4 5 6]] [1 1]",
+                0,
+                error::CalcError::DimensionMismatch,
+            ),
+            (
+                "[[1 2]] [[1 2][3 4]]",
+                1,
+                error::CalcError::DimensionMismatch,
+            ),
+            ("[[1 2]] [1 2]", 1, error::CalcError::WrongType),
+            ("[[1 4]]", 2, error::CalcError::WrongType),
+            ("1 [[1 4]]", 3, error::CalcError::WrongType),
+            ("[[1 2]] 0", 3, error::CalcError::ZeroDivision),
+            ("[[1 2 3][4 5 6]]", 4, error::CalcError::DimensionMismatch),
+            ("[1 2]", 4, error::CalcError::WrongType),
+        ];
+        for (stack, i, e) in failures {
+            process_command(&mut calc, &format!("c {}", stack));
+            let len = calc.stack.len();
+            assert_eq!(
+                calculator::process(&mut calc, &cmds[i]),
+                Err(e),
+                "{}",
+                stack
+            );
+            assert_eq!(calc.stack.len(), len);
+        }
+
+        let syntax = |line: &str| match &cli::CliCmd::tokenize(line)[0].oper {
+            cli::CliOperation::SyntaxError(e) => e.clone(),
+            oper => panic!("Not an error: {:?}", oper),
+        };
+        assert_eq!(
+            syntax("[[1 2][3]]"),
+            "Matrix rows must have the same number of elements"
+        );
+        assert_eq!(syntax("[[1 2] 3]"), "Invalid matrix element '3'");
+        assert_eq!(syntax("[[1 x]]"), "Invalid vector element 'x'");
+        for n in ["0", "-2", "1.5"] {
+            process_command(&mut calc, &format!("c {}", n));
+            assert_eq!(
+                calculator::process(&mut calc, &cli::CliCmd::tokenize("identity")[0]),
+                Err(error::CalcError::OutOfDomain("identity"))
+            );
+        }
+    }
+
+    #[test]
+    fn cli_linear_algebra() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[[1 2][3 4]] det");
+        assert_eq!(rounded_top(&calc), "-2");
+        process_command(&mut calc, "c [[2 0 1][1 3 2][1 1 2]] det");
+        assert_eq!(rounded_top(&calc), "6");
+        process_command(&mut calc, "c [[1 2][2 4]] det");
+        assert_eq!(rounded_top(&calc), "0");
+
+        process_command(&mut calc, "c [[4 7][2 6]] inv");
+        assert_eq!(rounded_top(&calc), "[[0.6 -0.7][-0.2 0.4]]");
+        process_command(&mut calc, "[[4 7][2 6]] *");
+        assert_eq!(rounded_top(&calc), "[[1 0][0 1]]");
+
+        process_command(
+            &mut calc,
+            "c [[1 2][3 4]] rank [[1 2][2 4]] rank [[1 2 3][2 4 6][1 0 0]] rank",
+        );
+        assert_eq!(calc.stack, [2.0, 1.0, 2.0]);
+
+        process_command(&mut calc, "c [[2 1 -1][-3 -1 2][-2 1 2]] [8 -11 -3] solve");
+        assert_eq!(rounded_top(&calc), "[2 3 -1]");
+        process_command(&mut calc, "c [[2 0][0 4]] [[2 4][4 8]] solve");
+        assert_eq!(rounded_top(&calc), "[[1 2][1 2]]");
+
+        let cmds = cli::CliCmd::tokenize("solve inv");
+        process_command(&mut calc, "c [[1 2][2 4]] [1 2]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::SingularMatrix)
+        );
+        process_command(&mut calc, "c [[1 2][3 4]] [1 2 3]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::DimensionMismatch)
+        );
+        process_command(&mut calc, "c [[1 2][2 4]]");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[1]),
+            Err(error::CalcError::SingularMatrix)
+        );
+    }
+
+    #[test]
+    fn cli_matrix_decompositions() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[[1 2][3 4]] lu");
+        assert_eq!(
+            format!("{:?}", calc.stack),
+            "[[[1.0 0.0][0.3333333333333333 1.0]], \
+             [[3.0 4.0][0.0 0.6666666666666667]], \
+             [[0.0 1.0][1.0 0.0]]]"
+        );
+        // P*A = L*U
+        process_command(&mut calc, "[[1 2][3 4]] * swap drop swap drop");
+        assert_eq!(rounded_top(&calc), "[[3 4][1 2]]");
+        process_command(&mut calc, "c [[1 2][3 4]] lu drop *");
+        assert_eq!(rounded_top(&calc), "[[3 4][1 2]]");
+
+        // A = Q*R, Q orthogonal and R upper triangular
+        process_command(&mut calc, "c [[3 1][4 2][0 5]] qr *");
+        assert_eq!(rounded_top(&calc), "[[3 1][4 2][0 5]]");
+        process_command(&mut calc, "c [[3 1][4 2][0 5]] qr drop dup trn *");
+        assert_eq!(rounded_top(&calc), "[[1 0 0][0 1 0][0 0 1]]");
+        process_command(&mut calc, "c [[3 1][4 2][0 5]] qr swap drop");
+        let value::Value::Matrix(r) = calc.stack.last().unwrap() else {
+            panic!("Not a matrix");
+        };
+        assert_eq!((r.row(1)[0], r.row(2)[0], r.row(2)[1]), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn session_matrices() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "[[1 -0.5][inf 4]]");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             matrix [[1.0 -0.5][inf 4.0]]\n\
+             end\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack, calc.stack);
+    }
+
+    #[test]
+    fn cli_date_arithmetic() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "2026-10-18 90d +");
+        assert_eq!(top(&calc), "2027-01-16");
+        process_command(&mut calc, "c 2026-12-25 2026-10-18 -");
+        assert_eq!(top(&calc), "68d");
+        process_command(&mut calc, "c 12h30m 2026-10-18 +");
+        assert_eq!(top(&calc), "2026-10-18T12:30:00");
+        process_command(&mut calc, "1w -");
+        assert_eq!(top(&calc), "2026-10-11T12:30:00");
+        process_command(&mut calc, "c 2026-10-18T08:15 2026-10-17T20:00:30 -");
+        assert_eq!(top(&calc), "12h14m30s");
+
+        process_command(&mut calc, "c 12h30m 2 *");
+        assert_eq!(top(&calc), "1d1h");
+        process_command(&mut calc, "c 1d 4 / neg");
+        assert_eq!(top(&calc), "-6h");
+        process_command(&mut calc, "c 1d 12h - 90s 0.5 * +");
+        assert_eq!(top(&calc), "12h45s");
+        process_command(&mut calc, "c 1d 12h /");
+        assert_eq!(calc.stack, [2.0]);
+        process_command(&mut calc, "c 2w 1m 0s - -");
+        assert_eq!(top(&calc), "13d23h59m");
+
+        let cmds = cli::CliCmd::tokenize("+ / *");
+        let failures = [
+            ("9999-12-31 1d", 0, error::CalcError::DateOutOfRange),
+            ("2026-10-18 1", 0, error::CalcError::WrongType),
+            ("2026-10-18 2026-10-18", 0, error::CalcError::WrongType),
+            ("1d 0", 1, error::CalcError::ZeroDivision),
+            ("1d 0s", 1, error::CalcError::ZeroDivision),
+            ("2026-10-18 2", 2, error::CalcError::WrongType),
+        ];
+        for (stack, i, e) in failures {
+            process_command(&mut calc, &format!("c {}", stack));
+            assert_eq!(
+                calculator::process(&mut calc, &cmds[i]),
+                Err(e),
+                "{}",
+                stack
+            );
+            assert_eq!(calc.stack.len(), 2);
+        }
+        for invalid in [
+            "2026-02-29",
+            "2026-13-01",
+            "2026-1-01",
+            "2026-10-18T24:00",
+            "1d1w",
+            "3x",
+        ] {
+            assert_eq!(
+                cli::CliCmd::tokenize(invalid)[0].oper,
+                cli::CliOperation::Unknown,
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn cli_date_commands() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "2026-10-18 dow 2026-10-19T23:59 dow");
+        assert_eq!(calc.stack, [7.0, 1.0]);
+
+        process_command(&mut calc, "c 2026-10-16 1 addbd");
+        assert_eq!(top(&calc), "2026-10-19");
+        process_command(&mut calc, "c 2026-10-18 5 addbd");
+        assert_eq!(top(&calc), "2026-10-23");
+        process_command(&mut calc, "c 2026-10-17 10 addbd");
+        assert_eq!(top(&calc), "2026-10-30");
+        process_command(&mut calc, "c 2026-10-19 -1 addbd");
+        assert_eq!(top(&calc), "2026-10-16");
+        process_command(&mut calc, "c 2026-10-19 -6 addbd");
+        assert_eq!(top(&calc), "2026-10-09");
+        process_command(&mut calc, "c 2026-10-18 0 addbd");
+        assert_eq!(top(&calc), "2026-10-18");
+
+        process_command(
+            &mut calc,
+            "c 2026-10-16 2026-10-26 bdays 2026-10-26 2026-10-16 bdays",
+        );
+        assert_eq!(calc.stack, [6.0, -6.0]);
+        process_command(
+            &mut calc,
+            "c 2026-10-17 2026-10-19 bdays 2026-01-01 2027-01-01 bdays",
+        );
+        assert_eq!(calc.stack, [0.0, 261.0]);
+
+        process_command(&mut calc, "c 1970-01-02 ->unix 1700000000.5 unix->");
+        assert_eq!(
+            format!("{:?}", calc.stack),
+            "[86400.0, 2023-11-14T22:13:20]"
+        );
+        process_command(&mut calc, "c -1 unix-> 1969-12-31T23:59:59 ->unix");
+        assert_eq!(format!("{:?}", calc.stack), "[1969-12-31T23:59:59, -1.0]");
+
+        // Today at midnight, now being later in the day
+        process_command(&mut calc, "c today ->unix 86400 / now today - 1d /");
+        let (days, day) = (
+            calc.stack[0].number().unwrap(),
+            calc.stack[1].number().unwrap(),
+        );
+        assert_eq!(days.fract(), 0.0);
+        assert!((0.0..1.0).contains(&day));
+
+        let cmds = cli::CliCmd::tokenize("addbd unix-> dow");
+        process_command(&mut calc, "c 2026-10-18 1.5");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[0]),
+            Err(error::CalcError::OutOfDomain("addbd"))
+        );
+        process_command(&mut calc, "c 1e300");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[1]),
+            Err(error::CalcError::DateOutOfRange)
+        );
+        process_command(&mut calc, "c 3d");
+        assert_eq!(
+            calculator::process(&mut calc, &cmds[2]),
+            Err(error::CalcError::WrongType)
+        );
+    }
+
+    #[test]
+    fn session_dates() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "2026-10-18 2026-10-18T12:30 -1d2h30m 0s");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             date 2026-10-18\n\
+             date 2026-10-18T12:30:00\n\
+             duration -1d2h30m\n\
+             duration 0s\n\
+             end\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack, calc.stack);
+    }
+
+    #[test]
+    fn cli_precision_arithmetic() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "prec 30 2 sqrt");
+        assert_eq!(top(&calc), "1.41421356237309504880168872421");
+        process_command(&mut calc, "c 1 3 /");
+        assert_eq!(top(&calc), "0.333333333333333333333333333333");
+        process_command(&mut calc, "c 0.1 0.2 +");
+        assert_eq!(top(&calc), "0.3");
+        process_command(&mut calc, "c 2 64 ^");
+        assert_eq!(top(&calc), "18446744073709551616");
+        process_command(&mut calc, "c 1e-40 1 + 1 -");
+        assert_eq!(top(&calc), "0");
+
+        // Halves are rounded to even
+        process_command(&mut calc, "c prec 3 1.235 1 * 1.245 1 *");
+        assert_eq!(format!("{:?}", calc.stack), "[1.24, 1.24]");
+        process_command(&mut calc, "c 999.5 1 * 2 3 / neg");
+        assert_eq!(format!("{:?}", calc.stack), "[1000, -0.667]");
+
+        // Literals keep their digits past those of f64
+        process_command(&mut calc, "c prec 25 3.14159265358979323846264338327 1 *");
+        assert_eq!(top(&calc), "3.141592653589793238462643");
+        process_command(&mut calc, "c 1e400 1e-399 * 7e-400 /");
+        assert_eq!(top(&calc), "1.428571428571428571428571e400");
+        process_command(&mut calc, "c prec off 3.14159265358979323846264338327");
+        assert_eq!(calc.stack, [std::f64::consts::PI]);
+
+        // The f64 numbers are left as they were without the precision mode
+        process_command(&mut calc, "c 0.1 0.2 +");
+        assert_eq!(calc.stack, [0.30000000000000004]);
+        process_command(&mut calc, "prec 20");
+        assert_eq!(top(&calc), "0.30000000000000004");
+        process_command(&mut calc, "1 3 / prec off");
+        assert_eq!(calc.stack, [0.30000000000000004, 1.0 / 3.0]);
+
+        process_command(&mut calc, "c prec 20 1 0 / -4 sqrt 0 0 ^");
+        assert_eq!(calc.stack, [1.0, 0.0, -4.0, 0.0, 0.0]);
+        let cmds = cli::CliCmd::tokenize("prec 0 prec x prec");
+        assert!(cmds
+            .iter()
+            .all(|cmd| matches!(cmd.oper, cli::CliOperation::SyntaxError(_))));
+    }
+
+    #[test]
+    fn cli_precision_functions() {
+        let mut calc = RpnCalc::new();
+        let cases = [
+            ("1 exp", "2.718281828459045235360287471352662497757"),
+            ("100 exp", "2.688117141816135448412625551580013587361e43"),
+            ("2 ln", "0.6931471805599453094172321214581765680755"),
+            ("1 sin", "0.8414709848078965066525023216302989996226"),
+            ("1 tan", "1.557407724654902230506974807458360173087"),
+            ("1 atan 4 *", "3.141592653589793238462643383279502884197"),
+            ("0 acos", "1.570796326794896619231321691639751442099"),
+            ("0.5 asin 6 *", "3.141592653589793238462643383279502884197"),
+            ("2 0.5 ^", "1.414213562373095048801688724209698078570"),
+        ];
+        for (program, result) in cases {
+            process_command(&mut calc, &format!("c prec 40 {}", program));
+            assert_eq!(top(&calc), result.trim_end_matches('0'), "{}", program);
+        }
+        process_command(&mut calc, "c -1 ln 2 asin");
+        assert_eq!(calc.stack, [-1.0, 2.0]);
+    }
+
+    #[test]
+    fn session_precision() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "prec 20 2 sqrt");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\n\
+             mode exprs off\n\
+             mode prec 20\n\
+             big 1.4142135623730950488\n\
+             end\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.stack, calc.stack);
+        assert_eq!(restored.precision, Some(20));
+    }
+
+    #[test]
+    fn cli_angle_mode() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "deg 90 sin");
+        assert_eq!(top(&calc), "1");
+        process_command(&mut calc, "180 sin 90 cos 360 tan");
+        assert_eq!(
+            calc.stack[1..],
+            [
+                value::Value::Number(0.0),
+                value::Value::Number(0.0),
+                value::Value::Number(0.0)
+            ]
+        );
+        process_command(&mut calc, "clear 1 atan 0.5 asin");
+        assert_eq!(
+            calc.stack,
+            [
+                value::Value::Number(45.0),
+                value::Value::Number(30.000000000000004)
+            ]
+        );
+        process_command(&mut calc, "clear rad 0 cos");
+        assert_eq!(top(&calc), "1");
+        assert_eq!(calc.modes()[1], ("angle", "rad".to_string()));
+
+        process_command(&mut calc, "clear prec 30 deg 270 sin 30 cos");
+        assert_eq!(calc.stack[0].to_string(), "-1");
+        assert_eq!(top(&calc), "0.866025403784438646763723170753");
+    }
+
+    #[test]
+    fn session_angle_mode() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "deg 1");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\nmode exprs off\nmode angle deg\nstack 1.0\nend\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.angle, expr::AngleMode::Degrees);
+    }
+
+    #[test]
+    fn session_modes_and_breakpoints() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "tolerance 1e-6 maxevals 500 atomic on");
+        process_command(&mut calc, "{ dup * } 'sq def break sq");
+        let content = session::Session::from_calc(&calc).to_string();
+        assert_eq!(
+            content,
+            "rpn-calc session 2\nmode exprs off\nmode tolerance 1e-6\nmode maxevals 500\n\
+             mode atomic on\nword sq dup *\nbreak sq\nend\n"
+        );
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.tolerance, 1e-6);
+        assert_eq!(restored.max_evaluations, 500);
+        assert!(restored.atomic);
+        assert!(restored.debugger.breakpoints.contains("sq"));
+
+        // Version 1 files are still read, with the default modes
+        let mut restored = RpnCalc::new();
+        session::Session::parse("rpn-calc session 1\nstack 1\nend\n")
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.tolerance, integrate::DEFAULT_TOLERANCE);
+        assert!(!restored.atomic);
+        assert_eq!(top(&restored), "1");
+    }
+
+    #[test]
+    fn config_parse_errors() {
+        use config::{Config, ConfigError};
+
+        let invalid = |content: &str| Config::parse(content).unwrap_err().to_string();
+        assert_eq!(
+            invalid("# settings\nprompt $\nfoo 1\n"),
+            "line 3: Unknown setting 'foo'"
+        );
+        assert_eq!(
+            invalid("display"),
+            "line 1: Expected a value after 'display'"
+        );
+        assert_eq!(
+            invalid("\ndisplay numbers"),
+            "line 2: Expected 'values' or 'exprs' after 'display'"
+        );
+        assert_eq!(
+            invalid("angle grad"),
+            "line 1: Expected 'rad' or 'deg' after 'angle'"
+        );
+        assert_eq!(
+            invalid("number 0"),
+            format!(
+                "line 1: Expected 'f64' or a number of digits up to {} after 'number'",
+                cli::MAX_PRECISION
+            )
+        );
+        assert_eq!(
+            invalid("history -1"),
+            "line 1: Expected a number of lines after 'history'"
+        );
+        assert_eq!(
+            invalid("alias m"),
+            "line 1: Expected a name and a command after 'alias'"
+        );
+        assert_eq!(
+            invalid("atomic yes"),
+            "line 1: Expected 'on' or 'off' after 'atomic'"
+        );
+        assert_eq!(
+            invalid("unalias x y"),
+            "line 1: Expected a name after 'unalias'"
+        );
+        assert!(matches!(
+            Config::load(&temp_file("missing_config")),
+            Err(ConfigError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn config_apply() {
+        let config = config::Config::parse(
+            "prompt rpn>\n\
+             history 50\n\
+             display exprs\n\
+             angle deg\n\
+             number 30\n\
+             stack 1 2\n\
+             stack 'x\n\
+             alias zzmul *\n\
+             startup { dup * } 'cfgsq def\n\
+             startup defscript cfghalf(a) = a / 2\n",
+        )
+        .unwrap();
+        assert_eq!(config.prompt(), Some("rpn>"));
+        assert_eq!(config.history_size(), Some(50));
+
+        let mut calc = RpnCalc::new();
+        config.apply(&mut calc, false).unwrap();
+        assert_eq!(calc.stack.len(), 3);
+        assert_eq!(calc.precision, Some(30));
+        assert_eq!(calc.angle, expr::AngleMode::Degrees);
+        assert!(calc.exprs.is_some());
+        process_command(&mut calc, "drop zzmul cfgsq cfghalf");
+        assert_eq!(top(&calc), "2");
+
+        // A restored session keeps its modes and stack
+        let mut restored = RpnCalc::new();
+        process_command(&mut restored, "5");
+        config.apply(&mut restored, true).unwrap();
+        assert_eq!(restored.stack, [value::Value::Number(5.0)]);
+        assert_eq!(restored.precision, None);
+        process_command(&mut restored, "cfgsq");
+        assert_eq!(top(&restored), "25");
+
+        let failing = |content: &str| {
+            let config = config::Config::parse(content).unwrap();
+            config
+                .apply(&mut RpnCalc::new(), false)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            failing("stack 1\nstack 1 dup"),
+            "line 2: Expected values after 'stack', found 'dup'"
+        );
+        assert_eq!(
+            failing("alias cfgbad nothing"),
+            "line 1: Unknown command 'nothing'"
+        );
+        assert_eq!(
+            failing("alias dup +"),
+            "line 1: 'dup' is the name of a command"
+        );
+        assert_eq!(failing("unalias zzdiv"), "line 1: 'zzdiv' is not an alias");
+        assert_eq!(failing("\nstartup 1 0 /"), "line 2: Zero division");
+        assert_eq!(failing("startup foo"), "line 1: Unknown command");
+    }
+
+    #[test]
+    fn cli_aliases() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "alias dbl dup");
+        process_command(&mut calc, "3 dbl +");
+        assert_eq!(top(&calc), "6");
+        process_command(&mut calc, "alias ev infix");
+        process_command(&mut calc, "ev 1 + 2");
+        assert_eq!(top(&calc), "3");
+
+        // A built-in alias is taken while its command keeps another name
+        process_command(&mut calc, "clear alias help swap");
+        process_command(&mut calc, "1 2 help");
+        assert_eq!(
+            calc.stack,
+            [value::Value::Number(2.0), value::Value::Number(1.0)]
+        );
+        let aliases = calc.aliases.list();
+        assert!(aliases.contains(&("a".to_string(), "+".to_string())));
+        assert!(aliases.contains(&("help".to_string(), "swap".to_string())));
+        assert!(aliases.contains(&("dbl".to_string(), "dup".to_string())));
+        assert!(!aliases.contains(&("help".to_string(), "h".to_string())));
+
+        process_command(&mut calc, "unalias help");
+        assert_eq!(
+            cli::CliCmd::tokenize_with("help", &calc.aliases)[0].oper,
+            cli::CliOperation::Word("help".to_string())
+        );
+        assert!(!calc.aliases.list().iter().any(|(alias, _)| alias == "help"));
+        assert!(!calc.aliases.names().contains(&"help".to_string()));
+
+        // The aliases are those of the calculator only, and saved with it
+        let other = RpnCalc::new();
+        assert!(other.aliases.names().contains(&"help".to_string()));
+        assert_eq!(
+            cli::CliCmd::tokenize("help")[0].oper,
+            cli::CliOperation::builtin("help", cli::Argument::None)
+        );
+        let content = session::Session::from_calc(&calc).to_string();
+        assert!(content.contains("\nalias dbl dup\nalias ev infix\nunalias help\n"));
+        let mut restored = RpnCalc::new();
+        session::Session::parse(&content)
+            .unwrap()
+            .restore(&mut restored);
+        assert_eq!(restored.aliases, calc.aliases);
+        process_command(&mut restored, "2 dbl *");
+        assert_eq!(top(&restored), "4");
+
+        process_command(&mut calc, "alias help h");
+        assert!(calc
+            .aliases
+            .list()
+            .contains(&("help".to_string(), "h".to_string())));
+        assert_eq!(
+            cli::CliCmd::tokenize_with("help", &calc.aliases)[0].oper,
+            cli::CliOperation::builtin("help", cli::Argument::None)
+        );
+    }
+
+    #[test]
+    fn cli_alias_errors() {
+        let mut calc = RpnCalc::new();
+        let error = |calc: &mut RpnCalc, line: &str| {
+            let cmds = cli::CliCmd::tokenize(line);
+            let (last, first) = cmds.split_last().unwrap();
+            for cmd in first {
+                calculator::process(calc, cmd).unwrap();
+            }
+            calculator::process(calc, last).unwrap_err()
+        };
+        let syntax = |e: &str| error::CalcError::Syntax(e.to_string());
+        let reserved = |name: &str| error::CalcError::ReservedName(name.to_string());
+        assert_eq!(
+            error(&mut calc, "alias 2 dup"),
+            syntax("'2' is read as a number")
+        );
+        assert_eq!(
+            error(&mut calc, "alias 1e3 dup"),
+            syntax("'1e3' is read as a number")
+        );
+        assert_eq!(error(&mut calc, "alias sqrt dup"), reserved("sqrt"));
+        assert_eq!(
+            error(&mut calc, "alias Twice dup"),
+            syntax("Invalid alias name 'Twice'")
+        );
+        assert_eq!(
+            error(&mut calc, "alias two nothing"),
+            syntax("Unknown command 'nothing'")
+        );
+        assert_eq!(
+            error(&mut calc, "{ dup } 'wd def alias wd dup"),
+            reserved("wd")
+        );
+        assert_eq!(
+            error(&mut calc, "alias two"),
+            syntax("Expected a name and a command after 'alias'")
+        );
+        assert_eq!(
+            error(&mut calc, "unalias"),
+            syntax("Expected a name after 'unalias'")
+        );
+        assert_eq!(error(&mut calc, "unalias sqrt"), reserved("sqrt"));
+        assert_eq!(
+            error(&mut calc, "unalias nothing"),
+            syntax("'nothing' is not an alias")
+        );
+
+        // The alias of a word would hide it
+        process_command(&mut calc, "alias aliased drop");
+        assert_eq!(
+            error(&mut calc, "{ dup } 'aliased def"),
+            reserved("aliased")
+        );
+    }
+
+    #[test]
+    fn cli_command_spans() {
+        let spans: Vec<(usize, String)> =
+            cli::CliCmd::tokenize("1  { dup * } [1 2] tolerance 1e-6 é infix 1 + 2 ")
+                .into_iter()
+                .map(|cmd| {
+                    let span = cmd.span.unwrap();
+                    (span.column, span.text)
+                })
+                .collect();
+        let span = |column: usize, text: &str| (column, text.to_string());
+        assert_eq!(
+            spans,
+            [
+                span(0, "1"),
+                span(3, "{ dup * }"),
+                span(13, "[1 2]"),
+                span(19, "tolerance 1e-6"),
+                span(34, "é"),
+                span(36, "infix 1 + 2"),
+                span(36, "infix 1 + 2"),
+                span(36, "infix 1 + 2"),
+            ]
+        );
+        let cmds = cli::CliCmd::tokenize("prec");
+        assert_eq!(cmds[0].span.as_ref().unwrap().text, "prec");
+    }
+
+    #[test]
+    fn cli_atomic_lines() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "10 0 / 1");
+        assert_eq!(calc.stack.len(), 3);
+
+        process_command(&mut calc, "clear atomic on exprs on 1 2");
+        process_command(&mut calc, "+ 10 0 / 1");
+        assert_eq!(
+            calc.stack,
+            [value::Value::Number(1.0), value::Value::Number(2.0)]
+        );
+        assert_eq!(calc.exprs.as_ref().unwrap().len(), 2);
+
+        let cmds = cli::CliCmd::tokenize("3 4 foo +");
+        assert_eq!(
+            calculator::process_line(&mut calc, &cmds),
+            Err((2, error::CalcError::UnknownCommand))
+        );
+        assert_eq!(calc.stack.len(), 2);
+        process_command(&mut calc, "+ atomic off");
+        assert_eq!(top(&calc), "3");
+        assert!(!calc.atomic);
+    }
+
+    #[test]
+    fn cli_error_underline() {
+        let cmds = cli::CliCmd::tokenize("1 2 foo +");
+        let span = cmds[2].span.as_ref().unwrap();
+        assert_eq!(span.underline(), "1 2 foo +\n    ^^^");
+        let cmds = cli::CliCmd::tokenize("1\t2 sqrt  ");
+        let span = cmds[2].span.as_ref().unwrap();
+        assert_eq!(span.underline(), "1\t2 sqrt\n \t  ^^^^");
+        let cmds = cli::CliCmd::tokenize("1 { dup");
+        let span = cmds[1].span.as_ref().unwrap();
+        assert_eq!(span.underline(), "1 { dup\n  ^^^^^");
+
+        // Unknown commands are errors wherever they are
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "1 2 foo +");
+        assert_eq!(top(&calc), "3");
+        let mut cli = RpnCalc::cli();
+        cli.read_new_command(std::io::Cursor::new("1 2 q"));
+        assert!(!cli.keep_running());
+    }
+
+    fn debugger_keys(calc: &mut RpnCalc, keys: &str) -> std::rc::Rc<std::cell::RefCell<String>> {
+        let keys = std::rc::Rc::new(std::cell::RefCell::new(keys.to_string()));
+        let left = keys.clone();
+        calc.debugger.keys = Box::new(move || {
+            let mut keys = left.borrow_mut();
+            (!keys.is_empty()).then(|| editor::Key::Char(keys.remove(0)))
+        });
+        keys
+    }
+
+    #[test]
+    fn debugger_breakpoints() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ dup * } 'sq def break sq");
+        // Paused before sq, then after dup and *
+        let keys = debugger_keys(&mut calc, "ssc");
+        process_command(&mut calc, "3 sq 1 +");
+        assert_eq!(top(&calc), "10");
+        assert!(keys.borrow().is_empty());
+
+        let keys = debugger_keys(&mut calc, "q");
+        process_command(&mut calc, "clear 3 sq 1 +");
+        assert_eq!(calc.stack, [value::Value::Number(3.0)]);
+        assert!(keys.borrow().is_empty());
+
+        process_command(&mut calc, "unbreak sq");
+        let keys = debugger_keys(&mut calc, "s");
+        process_command(&mut calc, "sq");
+        assert_eq!(top(&calc), "9");
+        assert_eq!(*keys.borrow(), "s");
+
+        let error = |calc: &mut RpnCalc, line: &str| {
+            let cmds = cli::CliCmd::tokenize(line);
+            calculator::process(calc, &cmds[0]).unwrap_err()
+        };
+        assert_eq!(
+            error(&mut calc, "break dup"),
+            error::CalcError::ReservedName("dup".to_string())
+        );
+        assert_eq!(
+            error(&mut calc, "unbreak sq"),
+            error::CalcError::Syntax("No breakpoint on 'sq'".to_string())
+        );
+        assert_eq!(
+            error(&mut calc, "break"),
+            error::CalcError::Syntax("Expected a word after 'break'".to_string())
+        );
+    }
+
+    #[test]
+    fn debugger_step_and_trace() {
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "trace on step on");
+        // A pause after each command, stepping on with s and space
+        let keys = debugger_keys(&mut calc, "s  ");
+        process_command(&mut calc, "1 2 +");
+        assert_eq!(top(&calc), "3");
+        assert!(keys.borrow().is_empty());
+
+        // Continuing runs the rest of the line
+        let keys = debugger_keys(&mut calc, "c");
+        process_command(&mut calc, "4 5 * +");
+        assert_eq!(top(&calc), "23");
+        assert!(keys.borrow().is_empty());
+
+        // Stopping skips the rest of the line
+        let keys = debugger_keys(&mut calc, "sq");
+        process_command(&mut calc, "1 2 3");
+        assert_eq!(top(&calc), "2");
+        assert!(keys.borrow().is_empty());
+
+        process_command(&mut calc, "step off trace off");
+        let keys = debugger_keys(&mut calc, "s");
+        process_command(&mut calc, "dup");
+        assert_eq!(*keys.borrow(), "s");
+        assert!(!calc.debugger.is_on());
+    }
+
+    #[test]
+    fn observer_events() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl observer::Observer for Recorder {
+            fn before(&mut self, _: &RpnCalc, cmd: &cli::CliCmd) {
+                self.0.borrow_mut().push(format!("before {}", cmd.oper));
+            }
+
+            fn after(&mut self, calc: &RpnCalc, cmd: &cli::CliCmd) {
+                let depth = calc.stack().len();
+                self.0
+                    .borrow_mut()
+                    .push(format!("after {} {}", cmd.oper, depth));
+            }
+
+            fn pushed(&mut self, value: &value::Value) {
+                self.0.borrow_mut().push(format!("pushed {}", value));
+            }
+
+            fn popped(&mut self, value: &value::Value) {
+                self.0.borrow_mut().push(format!("popped {}", value));
+            }
+
+            fn failed(&mut self, _: &RpnCalc, cmd: &cli::CliCmd, e: &error::CalcError) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("failed {}: {}", cmd.oper, e));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut calc = RpnCalc::new();
+        process_command(&mut calc, "{ dup * } 'sq def");
+        calc.add_observer(Recorder(events.clone()));
+        let take = || events.borrow_mut().drain(..).collect::<Vec<_>>();
+
+        process_command(&mut calc, "1 2 swap -");
+        assert_eq!(
+            take(),
+            [
+                "before 1",
+                "pushed 1",
+                "after 1 1",
+                "before 2",
+                "pushed 2",
+                "after 2 2",
+                "before swap",
+                "popped 2",
+                "popped 1",
+                "pushed 2",
+                "pushed 1",
+                "after swap 2",
+                "before -",
+                "popped 1",
+                "popped 2",
+                "pushed 1",
+                "after - 1",
+            ]
+        );
+
+        // The commands run by the word are not seen
+        process_command(&mut calc, "3 sq foo");
+        assert_eq!(
+            take(),
+            [
+                "before 3",
+                "pushed 3",
+                "after 3 2",
+                "before sq",
+                "popped 3",
+                "pushed 9",
+                "after sq 2",
+                "before foo",
+                "failed foo: Unknown command",
+            ]
+        );
+
+        // Undoing a line pops its values
+        process_command(&mut calc, "atomic on");
+        take();
+        process_command(&mut calc, "4 0 /");
+        assert_eq!(
+            take()[6..],
+            [
+                "before /",
+                "failed /: Zero division",
+                "popped 0",
+                "popped 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn tui_keys() {
+        let mut calc = RpnCalc::new();
+        let mut tui = RpnCalc::tui(editor::LineEditor::default());
+        tui.set_size(10, 100);
+        let mut out = Vec::new();
+
+        // Enter on an empty line runs dup, an operator runs with the next key
+        // and Backspace drops the top. An operator followed by a digit or
+        // another operator is typed as the rest of the line.
+        let keys = "3\r\r* \r\x7f2 foo\r-3\r++\r\x04";
+        tui.run(&mut calc, std::io::Cursor::new(keys), &mut out)
+            .unwrap();
+        assert_eq!(calc.stack, [8.0]);
+
+        let screen = String::from_utf8(out).unwrap();
+        assert!(screen.contains("Error at 'foo', column 3: Unknown command"));
+        assert!(screen.contains(" 2: 9"));
+        assert!(screen.contains(" 1: 2"));
+        assert!(screen.contains(" 3: 9"));
+        assert!(screen.contains(" 1: -3"));
+        assert!(screen.contains(" 1: 8"));
+        assert!(screen.contains(" f64 | rad | values"));
+        assert!(calc.echo);
+    }
+
+    #[test]
+    fn tui_history() {
+        let mut calc = RpnCalc::new();
+        let mut tui = RpnCalc::tui(editor::LineEditor::default());
+        tui.set_size(10, 100);
+        tui.set_notation(cli::Notation::Infix);
+
+        // The text shown by the commands goes to the history, in the notation set
+        let mut out = Vec::new();
+        let keys = "(1 + 2) * 4\rrpn 1 + 2\r\x04";
+        tui.run(&mut calc, std::io::Cursor::new(keys), &mut out)
+            .unwrap();
+        assert_eq!(calc.stack, [12.0]);
+        let screen = String::from_utf8(out).unwrap();
+        assert!(screen.contains("│> rpn 1 + 2"));
+        assert!(screen.contains("│  1 2 +"));
+        assert_eq!(calc.output, Output::Stdout);
+
+        // cls clears it
+        let mut out = Vec::new();
+        let keys = "cls\r\x04";
+        tui.run(&mut calc, std::io::Cursor::new(keys), &mut out)
+            .unwrap();
+        let screen = String::from_utf8(out).unwrap();
+        let last = screen.rsplit("\x1B[?25l").next().unwrap();
+        assert!(!last.contains("rpn 1 + 2"));
+        assert!(!last.contains("\x1B[2J"));
+    }
+
+    #[test]
+    fn tui_debugger() {
+        let mut calc = RpnCalc::new();
+        let mut tui = RpnCalc::tui(editor::LineEditor::default());
+        tui.set_size(10, 100);
+        let keys = debugger_keys(&mut calc, "q");
+
+        // The pauses don't wait for keys, the line running on
+        let mut out = Vec::new();
+        let input = "{ dup * } 'sq def break sq\rstep on\r3 sq 1 +\r\x04";
+        tui.run(&mut calc, std::io::Cursor::new(input), &mut out)
+            .unwrap();
+        assert_eq!(calc.stack, [10.0]);
+        assert_eq!(keys.borrow().as_str(), "q");
+        let screen = String::from_utf8(out).unwrap();
+        assert!(screen.contains("│  3  [3]"));
+
+        // They wait again once the TUI is left
+        process_command(&mut calc, "step off c 3 sq");
+        assert_eq!(calc.stack, [3.0]);
+        assert!(keys.borrow().is_empty());
+    }
 }
@@ -0,0 +1,91 @@
+// Callbacks on the commands run by a calculator, for an application to follow
+// its stack without comparing it after each line, as a view of the stack or a
+// log of the operations would:
+//
+//   struct Log;
+//
+//   impl Observer for Log {
+//       fn after(&mut self, calc: &RpnCalc, cmd: &CliCmd) {
+//           println!("{} -> {:?}", cmd.oper, calc.stack().last());
+//       }
+//   }
+//
+//   calc.add_observer(Log);
+//
+// Only the commands of the lines are seen, not those run by the words and
+// programs they call. Every callback does nothing unless implemented.
+
+use super::cli::CliCmd;
+use super::error::CalcError;
+use super::value::Value;
+use super::RpnCalc;
+
+pub trait Observer {
+    // Before the command runs
+    fn before(&mut self, _calc: &RpnCalc, _cmd: &CliCmd) {}
+
+    // After the command ran, once its values were pushed and popped
+    fn after(&mut self, _calc: &RpnCalc, _cmd: &CliCmd) {}
+
+    // A value pushed to the stack, from the lowest level
+    fn pushed(&mut self, _value: &Value) {}
+
+    // A value popped from the stack, from the top, before the values pushed
+    fn popped(&mut self, _value: &Value) {}
+
+    // After the command failed, once the values it left were pushed and popped
+    fn failed(&mut self, _calc: &RpnCalc, _cmd: &CliCmd, _error: &CalcError) {}
+}
+
+// Runs the command with `run`, telling the observers about it
+pub(super) fn process<F>(c: &mut RpnCalc, cmd: &CliCmd, run: F) -> Result<(), CalcError>
+where
+    F: FnOnce(&mut RpnCalc) -> Result<(), CalcError>,
+{
+    // Without observers, the stack isn't copied to be compared
+    if c.observers.is_empty() {
+        return run(c);
+    }
+    notify(c, |o, c| o.before(c, cmd));
+    let before = c.stack.clone();
+    let result = run(c);
+    stack_changed(c, &before);
+    match &result {
+        Ok(()) => notify(c, |o, c| o.after(c, cmd)),
+        Err(e) => notify(c, |o, c| o.failed(c, cmd, e)),
+    }
+    result
+}
+
+// Tells the observers about the values popped from `before` and pushed since,
+// the levels below them being left as they were
+pub(super) fn stack_changed(c: &mut RpnCalc, before: &[Value]) {
+    if c.observers.is_empty() {
+        return;
+    }
+    let kept = before
+        .iter()
+        .zip(c.stack.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let after = c.stack[kept..].to_vec();
+    notify(c, |o, _| {
+        before[kept..]
+            .iter()
+            .rev()
+            .for_each(|value| o.popped(value));
+        after.iter().for_each(|value| o.pushed(value));
+    });
+}
+
+fn notify<F>(c: &mut RpnCalc, mut callback: F)
+where
+    F: FnMut(&mut dyn Observer, &RpnCalc),
+{
+    // Taken out for the calculator to be lent to them
+    let mut observers = std::mem::take(&mut c.observers);
+    for observer in observers.iter_mut() {
+        callback(observer.as_mut(), c);
+    }
+    c.observers = observers;
+}
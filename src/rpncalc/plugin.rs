@@ -0,0 +1,225 @@
+// Plugin file format, version 1:
+//
+//   rpn-calc plugin 1
+//   requires <version>
+//   word <name> <arity> <commands>
+//   help <text>
+//   ...
+//
+// Plugins are the files ending in `.rpn` of the plugins directory, loaded at
+// startup in the order of their names. Each `word` line adds a command running
+// the RPN commands, as a program does, on the stack holding at least `arity`
+// levels, its commands being checked when loaded. The `help` line following a
+// word describes it in the help.
+// `requires` is optional and gives the oldest version of rpn-calc able to run
+// the plugin, as in `requires 0.1.0`.
+// Blank lines and lines starting with '#' are ignored.
+// A plugin failing to load adds none of its words.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::calculator;
+use super::cli::{CliCmd, CliOperation};
+use super::error::CalcError;
+use super::registry::{self, Operation};
+use super::value::Program;
+use super::RpnCalc;
+
+pub const PLUGIN_VERSION: u32 = 1;
+
+const HEADER: &str = "rpn-calc plugin";
+
+// Extension of the plugin files
+const EXTENSION: &str = "rpn";
+
+#[derive(Debug, PartialEq)]
+pub enum PluginError {
+    Io(String),
+    MissingHeader,
+    UnsupportedVersion(String),
+    RequiresVersion(String),
+    InvalidLine(usize, String),
+    // A command of a word that can't be read, with its line
+    InvalidWord(usize, String),
+    Register(CalcError),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Io(e) => write!(f, "{}", e),
+            PluginError::MissingHeader => write!(f, "Not a plugin file"),
+            PluginError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported plugin file version '{}'", v)
+            }
+            PluginError::RequiresVersion(v) => write!(
+                f,
+                "Requires rpn-calc {} or later, this is {}",
+                v,
+                env!("CARGO_PKG_VERSION")
+            ),
+            PluginError::InvalidLine(n, line) => write!(f, "Invalid line {}: '{}'", n, line),
+            PluginError::InvalidWord(n, e) => write!(f, "Invalid word at line {}: {}", n, e),
+            PluginError::Register(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// A command defined by a plugin
+#[derive(Debug, PartialEq)]
+struct PluginWord {
+    name: String,
+    arity: usize,
+    help: String,
+    program: Program,
+}
+
+impl Operation for PluginWord {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn help(&self) -> &str {
+        &self.help
+    }
+
+    fn execute(&self, calc: &mut RpnCalc) -> Result<(), CalcError> {
+        // The top of the stack is printed once the operation returns
+        let echo = std::mem::replace(&mut calc.echo, false);
+        let result = calculator::run(calc, &self.program);
+        calc.echo = echo;
+        result
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Plugin {
+    words: Vec<PluginWord>,
+}
+
+impl Plugin {
+    pub fn parse(content: &str) -> Result<Plugin, PluginError> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => {
+                let version = line[HEADER.len()..].trim();
+                if version != PLUGIN_VERSION.to_string() {
+                    return Err(PluginError::UnsupportedVersion(version.to_string()));
+                }
+            }
+            _ => return Err(PluginError::MissingHeader),
+        }
+
+        let mut plugin = Plugin { words: vec![] };
+        for (n, line) in lines {
+            let invalid = || PluginError::InvalidLine(n, line.to_string());
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "requires" => {
+                    let required = parse_version(value).ok_or_else(invalid)?;
+                    if parse_version(env!("CARGO_PKG_VERSION")) < Some(required) {
+                        return Err(PluginError::RequiresVersion(value.trim().to_string()));
+                    }
+                }
+                "word" => {
+                    let mut fields = value.trim().splitn(3, ' ');
+                    let name = fields.next().ok_or_else(invalid)?;
+                    let arity = fields.next().and_then(|a| a.parse().ok());
+                    let arity = arity.ok_or_else(invalid)?;
+                    let source = fields.next().unwrap_or("");
+                    check_commands(source).map_err(|e| PluginError::InvalidWord(n, e))?;
+                    plugin.words.push(PluginWord {
+                        name: name.to_string(),
+                        arity,
+                        help: String::new(),
+                        program: Program::new(source),
+                    });
+                }
+                "help" => match plugin.words.last_mut() {
+                    Some(word) if word.help.is_empty() => word.help = value.trim().to_string(),
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(plugin)
+    }
+
+    pub fn load(path: &Path) -> Result<Plugin, PluginError> {
+        let content = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        Plugin::parse(&content)
+    }
+
+    // Adds the words to the commands, all of them or none
+    pub fn register(self) -> Result<usize, PluginError> {
+        let count = self.words.len();
+        let words = self.words.into_iter();
+        registry::register_all(words.map(|w| Arc::new(w) as Arc<dyn Operation>).collect())
+            .map_err(PluginError::Register)?;
+        Ok(count)
+    }
+}
+
+// The words called may be defined later, only the commands that can never run
+// are errors
+fn check_commands(source: &str) -> Result<(), String> {
+    for cmd in CliCmd::tokenize(source) {
+        match cmd.oper {
+            CliOperation::SyntaxError(e) => return Err(e),
+            CliOperation::Unknown => {
+                let name = cmd.span.map(|span| span.text).unwrap_or_default();
+                return Err(format!("Unknown command '{}'", name));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Loads and registers the plugins of `dir`, giving the number of words added
+// by each, or why it failed. There are none when `dir` doesn't exist.
+pub fn load_plugins(dir: &Path) -> Vec<(PathBuf, Result<usize, PluginError>)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let result = Plugin::load(&path).and_then(Plugin::register);
+            (path, result)
+        })
+        .collect()
+}
+
+// Major, minor and patch numbers of a version like 0.1.0
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut numbers = version.trim().split('.').map(|n| n.parse().ok());
+    let version = (numbers.next()??, numbers.next()??, numbers.next()??);
+    numbers.next().is_none().then_some(version)
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> PluginError {
+    PluginError::Io(format!("{}: {}", path.display(), e))
+}
+
+// $XDG_CONFIG_HOME/rpn-calc/plugins, falling back to ~/.config
+pub fn default_plugins_dir() -> Option<PathBuf> {
+    Some(super::config_dir()?.join("plugins"))
+}
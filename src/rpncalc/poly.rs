@@ -0,0 +1,234 @@
+// Polynomials as their coefficients, from the highest degree to the constant:
+// [1 -3 2] is x^2 - 3*x + 2.
+
+use std::f64::consts::PI;
+
+use super::error::CalcError;
+
+// Without the leading zero coefficients, the zero polynomial being []
+pub fn trim(p: &[f64]) -> Vec<f64> {
+    let start = p.iter().position(|&c| c != 0.0).unwrap_or(p.len());
+    p[start..].to_vec()
+}
+
+pub fn eval(p: &[f64], x: f64) -> f64 {
+    p.iter().fold(0.0, |acc, &c| acc * x + c)
+}
+
+pub fn add(p: &[f64], q: &[f64]) -> Vec<f64> {
+    let len = p.len().max(q.len());
+    let coefficient = |p: &[f64], i: usize| {
+        let shift = len - p.len();
+        if i < shift {
+            0.0
+        } else {
+            p[i - shift]
+        }
+    };
+    trim(
+        &(0..len)
+            .map(|i| coefficient(p, i) + coefficient(q, i))
+            .collect::<Vec<_>>(),
+    )
+}
+
+pub fn negate(p: &[f64]) -> Vec<f64> {
+    p.iter().map(|c| -c).collect()
+}
+
+pub fn mul(p: &[f64], q: &[f64]) -> Vec<f64> {
+    if p.is_empty() || q.is_empty() {
+        return vec![];
+    }
+    let mut product = vec![0.0; p.len() + q.len() - 1];
+    for (i, a) in p.iter().enumerate() {
+        for (j, b) in q.iter().enumerate() {
+            product[i + j] += a * b;
+        }
+    }
+    trim(&product)
+}
+
+// Quotient and remainder of the long division, None dividing by zero
+pub fn div(p: &[f64], q: &[f64]) -> Option<(Vec<f64>, Vec<f64>)> {
+    let (p, q) = (trim(p), trim(q));
+    if q.is_empty() {
+        return None;
+    }
+    if p.len() < q.len() {
+        return Some((vec![], p));
+    }
+
+    let mut remainder = p.clone();
+    let mut quotient = vec![0.0; p.len() - q.len() + 1];
+    for i in 0..quotient.len() {
+        let factor = remainder[i] / q[0];
+        quotient[i] = factor;
+        for (j, c) in q.iter().enumerate() {
+            remainder[i + j] -= factor * c;
+        }
+    }
+    // The leading coefficients are cancelled, exactly in theory
+    let remainder = trim(&remainder[quotient.len()..]);
+    Some((trim(&quotient), remainder))
+}
+
+pub fn derivative(p: &[f64]) -> Vec<f64> {
+    let degree = p.len().saturating_sub(1);
+    trim(
+        &p[..degree]
+            .iter()
+            .enumerate()
+            .map(|(i, c)| c * (degree - i) as f64)
+            .collect::<Vec<_>>(),
+    )
+}
+
+// Every root as (real, imaginary), in decreasing order of the real parts.
+// The zero polynomial, whose roots are every number, is out of the domain.
+pub fn roots(p: &[f64]) -> Result<Vec<(f64, f64)>, CalcError> {
+    let p = trim(p);
+    if p.is_empty() {
+        return Err(CalcError::OutOfDomain("roots"));
+    }
+    let mut roots = match p.len() - 1 {
+        0 => vec![],
+        1 => vec![(-p[1] / p[0], 0.0)],
+        2 => quadratic(p[0], p[1], p[2]),
+        3 => cubic(p[1] / p[0], p[2] / p[0], p[3] / p[0]),
+        _ => durand_kerner(&p).ok_or(CalcError::NoConvergence)?,
+    };
+    roots.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.total_cmp(&a.1)));
+    Ok(roots)
+}
+
+// a*x^2 + b*x + c, without the cancellation of -b + sqrt(b^2 - 4*a*c), nor
+// the overflow of b^2 with the coefficients scaled to at most 1
+fn quadratic(a: f64, b: f64, c: f64) -> Vec<(f64, f64)> {
+    let scale = a.abs().max(b.abs()).max(c.abs());
+    let (a, b, c) = (a / scale, b / scale, c / scale);
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        let (re, im) = (-b / (2.0 * a), (-discriminant).sqrt() / (2.0 * a));
+        return vec![(re, im.abs()), (re, -im.abs())];
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    if q == 0.0 {
+        return vec![(0.0, 0.0), (0.0, 0.0)];
+    }
+    vec![(q / a, 0.0), (c / q, 0.0)]
+}
+
+// x^3 + a*x^2 + b*x + c, trigonometric form with three real roots and
+// Cardano's formula otherwise
+fn cubic(a: f64, b: f64, c: f64) -> Vec<(f64, f64)> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let shift = a / 3.0;
+
+    if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).acos();
+        let scale = -2.0 * q.sqrt();
+        return (0..3)
+            .map(|k| {
+                let angle = (theta + 2.0 * PI * k as f64) / 3.0;
+                (scale * angle.cos() - shift, 0.0)
+            })
+            .collect();
+    }
+
+    let big_a = -(r.abs() + (r * r - q * q * q).sqrt()).cbrt().copysign(r);
+    let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+    let re = -0.5 * (big_a + big_b) - shift;
+    let im = (0.5 * 3f64.sqrt() * (big_a - big_b)).abs();
+    vec![(big_a + big_b - shift, 0.0), (re, im), (re, -im)]
+}
+
+#[derive(Clone, Copy)]
+struct Complex(f64, f64);
+
+impl Complex {
+    fn add(self, o: Complex) -> Complex {
+        Complex(self.0 + o.0, self.1 + o.1)
+    }
+
+    fn sub(self, o: Complex) -> Complex {
+        Complex(self.0 - o.0, self.1 - o.1)
+    }
+
+    fn mul(self, o: Complex) -> Complex {
+        Complex(self.0 * o.0 - self.1 * o.1, self.0 * o.1 + self.1 * o.0)
+    }
+
+    fn div(self, o: Complex) -> Complex {
+        let norm = o.0 * o.0 + o.1 * o.1;
+        Complex(
+            (self.0 * o.0 + self.1 * o.1) / norm,
+            (self.1 * o.0 - self.0 * o.1) / norm,
+        )
+    }
+
+    fn abs(self) -> f64 {
+        self.0.hypot(self.1)
+    }
+}
+
+// Simultaneous Newton-like iteration on every root, starting from points
+// spread on a circle enclosing the roots, None if it doesn't converge
+fn durand_kerner(p: &[f64]) -> Option<Vec<(f64, f64)>> {
+    let monic: Vec<f64> = p.iter().map(|c| c / p[0]).collect();
+    let degree = monic.len() - 1;
+    // Cauchy's bound on the modulus of the roots
+    let radius = 1.0 + monic[1..].iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    let mut roots: Vec<Complex> = (0..degree)
+        .map(|k| {
+            let angle = 2.0 * PI * k as f64 / degree as f64 + 0.4;
+            Complex(radius * angle.cos(), radius * angle.sin())
+        })
+        .collect();
+
+    let eval = |z: Complex| {
+        monic
+            .iter()
+            .fold(Complex(0.0, 0.0), |acc, &c| acc.mul(z).add(Complex(c, 0.0)))
+    };
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..degree {
+            let mut denominator = Complex(1.0, 0.0);
+            for (j, root) in roots.iter().enumerate() {
+                if j != i {
+                    denominator = denominator.mul(roots[i].sub(*root));
+                }
+            }
+            let step = eval(roots[i]).div(denominator);
+            roots[i] = roots[i].sub(step);
+            change = change.max(step.abs() / roots[i].abs().max(1.0));
+        }
+        if change < 1e-15 {
+            break;
+        }
+    }
+
+    // The steps to multiple roots stay larger, as their polynomial is flat
+    // there, so the values are checked to be zero within the rounding errors
+    let largest = monic.iter().fold(0.0f64, |m, c| m.max(c.abs()));
+    let magnitude = |z: Complex| largest * monic.iter().fold(0.0, |acc, _| acc * z.abs() + 1.0);
+    if !roots.iter().all(|&z| eval(z).abs() <= 1e-10 * magnitude(z)) {
+        return None;
+    }
+
+    // Imaginary parts within the rounding errors are zero
+    let roots = roots
+        .into_iter()
+        .map(|z| {
+            let im = if z.1.abs() < 1e-10 * z.0.abs().max(1.0) {
+                0.0
+            } else {
+                z.1
+            };
+            (z.0, im)
+        })
+        .collect();
+    Some(roots)
+}
@@ -0,0 +1,467 @@
+// Operations by name, from which the commands are read, run, listed in the
+// help and completed: the built-in commands, and the operations registered by
+// other crates. A crate adds one by implementing Operation, then registering
+// it before reading any command:
+//
+//   struct Hypot;
+//
+//   impl Operation for Hypot {
+//       fn name(&self) -> &str { "hypot" }
+//       fn arity(&self) -> usize { 2 }
+//       fn help(&self) -> &str { "Length of the hypotenuse of two sides" }
+//       fn execute(&self, calc: &mut RpnCalc) -> Result<(), CalcError> {
+//           let b = calc.pop().and_then(|b| b.number()).ok_or(CalcError::WrongType)?;
+//           let a = calc.pop().and_then(|a| a.number()).ok_or(CalcError::WrongType)?;
+//           calc.push(Value::Number(a.hypot(b)));
+//           Ok(())
+//       }
+//   }
+//
+//   registry::register(Hypot)?;
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::calculator::BUILTINS;
+use super::cli::{Argument, CliCmd, CliOperation};
+use super::error::CalcError;
+use super::expr::Expr;
+use super::RpnCalc;
+
+pub trait Operation: Send + Sync {
+    // Name typed to run the operation
+    fn name(&self) -> &str;
+
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
+    // Stack levels the operation takes, checked before it runs
+    fn arity(&self) -> usize;
+
+    // One line description for the help
+    fn help(&self) -> &str;
+
+    // Runs on the calculator, whose stack is restored if it fails
+    fn execute(&self, calc: &mut RpnCalc) -> Result<(), CalcError>;
+}
+
+type Run = fn(&mut RpnCalc) -> Result<(), CalcError>;
+type RunWith = fn(&mut RpnCalc, &Argument) -> Result<(), CalcError>;
+
+// How a built-in command reads its argument, then runs with it. The parsers
+// are given the name of the command for their errors, and those of the rest of
+// the line the aliases to read commands with.
+pub(super) enum Syntax {
+    Plain(Run),
+    // The next word, if any, as in 'tolerance 1e-6'
+    Argument(fn(&str, Option<&str>) -> Result<Argument, String>, RunWith),
+    // The rest of the line, as in 'defscript f(x) = x^2'
+    Line(
+        fn(&str, &str, &Aliases) -> Result<Argument, String>,
+        RunWith,
+    ),
+    // The rest of the line, read as the commands it stands for, as in
+    // 'infix 1 + 2'
+    Commands(fn(&str, &Aliases) -> Result<Vec<CliOperation>, String>),
+}
+
+// How the expressions of the stack follow a built-in command once it ran,
+// when they are tracked
+pub(super) enum Exprs {
+    // The stack is left as it was
+    Unchanged,
+    Unary(fn(Expr) -> Expr),
+    Binary(fn(Expr, Expr) -> Expr),
+    // Moves the expressions as the command does the levels
+    Stack(fn(&mut Vec<Expr>)),
+    // The top levels are that many results, their own expressions
+    Results(usize),
+    // That many levels are replaced by results, their own expressions
+    Replaced(usize),
+    // Every level starts a new expression
+    Reset,
+}
+
+pub struct Builtin {
+    // The name, then the aliases
+    names: &'static [&'static str],
+    // Written after the names in the help, as in 'save [file]'
+    pub(super) argument: &'static str,
+    arity: usize,
+    help: &'static str,
+    pub(super) syntax: Syntax,
+    pub(super) exprs: Exprs,
+}
+
+impl Builtin {
+    // Whether the command has that name, even if removed by the user
+    pub(super) fn has_name(&self, name: &str) -> bool {
+        self.names.contains(&name)
+    }
+
+    // Runs the command with the argument read after its name
+    pub(super) fn run(&self, calc: &mut RpnCalc, argument: &Argument) -> Result<(), CalcError> {
+        match self.syntax {
+            Syntax::Plain(run) => run(calc),
+            Syntax::Argument(_, run) | Syntax::Line(_, run) => run(calc, argument),
+            // Replaced by its commands when read
+            Syntax::Commands(_) => Err(CalcError::Syntax(format!(
+                "Expected {} after '{}'",
+                self.argument,
+                self.name()
+            ))),
+        }
+    }
+}
+
+// Built-in commands are told apart by their address
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Builtin) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Operation for Builtin {
+    fn name(&self) -> &str {
+        self.names[0]
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &self.names[1..]
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    // Runs without an argument, as if none followed its name
+    fn execute(&self, calc: &mut RpnCalc) -> Result<(), CalcError> {
+        let argument = match self.syntax {
+            Syntax::Argument(parse, _) => parse(self.name(), None),
+            Syntax::Line(parse, _) => parse(self.name(), "", &calc.aliases),
+            Syntax::Plain(_) | Syntax::Commands(_) => Ok(Argument::None),
+        };
+        self.run(calc, &argument.map_err(CalcError::Syntax)?)
+    }
+}
+
+pub(super) const fn plain(
+    names: &'static [&'static str],
+    arity: usize,
+    help: &'static str,
+    run: Run,
+    exprs: Exprs,
+) -> Builtin {
+    Builtin {
+        names,
+        argument: "",
+        arity,
+        help,
+        syntax: Syntax::Plain(run),
+        exprs,
+    }
+}
+
+pub(super) const fn with_argument(
+    names: &'static [&'static str],
+    argument: &'static str,
+    help: &'static str,
+    parse: fn(&str, Option<&str>) -> Result<Argument, String>,
+    run: RunWith,
+    exprs: Exprs,
+) -> Builtin {
+    Builtin {
+        names,
+        argument,
+        arity: 0,
+        help,
+        syntax: Syntax::Argument(parse, run),
+        exprs,
+    }
+}
+
+pub(super) const fn with_line(
+    names: &'static [&'static str],
+    argument: &'static str,
+    help: &'static str,
+    parse: fn(&str, &str, &Aliases) -> Result<Argument, String>,
+    run: RunWith,
+) -> Builtin {
+    Builtin {
+        names,
+        argument,
+        arity: 0,
+        help,
+        syntax: Syntax::Line(parse, run),
+        exprs: Exprs::Unchanged,
+    }
+}
+
+pub(super) const fn with_commands(
+    names: &'static [&'static str],
+    argument: &'static str,
+    help: &'static str,
+    parse: fn(&str, &Aliases) -> Result<Vec<CliOperation>, String>,
+) -> Builtin {
+    Builtin {
+        names,
+        argument,
+        arity: 0,
+        help,
+        syntax: Syntax::Commands(parse),
+        exprs: Exprs::Unchanged,
+    }
+}
+
+static REGISTERED: RwLock<Vec<Arc<dyn Operation>>> = RwLock::new(Vec::new());
+
+// The names of the commands of a calculator, changed by its user
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Aliases {
+    // Names given by the user to commands, with the name of the command
+    aliases: Vec<(String, String)>,
+    // Names of built-in commands removed by the user
+    removed: Vec<String>,
+}
+
+// The names of the commands none of which was changed, as read in the files
+pub(super) static DEFAULT_ALIASES: Aliases = Aliases::new();
+
+// Adds an operation to the commands, unless one of its names is taken or
+// can't be read as the name of a command
+pub fn register<O: Operation + 'static>(operation: O) -> Result<(), CalcError> {
+    register_all(vec![Arc::new(operation)])
+}
+
+// Adds all the operations, or none of them if one can't be added
+pub fn register_all(operations: Vec<Arc<dyn Operation>>) -> Result<(), CalcError> {
+    let mut names: Vec<&str> = vec![];
+    for op in operations.iter() {
+        for name in std::iter::once(op.name()).chain(op.aliases().iter().copied()) {
+            // Read as a single command named after no built-in
+            let valid = name.to_lowercase() == name
+                && matches!(
+                    &CliCmd::tokenize(name)[..],
+                    [CliCmd {
+                        oper: CliOperation::Word(_)
+                            | CliOperation::Registered(_)
+                            | CliOperation::Unknown,
+                        ..
+                    }]
+                );
+            if command(name).is_some() || names.contains(&name) {
+                return Err(CalcError::ReservedName(name.to_string()));
+            } else if !valid {
+                return Err(CalcError::Syntax(format!(
+                    "Invalid operation name '{}'",
+                    name
+                )));
+            }
+            names.push(name);
+        }
+    }
+    let mut registered = REGISTERED.write().unwrap();
+    for name in names {
+        let taken = |op: &Arc<dyn Operation>| op.name() == name || op.aliases().contains(&name);
+        if registered.iter().any(taken) {
+            return Err(CalcError::ReservedName(name.to_string()));
+        }
+    }
+    registered.extend(operations);
+    Ok(())
+}
+
+impl Aliases {
+    pub const fn new() -> Aliases {
+        Aliases {
+            aliases: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    // The names given by the user with their commands, then the names removed
+    pub(super) fn parts(&self) -> (&[(String, String)], &[String]) {
+        (&self.aliases, &self.removed)
+    }
+
+    // Makes `name` run the command `target`, replacing the alias of that name.
+    // A name of a built-in command can be taken while the command keeps another.
+    pub fn set(&mut self, name: &str, target: &str) -> Result<(), CalcError> {
+        let target = target.to_lowercase();
+        let command = match (self.builtin(&target), self.registered(&target)) {
+            (Some(builtin), _) => builtin.name().to_string(),
+            (None, Some(operation)) => operation.name().to_string(),
+            (None, None) => return Err(CalcError::Syntax(format!("Unknown command '{}'", target))),
+        };
+        if !self.is_alias(name) {
+            self.check_name(name)?;
+        }
+        self.removed.retain(|removed| removed != name);
+        self.aliases.retain(|(alias, _)| alias != name);
+        self.aliases.push((name.to_string(), command));
+        Ok(())
+    }
+
+    // Makes `name` run no command, be it an alias given by the user or a name
+    // of a built-in command having another
+    pub fn remove(&mut self, name: &str) -> Result<(), CalcError> {
+        let aliased = self.is_alias(name);
+        let removed = self.removed.iter().any(|r| r == name);
+        match command(name) {
+            // As a restored session may have done before the configuration
+            Some(_) if removed => {}
+            Some(builtin) => {
+                if !aliased && self.names_of(builtin).len() < 2 {
+                    return Err(CalcError::ReservedName(name.to_string()));
+                }
+                self.removed.push(name.to_string());
+            }
+            _ if !aliased => return Err(CalcError::Syntax(format!("'{}' is not an alias", name))),
+            _ => {}
+        }
+        self.aliases.retain(|(alias, _)| alias != name);
+        Ok(())
+    }
+
+    // Every alias with the name of its command, the built-in ones first
+    pub fn list(&self) -> Vec<(String, String)> {
+        // Built-in commands are named by their first name left
+        let command_name = |b: &Builtin| self.names_of(b).first().copied().unwrap_or(b.names[0]);
+        let mut aliases: Vec<(String, String)> = vec![];
+        for b in BUILTINS {
+            let command = command_name(b);
+            for name in self.names_of(b).into_iter().filter(|name| *name != command) {
+                aliases.push((name.to_string(), command.to_string()));
+            }
+        }
+        for (alias, command) in self.aliases.iter() {
+            let command = match BUILTINS.iter().find(|b| b.name() == command) {
+                Some(b) => command_name(b).to_string(),
+                None => command.clone(),
+            };
+            aliases.push((alias.clone(), command));
+        }
+        aliases
+    }
+
+    fn is_alias(&self, name: &str) -> bool {
+        self.aliases.iter().any(|(alias, _)| alias == name)
+    }
+
+    // Whether `name` can be given to a command, it being no alias already
+    fn check_name(&self, name: &str) -> Result<(), CalcError> {
+        let tokens = CliCmd::tokenize_with(name, self);
+        match (&tokens[..], self.builtin(name)) {
+            (
+                [CliCmd {
+                    oper: CliOperation::Push(_) | CliOperation::PushDecimal(_),
+                    ..
+                }],
+                _,
+            ) => Err(CalcError::Syntax(format!("'{}' is read as a number", name))),
+            // The command keeps another name
+            (_, Some(builtin)) if self.names_of(builtin).len() > 1 => Ok(()),
+            (_, Some(_)) => Err(CalcError::ReservedName(name.to_string())),
+            _ if self.registered(name).is_some() => Err(CalcError::ReservedName(name.to_string())),
+            (
+                [CliCmd {
+                    oper: CliOperation::Word(_) | CliOperation::Unknown,
+                    ..
+                }],
+                _,
+            ) if name.to_lowercase() == name => Ok(()),
+            _ => Err(CalcError::Syntax(format!("Invalid alias name '{}'", name))),
+        }
+    }
+
+    // The names of a built-in command neither removed nor taken by an alias
+    fn names_of(&self, builtin: &Builtin) -> Vec<&'static str> {
+        builtin
+            .names
+            .iter()
+            .copied()
+            .filter(|name| !self.removed.iter().any(|r| r == name))
+            .filter(|name| !self.is_alias(name))
+            .collect()
+    }
+
+    // The name of the command aliased by `name`, if it is an alias
+    fn aliased(&self, name: &str) -> Option<&str> {
+        let (_, command) = self.aliases.iter().find(|(alias, _)| alias == name)?;
+        Some(command)
+    }
+
+    // The built-in command named `name`, in lowercase
+    pub(super) fn builtin(&self, name: &str) -> Option<&'static Builtin> {
+        match self.aliased(name) {
+            Some(command) => BUILTINS.iter().find(|b| b.name() == command),
+            None if self.removed.iter().any(|r| r == name) => None,
+            None => command(name),
+        }
+    }
+
+    // The registered operation named `name`, in lowercase
+    pub(super) fn registered(&self, name: &str) -> Option<Arc<dyn Operation>> {
+        match self.aliased(name) {
+            Some(command) => registered(command),
+            None => registered(name),
+        }
+    }
+
+    // Every name and alias, for completion
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILTINS
+            .iter()
+            .flat_map(|b| self.names_of(b).into_iter().map(|name| name.to_string()))
+            .collect();
+        for op in REGISTERED.read().unwrap().iter() {
+            names.push(op.name().to_string());
+            names.extend(op.aliases().iter().map(|alias| alias.to_string()));
+        }
+        names.extend(self.aliases.iter().map(|(alias, _)| alias.clone()));
+        names
+    }
+
+    // The names and arguments of each command with its description, the
+    // built-in commands first
+    pub fn help(&self) -> Vec<(String, String)> {
+        let usage = |names: Vec<&str>, argument: &str| {
+            let mut usage = names.join(" ");
+            if !argument.is_empty() {
+                usage = format!("{} {}", usage, argument);
+            }
+            usage
+        };
+        let mut help: Vec<(String, String)> = BUILTINS
+            .iter()
+            .map(|b| (usage(self.names_of(b), b.argument), b.help.to_string()))
+            .collect();
+        for op in REGISTERED.read().unwrap().iter() {
+            let names = std::iter::once(op.name()).chain(op.aliases().iter().copied());
+            help.push((usage(names.collect(), ""), op.help().to_string()));
+        }
+        help
+    }
+}
+
+// The built-in command having the name `name`, whatever the aliases
+pub(super) fn command(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.has_name(name))
+}
+
+// The registered operation having the name `name`, whatever the aliases
+pub(super) fn registered(name: &str) -> Option<Arc<dyn Operation>> {
+    let named = |op: &&Arc<dyn Operation>| op.name() == name || op.aliases().contains(&name);
+    REGISTERED.read().unwrap().iter().find(named).cloned()
+}
@@ -0,0 +1,565 @@
+// Scripts: words written in a small language for what is awkward in RPN,
+// defined with `defscript` followed by the rest of the line:
+//
+//   defscript hypot(a, b) = sqrt(a*a + b*b)
+//   defscript fact(n) { let p = 1; while n > 1 { p = p * n; n = n - 1 } return p }
+//
+// A script takes the numbers of the top levels of the stack as its
+// parameters, the last one being the top, and replaces them with the values
+// it returns, which may be none or several, as in `return a, b`.
+//
+// Statements, the ';' after them being optional:
+//   let x = <expr>         declares a variable
+//   x = <expr>             assigns a declared variable or parameter
+//   if <expr> { } else { } else being optional, also 'else if'
+//   while <expr> { }
+//   return <expr>, ...
+//
+// Expressions are on numbers, from the loosest to the tightest binding:
+//   ||  &&  == != < <= > >=  + -  * / %  unary - !  ^  f(a, ...)  (...)
+// Comparisons and logical operators give 1 or 0, any number but 0 being true.
+// The functions are the calculator ones, sqrt exp ln sin cos tan asin acos
+// atan, the angles being in the unit of the calculator angle mode, and abs
+// floor ceil round min max.
+//
+// Scripts can't reach anything but their numbers: there is no input, output
+// or file access, and a call is stopped after MAX_INSTRUCTIONS statements and
+// expressions so endless loops end. Blocks and expressions nest MAX_DEPTH
+// times at most, so parsing and running them can't overflow the stack.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::error::CalcError;
+use super::expr::{AngleMode, Function};
+
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Script {
+    // As written after 'defscript'
+    source: String,
+    name: String,
+    params: Vec<String>,
+    body: Vec<Statement>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Statement {
+    Let(String, Node),
+    Assign(String, Node),
+    If(Node, Vec<Statement>, Vec<Statement>),
+    While(Node, Vec<Statement>),
+    Return(Vec<Node>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "=", "(", ")",
+    "{", "}", ",", ";",
+];
+
+const KEYWORDS: &[&str] = &["let", "if", "else", "while", "return"];
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, String> {
+        let source = source.trim();
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let name = parser.name()?;
+        parser.expect("(")?;
+        let mut params: Vec<String> = vec![];
+        if !parser.accept(")") {
+            loop {
+                let param = parser.name()?;
+                if params.contains(&param) {
+                    return Err(format!("Parameter '{}' is repeated", param));
+                }
+                params.push(param);
+                if parser.accept(")") {
+                    break;
+                }
+                parser.expect(",")?;
+            }
+        }
+        let body = if parser.accept("=") {
+            vec![Statement::Return(parser.list()?)]
+        } else {
+            parser.block()?
+        };
+        if let Some((_, token)) = parser.peek() {
+            return Err(format!("Unexpected {} after the script", token));
+        }
+        Ok(Script {
+            source: source.to_string(),
+            name,
+            params,
+            body,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Number of values taken from the stack
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn call(&self, args: &[f64], angle: AngleMode) -> Result<Vec<f64>, CalcError> {
+        let mut machine = Machine {
+            angle,
+            variables: self
+                .params
+                .iter()
+                .cloned()
+                .zip(args.iter().copied())
+                .collect(),
+            instructions: 0,
+        };
+        match machine.run(&self.body)? {
+            Some(results) => Ok(results),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+// Tokens with their column, from 1
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // An exponent, as in 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let sign = matches!(chars.get(i + 1), Some('+' | '-')) as usize;
+                if chars.get(i + 1 + sign).is_some_and(char::is_ascii_digit) {
+                    i += 1 + sign;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let literal: String = chars[start..i].iter().collect();
+            match literal.parse() {
+                Ok(n) => Token::Number(n),
+                Err(_) => return Err(format!("Invalid number '{}'", literal)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Token::Name(chars[start..i].iter().collect())
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+                Some(symbol) => {
+                    i += symbol.len();
+                    Token::Symbol(symbol)
+                }
+                None => return Err(format!("Unexpected '{}' at column {}", c, start + 1)),
+            }
+        };
+        tokens.push((start + 1, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    // Blocks, parentheses, calls and operators being parsed
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let (_, token) = self
+            .tokens
+            .get(self.position)
+            .ok_or("Unexpected end of the script")?;
+        self.position += 1;
+        Ok(token.clone())
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let accepted = matches!(self.peek(), Some((_, Token::Symbol(s))) if *s == symbol);
+        self.position += accepted as usize;
+        accepted
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let accepted = matches!(self.peek(), Some((_, Token::Name(n))) if n == keyword);
+        self.position += accepted as usize;
+        accepted
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept(symbol) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some((column, token)) => Err(format!(
+                "Expected '{}' at column {}, found {}",
+                symbol, column, token
+            )),
+            None => Err(format!("Expected '{}' at the end of the script", symbol)),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("Script nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    // Parses a nested part of the script, unless nested too deeply
+    fn nested<T>(&mut self, parse: fn(&mut Parser) -> Result<T, String>) -> Result<T, String> {
+        self.enter()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let column = self.peek().map(|(column, _)| *column);
+        match self.next()? {
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            token => Err(format!(
+                "Expected a name at column {}, found {}",
+                column.unwrap(),
+                token
+            )),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, String> {
+        self.expect("{")?;
+        let mut statements = vec![];
+        while !self.accept("}") {
+            if self.peek().is_none() {
+                return Err("Expected '}' at the end of the script".to_string());
+            }
+            statements.push(self.statement()?);
+            self.accept(";");
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        if self.accept_keyword("let") {
+            let name = self.name()?;
+            self.expect("=")?;
+            return Ok(Statement::Let(name, self.expression()?));
+        }
+        if self.accept_keyword("if") {
+            return self.if_statement();
+        }
+        if self.accept_keyword("while") {
+            return Ok(Statement::While(
+                self.expression()?,
+                self.nested(Parser::block)?,
+            ));
+        }
+        if self.accept_keyword("return") {
+            let end = matches!(self.peek(), None | Some((_, Token::Symbol("}" | ";"))));
+            return Ok(Statement::Return(if end { vec![] } else { self.list()? }));
+        }
+        let name = self.name()?;
+        self.expect("=")?;
+        Ok(Statement::Assign(name, self.expression()?))
+    }
+
+    // After 'if'
+    fn if_statement(&mut self) -> Result<Statement, String> {
+        let condition = self.expression()?;
+        let then = self.nested(Parser::block)?;
+        let otherwise = if !self.accept_keyword("else") {
+            vec![]
+        } else if self.accept_keyword("if") {
+            vec![self.nested(Parser::if_statement)?]
+        } else {
+            self.nested(Parser::block)?
+        };
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn list(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = vec![self.expression()?];
+        while self.accept(",") {
+            nodes.push(self.expression()?);
+        }
+        Ok(nodes)
+    }
+
+    fn expression(&mut self) -> Result<Node, String> {
+        self.nested(|parser| parser.binary(0))
+    }
+
+    // Left associative binary operators, by level of binding, each operator
+    // nesting the node on its left
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["==", "!=", "<", "<=", ">", ">="],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let depth = self.depth;
+        let mut node = self.binary(level + 1)?;
+        'operators: loop {
+            for operator in LEVELS[level] {
+                if self.accept(operator) {
+                    self.enter()?;
+                    let right = self.binary(level + 1)?;
+                    node = Node::Binary(operator, Box::new(node), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            self.depth = depth;
+            return Ok(node);
+        }
+    }
+
+    // -2^2 is -(2^2), and 2^3^2 is 2^(3^2)
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.accept("-") {
+            return Ok(Node::Negate(Box::new(self.nested(Parser::unary)?)));
+        }
+        if self.accept("!") {
+            return Ok(Node::Not(Box::new(self.nested(Parser::unary)?)));
+        }
+        let base = self.primary()?;
+        if self.accept("^") {
+            let exponent = self.nested(Parser::unary)?;
+            return Ok(Node::Binary("^", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let column = self.peek().map(|(column, _)| *column);
+        match self.next()? {
+            Token::Number(n) => Ok(Node::Number(n)),
+            Token::Symbol("(") => {
+                let node = self.expression()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                if !self.accept("(") {
+                    return Ok(Node::Variable(name));
+                }
+                let args = if self.accept(")") {
+                    vec![]
+                } else {
+                    let args = self.list()?;
+                    self.expect(")")?;
+                    args
+                };
+                match arity(&name) {
+                    Some(arity) if arity == args.len() => Ok(Node::Call(name, args)),
+                    Some(arity) => Err(format!("'{}' takes {} arguments", name, arity)),
+                    None => Err(format!("Unknown function '{}'", name)),
+                }
+            }
+            token => Err(format!(
+                "Unexpected {} at column {}",
+                token,
+                column.unwrap()
+            )),
+        }
+    }
+}
+
+// Number of arguments of the functions scripts may call
+fn arity(name: &str) -> Option<usize> {
+    match name {
+        "abs" | "floor" | "ceil" | "round" => Some(1),
+        "min" | "max" => Some(2),
+        _ => Function::from_name(name).map(|_| 1),
+    }
+}
+
+struct Machine {
+    angle: AngleMode,
+    variables: HashMap<String, f64>,
+    instructions: usize,
+}
+
+impl Machine {
+    fn count(&mut self) -> Result<(), CalcError> {
+        self.instructions += 1;
+        if self.instructions > MAX_INSTRUCTIONS {
+            return Err(CalcError::Script(format!(
+                "Stopped after {} instructions",
+                MAX_INSTRUCTIONS
+            )));
+        }
+        Ok(())
+    }
+
+    // The returned values, if the statements return
+    fn run(&mut self, statements: &[Statement]) -> Result<Option<Vec<f64>>, CalcError> {
+        for statement in statements {
+            self.count()?;
+            match statement {
+                Statement::Let(name, node) => {
+                    let value = self.eval(node)?;
+                    self.variables.insert(name.clone(), value);
+                }
+                Statement::Assign(name, node) => {
+                    let value = self.eval(node)?;
+                    match self.variables.get_mut(name) {
+                        Some(variable) => *variable = value,
+                        None => return Err(unknown_variable(name)),
+                    }
+                }
+                Statement::If(condition, then, otherwise) => {
+                    let branch = if self.eval(condition)? != 0.0 {
+                        then
+                    } else {
+                        otherwise
+                    };
+                    if let Some(results) = self.run(branch)? {
+                        return Ok(Some(results));
+                    }
+                }
+                Statement::While(condition, body) => {
+                    while self.eval(condition)? != 0.0 {
+                        if let Some(results) = self.run(body)? {
+                            return Ok(Some(results));
+                        }
+                    }
+                }
+                Statement::Return(nodes) => {
+                    let results = nodes.iter().map(|node| self.eval(node));
+                    return Ok(Some(results.collect::<Result<_, _>>()?));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn eval(&mut self, node: &Node) -> Result<f64, CalcError> {
+        self.count()?;
+        let truth = |b: bool| if b { 1.0 } else { 0.0 };
+        match node {
+            Node::Number(n) => Ok(*n),
+            Node::Variable(name) => self
+                .variables
+                .get(name)
+                .copied()
+                .ok_or_else(|| unknown_variable(name)),
+            Node::Negate(a) => Ok(-self.eval(a)?),
+            Node::Not(a) => Ok(truth(self.eval(a)? == 0.0)),
+            // Only evaluated as needed
+            Node::Binary("&&", a, b) => Ok(truth(self.eval(a)? != 0.0 && self.eval(b)? != 0.0)),
+            Node::Binary("||", a, b) => Ok(truth(self.eval(a)? != 0.0 || self.eval(b)? != 0.0)),
+            Node::Binary(operator, a, b) => {
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                match *operator {
+                    "+" => Ok(a + b),
+                    "-" => Ok(a - b),
+                    "*" => Ok(a * b),
+                    "/" | "%" if b == 0.0 => Err(CalcError::ZeroDivision),
+                    "/" => Ok(a / b),
+                    "%" => Ok(a % b),
+                    "^" if a == 0.0 && b == 0.0 => Err(CalcError::ZeroPowerZero),
+                    "^" => Ok(a.powf(b)),
+                    "==" => Ok(truth(a == b)),
+                    "!=" => Ok(truth(a != b)),
+                    "<" => Ok(truth(a < b)),
+                    "<=" => Ok(truth(a <= b)),
+                    ">" => Ok(truth(a > b)),
+                    _ => Ok(truth(a >= b)),
+                }
+            }
+            Node::Call(name, args) => {
+                let args: Vec<f64> = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<_, _>>()?;
+                match (name.as_str(), &args[..]) {
+                    ("abs", [a]) => Ok(a.abs()),
+                    ("floor", [a]) => Ok(a.floor()),
+                    ("ceil", [a]) => Ok(a.ceil()),
+                    ("round", [a]) => Ok(a.round()),
+                    ("min", [a, b]) => Ok(a.min(*b)),
+                    ("max", [a, b]) => Ok(a.max(*b)),
+                    (name, [a]) => {
+                        let function = Function::from_name(name).unwrap();
+                        if !function.in_domain(*a) {
+                            return Err(CalcError::OutOfDomain(function.name()));
+                        }
+                        match self.angle {
+                            AngleMode::Degrees => Ok(function.apply_degrees(*a)),
+                            AngleMode::Radians => Ok(function.apply(*a)),
+                        }
+                    }
+                    _ => unreachable!("arguments checked when parsed"),
+                }
+            }
+        }
+    }
+}
+
+fn unknown_variable(name: &str) -> CalcError {
+    CalcError::Script(format!("Unknown variable '{}'", name))
+}
@@ -0,0 +1,359 @@
+// Session file format, version 2:
+//
+//   rpn-calc session 2
+//   mode exprs on|off
+//   mode angle deg
+//   mode prec <digits>
+//   mode tolerance <tol>
+//   mode maxevals <n>
+//   mode atomic on
+//   word <name> <commands>
+//   script <script>
+//   alias <name> <command>
+//   unalias <name>
+//   break <word>
+//   stack <number>
+//   big <digits>
+//   symbolic <rpn>
+//   program <commands>
+//   vector <numbers>
+//   matrix <literal>
+//   date <literal>
+//   duration <literal>
+//   end
+//
+// The modes are optional, the other ones than `exprs` being written only when
+// they differ from the defaults. User words are written `word <name>
+// <commands>`, the words defined by a script `script <script>`, as in
+// `script hyp(a, b) = sqrt(a^2 + b^2)`, the names given to commands by the
+// user `alias <name> <command>`, the names of built-in commands removed
+// `unalias <name>`, and the breakpoints `break <word>`.
+// Then there is one line per stack level, from the bottom of the stack to the
+// top. Numbers are written with the shortest representation that reads back to
+// the same f64, so values round-trip exactly, including `NaN`, `inf`, `-inf`
+// and `-0.0`, while the numbers of the precision mode are written with all
+// their digits, as in `big 1.4142135623730950488`. Symbolic expressions are
+// written with the RPN commands building them, as in
+// `symbolic 'x 2 ^ 3 'x * +`, matrices as in `matrix [[1.0 2.0][3.0 4.0]]`,
+// and dates and durations as in `date 2026-10-18` and `duration 1d12h`.
+// Files of version 1, without the tolerance, maxevals, atomic, alias, unalias
+// and break lines, are read too.
+// Blank lines and lines starting with '#' are ignored.
+// The `end` line is mandatory so truncated files are rejected.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::bigfloat::BigFloat;
+use super::calculator;
+use super::cli::{is_symbol_name, CliCmd, CliOperation};
+use super::date::{DateTime, Duration};
+use super::expr::AngleMode;
+use super::integrate;
+use super::registry::{Aliases, Exprs};
+use super::script::Script;
+use super::value::{Program, Value};
+use super::RpnCalc;
+
+pub const SESSION_VERSION: u32 = 2;
+
+// Versions still read, their lines being a subset of the current ones
+const READ_VERSIONS: &[&str] = &["1", "2"];
+
+const HEADER: &str = "rpn-calc session";
+
+#[derive(Debug, PartialEq)]
+pub enum SessionError {
+    Io(String),
+    NoDefaultFile,
+    MissingHeader,
+    UnsupportedVersion(String),
+    InvalidLine(usize, String),
+    MissingEnd,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Io(e) => write!(f, "{}", e),
+            SessionError::NoDefaultFile => write!(f, "No default session file location"),
+            SessionError::MissingHeader => write!(f, "Not a session file"),
+            SessionError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported session file version '{}'", v)
+            }
+            SessionError::InvalidLine(n, line) => write!(f, "Invalid line {}: '{}'", n, line),
+            SessionError::MissingEnd => write!(f, "Session file is truncated"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    stack: Vec<Value>,
+    exprs: bool,
+    precision: Option<usize>,
+    angle: AngleMode,
+    tolerance: f64,
+    max_evaluations: usize,
+    atomic: bool,
+    words: BTreeMap<String, Program>,
+    scripts: BTreeMap<String, Script>,
+    aliases: Aliases,
+    breakpoints: BTreeSet<String>,
+}
+
+impl Session {
+    pub fn from_calc(calc: &RpnCalc) -> Session {
+        Session {
+            stack: calc.stack.clone(),
+            exprs: calc.exprs.is_some(),
+            precision: calc.precision,
+            angle: calc.angle,
+            tolerance: calc.tolerance,
+            max_evaluations: calc.max_evaluations,
+            atomic: calc.atomic,
+            words: calc.words.clone(),
+            scripts: calc.scripts.clone(),
+            aliases: calc.aliases.clone(),
+            breakpoints: calc.debugger.breakpoints.clone(),
+        }
+    }
+
+    // Expressions are not saved, the restored values start new ones
+    pub fn restore(self, calc: &mut RpnCalc) {
+        calc.exprs = self
+            .exprs
+            .then(|| self.stack.iter().map(Value::tracked_expr).collect());
+        calc.stack = self.stack;
+        calc.precision = self.precision;
+        calc.angle = self.angle;
+        calc.tolerance = self.tolerance;
+        calc.max_evaluations = self.max_evaluations;
+        calc.atomic = self.atomic;
+        calc.words = self.words;
+        calc.scripts = self.scripts;
+        calc.aliases = self.aliases;
+        calc.debugger.breakpoints = self.breakpoints;
+    }
+
+    pub fn parse(content: &str) -> Result<Session, SessionError> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => {
+                let version = line[HEADER.len()..].trim();
+                if !READ_VERSIONS.contains(&version) {
+                    return Err(SessionError::UnsupportedVersion(version.to_string()));
+                }
+            }
+            _ => return Err(SessionError::MissingHeader),
+        }
+
+        let mut session = Session {
+            stack: vec![],
+            exprs: false,
+            precision: None,
+            angle: AngleMode::Radians,
+            tolerance: integrate::DEFAULT_TOLERANCE,
+            max_evaluations: integrate::DEFAULT_MAX_EVALUATIONS,
+            atomic: false,
+            words: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+            aliases: Aliases::new(),
+            breakpoints: BTreeSet::new(),
+        };
+        for (n, line) in lines.by_ref() {
+            let invalid = || SessionError::InvalidLine(n, line.to_string());
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "stack" => session
+                    .stack
+                    .push(Value::Number(value.trim().parse().map_err(|_| invalid())?)),
+                "big" => session
+                    .stack
+                    .push(Value::Big(BigFloat::parse(value).ok_or_else(invalid)?)),
+                "symbolic" => session
+                    .stack
+                    .push(parse_symbolic(value).ok_or_else(invalid)?),
+                "program" => session.stack.push(Value::Program(Program::new(value))),
+                "matrix" => match &CliCmd::tokenize(value)[..] {
+                    [CliCmd {
+                        oper: CliOperation::PushMatrix(m),
+                        ..
+                    }] => session.stack.push(Value::Matrix(m.clone())),
+                    _ => return Err(invalid()),
+                },
+                "date" => session
+                    .stack
+                    .push(Value::Date(DateTime::parse(value).ok_or_else(invalid)?)),
+                "duration" => session
+                    .stack
+                    .push(Value::Duration(Duration::parse(value).ok_or_else(invalid)?)),
+                "vector" => session.stack.push(Value::Vector(
+                    value
+                        .split_whitespace()
+                        .map(|n| n.parse().map_err(|_| invalid()))
+                        .collect::<Result<_, _>>()?,
+                )),
+                "word" => {
+                    let (name, source) = value.split_once(' ').unwrap_or((value, ""));
+                    if !is_symbol_name(name) {
+                        return Err(invalid());
+                    }
+                    session.words.insert(name.to_string(), Program::new(source));
+                }
+                "script" => {
+                    let script = Script::parse(value).map_err(|_| invalid())?;
+                    session.scripts.insert(script.name().to_string(), script);
+                }
+                // Checked as the commands do, in the order they were given
+                "alias" => match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [name, command] => session.aliases.set(name, command).map_err(|_| invalid())?,
+                    _ => return Err(invalid()),
+                },
+                "unalias" if !value.is_empty() && !value.contains(' ') => {
+                    session.aliases.remove(value).map_err(|_| invalid())?
+                }
+                "break" => {
+                    if !is_symbol_name(value) {
+                        return Err(invalid());
+                    }
+                    session.breakpoints.insert(value.to_string());
+                }
+                "mode" => match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["exprs", "on"] => session.exprs = true,
+                    ["exprs", "off"] => session.exprs = false,
+                    ["angle", "rad"] => session.angle = AngleMode::Radians,
+                    ["angle", "deg"] => session.angle = AngleMode::Degrees,
+                    ["prec", digits] => {
+                        let digits = digits.parse().ok().filter(|&d| d > 0);
+                        session.precision = Some(digits.ok_or_else(invalid)?);
+                    }
+                    ["tolerance", tolerance] => {
+                        let tolerance = tolerance.parse().ok().filter(|&t: &f64| t > 0.0);
+                        session.tolerance = tolerance.ok_or_else(invalid)?;
+                    }
+                    ["maxevals", n] => {
+                        let n = n.parse().ok().filter(|&n| n > 0);
+                        session.max_evaluations = n.ok_or_else(invalid)?;
+                    }
+                    ["atomic", "on"] => session.atomic = true,
+                    ["atomic", "off"] => session.atomic = false,
+                    _ => return Err(invalid()),
+                },
+                "end" if value.is_empty() => return Ok(session),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Err(SessionError::MissingEnd)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SessionError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| io_error(path, e))?;
+        }
+        fs::write(path, self.to_string()).map_err(|e| io_error(path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Session, SessionError> {
+        let content = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        Session::parse(&content)
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", HEADER, SESSION_VERSION)?;
+        writeln!(f, "mode exprs {}", if self.exprs { "on" } else { "off" })?;
+        if self.angle == AngleMode::Degrees {
+            writeln!(f, "mode angle deg")?;
+        }
+        if let Some(digits) = self.precision {
+            writeln!(f, "mode prec {}", digits)?;
+        }
+        if self.tolerance != integrate::DEFAULT_TOLERANCE {
+            writeln!(f, "mode tolerance {:?}", self.tolerance)?;
+        }
+        if self.max_evaluations != integrate::DEFAULT_MAX_EVALUATIONS {
+            writeln!(f, "mode maxevals {}", self.max_evaluations)?;
+        }
+        if self.atomic {
+            writeln!(f, "mode atomic on")?;
+        }
+        for (name, program) in self.words.iter() {
+            writeln!(f, "word {} {}", name, program.source())?;
+        }
+        for script in self.scripts.values() {
+            writeln!(f, "script {}", script.source())?;
+        }
+        let (aliases, removed) = self.aliases.parts();
+        for (name, command) in aliases.iter() {
+            writeln!(f, "alias {} {}", name, command)?;
+        }
+        for name in removed.iter() {
+            writeln!(f, "unalias {}", name)?;
+        }
+        for name in self.breakpoints.iter() {
+            writeln!(f, "break {}", name)?;
+        }
+        for value in self.stack.iter() {
+            match value {
+                Value::Number(n) => writeln!(f, "stack {:?}", n)?,
+                Value::Big(n) => writeln!(f, "big {}", n)?,
+                Value::Symbolic(expr) => writeln!(f, "symbolic {}", expr.to_rpn())?,
+                Value::Program(program) => writeln!(f, "program {}", program.source())?,
+                Value::Matrix(m) => writeln!(f, "matrix {:?}", m)?,
+                Value::Date(date) => writeln!(f, "date {}", date)?,
+                Value::Duration(duration) => writeln!(f, "duration {}", duration)?,
+                Value::Vector(v) => {
+                    write!(f, "vector")?;
+                    for n in v.iter() {
+                        write!(f, " {:?}", n)?;
+                    }
+                    writeln!(f)?
+                }
+            }
+        }
+        writeln!(f, "end")
+    }
+}
+
+// Rebuilds the expression from its RPN commands, which may only push values
+// and compute on them
+fn parse_symbolic(rpn: &str) -> Option<Value> {
+    let mut calc = RpnCalc::new();
+    calc.echo = false;
+    for cmd in CliCmd::tokenize(rpn) {
+        match cmd.oper {
+            CliOperation::Push(_) | CliOperation::PushDecimal(_) | CliOperation::PushSymbol(_) => {
+                calculator::process(&mut calc, &cmd).ok()?
+            }
+            CliOperation::Builtin(builtin, _)
+                if matches!(builtin.exprs, Exprs::Unary(_) | Exprs::Binary(_)) =>
+            {
+                calculator::process(&mut calc, &cmd).ok()?
+            }
+            _ => return None,
+        }
+    }
+    match calc.stack.pop() {
+        Some(value @ Value::Symbolic(_)) if calc.stack.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> SessionError {
+    SessionError::Io(format!("{}: {}", path.display(), e))
+}
+
+// $XDG_STATE_HOME/rpn-calc/session, falling back to ~/.local/state
+pub fn default_session_file() -> Option<PathBuf> {
+    Some(super::state_dir()?.join("session"))
+}
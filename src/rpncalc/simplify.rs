@@ -0,0 +1,192 @@
+// Algebraic simplification:
+//   - constants are folded, 2*3 + x is x + 6
+//   - like terms are collected, x + 2*x is 3*x and x*x/y is x^2/y
+//   - identities are removed, x + 0, 1*x and x^1 are x, 0*x is 0, x^0 is 1
+// Sums keep their terms in order of appearance with the constant last and
+// products sort their factors, so x*y and y*x simplify to the same expression.
+
+use super::expr::Expr;
+
+impl Expr {
+    pub fn simplify(&self) -> Expr {
+        match self {
+            Expr::Number(_) | Expr::Symbol(_) => self.clone(),
+            Expr::Neg(a) => product(&Expr::Number(-1.0), &a.simplify()),
+            Expr::Add(a, b) => sum(&a.simplify(), &b.simplify(), 1.0),
+            Expr::Sub(a, b) => sum(&a.simplify(), &b.simplify(), -1.0),
+            Expr::Mul(a, b) => product(&a.simplify(), &b.simplify()),
+            Expr::Div(a, b) => {
+                let (mut coefficient, mut factors) = (1.0, vec![]);
+                collect_factors(&a.simplify(), 1.0, &mut coefficient, &mut factors);
+                collect_factors(&b.simplify(), -1.0, &mut coefficient, &mut factors);
+                build_product(coefficient, factors)
+            }
+            Expr::Pow(a, b) => power(a.simplify(), b.simplify()),
+            Expr::Call(function, a) => match a.simplify() {
+                // Out of domain calls, like sqrt(-1), are kept as written
+                Expr::Number(n) if !function.apply(n).is_nan() => Expr::Number(function.apply(n)),
+                a => Expr::call(*function, a),
+            },
+        }
+    }
+}
+
+// a + sign*b, for simplified a and b
+fn sum(a: &Expr, b: &Expr, sign: f64) -> Expr {
+    let (mut constant, mut terms) = (0.0, vec![]);
+    collect_terms(a, 1.0, &mut constant, &mut terms);
+    collect_terms(b, sign, &mut constant, &mut terms);
+    build_sum(constant, terms)
+}
+
+fn product(a: &Expr, b: &Expr) -> Expr {
+    let (mut coefficient, mut factors) = (1.0, vec![]);
+    collect_factors(a, 1.0, &mut coefficient, &mut factors);
+    collect_factors(b, 1.0, &mut coefficient, &mut factors);
+    build_product(coefficient, factors)
+}
+
+fn power(base: Expr, exponent: Expr) -> Expr {
+    match (&base, &exponent) {
+        // 0^0 is undefined, it is kept as written
+        (Expr::Number(a), Expr::Number(b)) if *a == 0.0 && *b == 0.0 => Expr::pow(base, exponent),
+        (Expr::Number(a), Expr::Number(b)) => Expr::Number(a.powf(*b)),
+        (_, Expr::Number(b)) if *b == 0.0 => Expr::Number(1.0),
+        (_, Expr::Number(b)) if *b == 1.0 => base,
+        (Expr::Number(a), _) if *a == 1.0 => Expr::Number(1.0),
+        // (x^a)^n is x^(a*n) and (x*y)^n is x^n*y^n for integer n only,
+        // (x^2)^0.5 being |x| and not x
+        (Expr::Pow(..) | Expr::Mul(..) | Expr::Div(..) | Expr::Neg(_), Expr::Number(n))
+            if n.fract() == 0.0 =>
+        {
+            let (mut coefficient, mut factors) = (1.0, vec![]);
+            collect_factors(&base, 1.0, &mut coefficient, &mut factors);
+            let factors = factors
+                .into_iter()
+                .map(|(base, exponent)| (base, product(&exponent, &Expr::Number(*n))))
+                .collect();
+            build_product(coefficient.powf(*n), factors)
+        }
+        _ => Expr::pow(base, exponent),
+    }
+}
+
+// Splits a simplified expression into a constant and coefficient*monomial
+// terms, merging the terms with the same monomial.
+fn collect_terms(e: &Expr, sign: f64, constant: &mut f64, terms: &mut Vec<(f64, Expr)>) {
+    match e {
+        Expr::Number(n) => *constant += sign * n,
+        Expr::Add(a, b) => {
+            collect_terms(a, sign, constant, terms);
+            collect_terms(b, sign, constant, terms);
+        }
+        Expr::Sub(a, b) => {
+            collect_terms(a, sign, constant, terms);
+            collect_terms(b, -sign, constant, terms);
+        }
+        _ => {
+            let (mut coefficient, mut factors) = (1.0, vec![]);
+            collect_factors(e, 1.0, &mut coefficient, &mut factors);
+            let monomial = build_product(1.0, factors);
+            match terms.iter_mut().find(|(_, m)| *m == monomial) {
+                Some((c, _)) => *c += sign * coefficient,
+                None => terms.push((sign * coefficient, monomial)),
+            }
+        }
+    }
+}
+
+fn build_sum(constant: f64, terms: Vec<(f64, Expr)>) -> Expr {
+    let scale = |coefficient: f64, monomial: &Expr| product(&Expr::Number(coefficient), monomial);
+    let mut result: Option<Expr> = None;
+    for (coefficient, monomial) in terms.iter().filter(|(c, _)| *c != 0.0) {
+        result = Some(match result {
+            None => scale(*coefficient, monomial),
+            Some(acc) if *coefficient < 0.0 => acc - scale(-coefficient, monomial),
+            Some(acc) => acc + scale(*coefficient, monomial),
+        });
+    }
+    match result {
+        None => Expr::Number(constant),
+        Some(acc) if constant == 0.0 => acc,
+        Some(acc) if constant < 0.0 => acc - Expr::Number(-constant),
+        Some(acc) => acc + Expr::Number(constant),
+    }
+}
+
+// Splits a simplified expression into a numeric coefficient and base^exponent
+// factors, merging the factors with the same base. A negative `sign` collects
+// the reciprocal.
+fn collect_factors(e: &Expr, sign: f64, coefficient: &mut f64, factors: &mut Vec<(Expr, Expr)>) {
+    let (base, exponent) = match e {
+        Expr::Number(n) => {
+            if sign < 0.0 {
+                *coefficient /= n;
+            } else {
+                *coefficient *= n;
+            }
+            return;
+        }
+        Expr::Neg(a) => {
+            *coefficient = -*coefficient;
+            return collect_factors(a, sign, coefficient, factors);
+        }
+        Expr::Mul(a, b) => {
+            collect_factors(a, sign, coefficient, factors);
+            return collect_factors(b, sign, coefficient, factors);
+        }
+        Expr::Div(a, b) => {
+            collect_factors(a, sign, coefficient, factors);
+            return collect_factors(b, -sign, coefficient, factors);
+        }
+        Expr::Pow(base, exponent) => ((**base).clone(), (**exponent).clone()),
+        _ => (e.clone(), Expr::Number(1.0)),
+    };
+
+    let exponent = product(&exponent, &Expr::Number(sign));
+    match factors.iter_mut().find(|(b, _)| *b == base) {
+        Some((_, e)) => *e = sum(e, &exponent, 1.0),
+        None => factors.push((base, exponent)),
+    }
+}
+
+fn build_product(coefficient: f64, mut factors: Vec<(Expr, Expr)>) -> Expr {
+    factors.retain(|(_, exponent)| *exponent != Expr::Number(0.0));
+    if coefficient == 0.0 || factors.is_empty() {
+        return Expr::Number(coefficient);
+    }
+    // Coefficients are distributed over sums, 2*(x + 1) is 2*x + 2
+    if let [(base @ (Expr::Add(..) | Expr::Sub(..)), Expr::Number(exponent))] = &factors[..] {
+        if *exponent == 1.0 {
+            let (mut constant, mut terms) = (0.0, vec![]);
+            collect_terms(base, coefficient, &mut constant, &mut terms);
+            return build_sum(constant, terms);
+        }
+    }
+    // Symbols first, x*cos(x) rather than cos(x)*x
+    factors.sort_by_cached_key(|(base, _)| (!matches!(base, Expr::Symbol(_)), base.to_string()));
+
+    // Negative numeric exponents go to the denominator, x*y^-2 is x/y^2
+    let (mut numerator, mut denominator) = (vec![], vec![]);
+    for (base, exponent) in factors {
+        match exponent {
+            Expr::Number(n) if n < 0.0 => denominator.push(power(base, Expr::Number(-n))),
+            exponent => numerator.push(power(base, exponent)),
+        }
+    }
+
+    // -x*y rather than -(x*y)
+    if coefficient == -1.0 && !numerator.is_empty() {
+        let first = numerator.remove(0);
+        numerator.insert(0, -first);
+    }
+    let numerator = match numerator.into_iter().reduce(|a, b| a * b) {
+        None => Expr::Number(coefficient),
+        Some(n) if coefficient.abs() == 1.0 => n,
+        Some(n) => Expr::Number(coefficient) * n,
+    };
+    match denominator.into_iter().reduce(|a, b| a * b) {
+        None => numerator,
+        Some(d) => numerator / d,
+    }
+}
@@ -0,0 +1,124 @@
+// Root finding of f(x) = 0, f being evaluated by the caller and allowed to
+// fail. A bracket [a, b] with a sign change is refined with Brent's method;
+// from a single guess, secant steps are taken until they bracket a root,
+// which Brent's method then refines, or converge on their own, as they do on
+// roots without a sign change like x^2.
+
+use super::error::CalcError;
+
+const MAX_ITERATIONS: usize = 200;
+
+pub fn bracket<F>(mut f: F, a: f64, b: f64) -> Result<f64, CalcError>
+where
+    F: FnMut(f64) -> Result<f64, CalcError>,
+{
+    let (fa, fb) = (f(a)?, f(b)?);
+    brent(&mut f, a, b, fa, fb)
+}
+
+pub fn guess<F>(mut f: F, x: f64) -> Result<f64, CalcError>
+where
+    F: FnMut(f64) -> Result<f64, CalcError>,
+{
+    let (mut x0, mut x1) = (x, x + 1e-4 * x.abs().max(1.0));
+    let (mut f0, mut f1) = (f(x0)?, f(x1)?);
+    for _ in 0..MAX_ITERATIONS {
+        if f0 == 0.0 {
+            return Ok(x0);
+        }
+        if f0.signum() != f1.signum() {
+            return brent(&mut f, x0, x1, f0, f1);
+        }
+        if f1 == f0 {
+            break;
+        }
+        let x2 = x1 - f1 * (x1 - x0) / (f1 - f0);
+        if !x2.is_finite() {
+            break;
+        }
+        (x0, f0) = (x1, f1);
+        (x1, f1) = (x2, f(x2)?);
+        let step = (x1 - x0).abs();
+        if step <= 4.0 * f64::EPSILON * x1.abs().max(1.0) && f1.abs() <= 1e-10 {
+            return Ok(x1);
+        }
+    }
+    Err(CalcError::NoConvergence)
+}
+
+// Brent's method, combining bisection, secant and inverse quadratic
+// interpolation steps, on a bracket with f(a) and f(b) of opposite signs
+fn brent<F>(f: &mut F, a: f64, b: f64, fa: f64, fb: f64) -> Result<f64, CalcError>
+where
+    F: FnMut(f64) -> Result<f64, CalcError>,
+{
+    if fa == 0.0 {
+        return Ok(a);
+    }
+    if fb == 0.0 {
+        return Ok(b);
+    }
+    if fa.signum() == fb.signum() {
+        return Err(CalcError::NoSignChange);
+    }
+
+    let (mut a, mut b, mut fa, mut fb) = (a, b, fa, fb);
+    let (mut c, mut fc) = (a, fa);
+    let (mut d, mut e) = (b - a, b - a);
+    for _ in 0..MAX_ITERATIONS {
+        if fb.signum() == fc.signum() {
+            (c, fc) = (a, fa);
+            d = b - a;
+            e = d;
+        }
+        // b is the best estimate, c the other end of the bracket
+        if fc.abs() < fb.abs() {
+            (a, b, c) = (b, c, b);
+            (fa, fb, fc) = (fb, fc, fb);
+        }
+
+        let tolerance = 2.0 * f64::EPSILON * b.abs() + 0.5e-15;
+        let half = 0.5 * (c - b);
+        if half.abs() <= tolerance || fb == 0.0 {
+            return Ok(b);
+        }
+
+        if e.abs() >= tolerance && fa.abs() > fb.abs() {
+            // Interpolation, secant with two points, inverse quadratic with three
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2.0 * half * s, 1.0 - s)
+            } else {
+                let (q, r) = (fa / fc, fb / fc);
+                (
+                    s * (2.0 * half * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            }
+            p = p.abs();
+            // Accepted only if it stays well inside the bracket
+            if 2.0 * p < (3.0 * half * q - (tolerance * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = half;
+                e = d;
+            }
+        } else {
+            d = half;
+            e = d;
+        }
+
+        (a, fa) = (b, fb);
+        b += if d.abs() > tolerance {
+            d
+        } else {
+            tolerance.copysign(half)
+        };
+        fb = f(b)?;
+    }
+    Err(CalcError::NoConvergence)
+}
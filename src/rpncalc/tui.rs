@@ -0,0 +1,314 @@
+// Full-screen terminal interface, drawn with ANSI escape sequences:
+//
+//    3: 1.5             │> 1 2 +
+//    2: 2               │  3
+//    1: 3               │> 1.5 2
+//   > _
+//    f64 | rad | values          Enter dup  Bksp drop  PgUp/PgDn history  ^D quit
+//
+// The stack is on the left, its top at the bottom, and the lines entered with
+// their result on the right, below the text shown by commands as p or help.
+// On an empty input line, Enter duplicates the top of the stack and Backspace
+// drops it. There, + - * / ^ run with the next key unless it goes on with a
+// number or a longer command, as in -3 or ++. The input line is edited as in
+// the line editor, with its history and completion.
+//
+// The text of a line only shows once it ran, so the step mode and the
+// breakpoints don't pause there: the lines run on as without input.
+
+use std::io;
+use std::io::{Read, Write};
+
+use super::cli::{CliCmd, Notation};
+use super::editor::{self, Action, Key, LineEditor, RawMode};
+use super::{Output, RpnCalc};
+
+const DEFAULT_SIZE: (usize, usize) = (24, 80);
+
+const HINTS: &str = "Enter dup  Bksp drop  PgUp/PgDn history  ^D quit";
+
+pub struct Tui {
+    editor: LineEditor,
+    prompt: String,
+    notation: Notation,
+    rows: usize,
+    cols: usize,
+    // The lines entered with the text they showed and their result
+    history: Vec<(String, Vec<String>)>,
+    // Lines of the history scrolled back
+    scroll: usize,
+    // Shown in the status bar until the next key
+    message: String,
+    // An operator typed on an empty line, waiting for the next key
+    pending: bool,
+    keep_running: bool,
+}
+
+impl Tui {
+    pub fn new(editor: LineEditor) -> Tui {
+        Tui {
+            editor,
+            prompt: super::cli::DEFAULT_PROMPT.to_string(),
+            notation: Notation::Rpn,
+            rows: DEFAULT_SIZE.0,
+            cols: DEFAULT_SIZE.1,
+            history: vec![],
+            scroll: 0,
+            message: String::new(),
+            pending: false,
+            keep_running: true,
+        }
+    }
+
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_string();
+    }
+
+    pub fn set_notation(&mut self, notation: Notation) {
+        self.notation = notation;
+    }
+
+    pub fn set_size(&mut self, rows: usize, cols: usize) {
+        self.rows = rows.max(3);
+        self.cols = cols.max(3);
+    }
+
+    // Runs on the terminal, in its alternate screen, until Ctrl+D or quit
+    pub fn run_in_terminal(&mut self, calc: &mut RpnCalc) -> io::Result<()> {
+        let Some(_raw_mode) = RawMode::enable() else {
+            return Err(io::Error::other("the interface needs a terminal"));
+        };
+        if let Some((rows, cols)) = editor::terminal_size() {
+            self.set_size(rows, cols);
+        }
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "\x1B[?1049h")?;
+        let result = self.run(calc, io::stdin().lock(), &mut stdout);
+        write!(stdout, "\x1B[?1049l")?;
+        stdout.flush()?;
+        result
+    }
+
+    // Reads the keys from `input` and draws the screen to `out`
+    pub fn run<R, W>(&mut self, calc: &mut RpnCalc, mut input: R, mut out: W) -> io::Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        self.keep_running = true;
+        // The results are shown in the history
+        let echo = std::mem::replace(&mut calc.echo, false);
+        self.editor.set_completions(calc.completions());
+        write!(out, "\x1B[2J{}", self.render(calc))?;
+        out.flush()?;
+        while self.keep_running {
+            let key = read_key(&mut input)?;
+            self.feed(calc, key);
+            write!(out, "{}", self.render(calc))?;
+            out.flush()?;
+        }
+        calc.echo = echo;
+        Ok(())
+    }
+
+    fn feed(&mut self, calc: &mut RpnCalc, key: Key) {
+        self.message.clear();
+        if std::mem::take(&mut self.pending) {
+            match key {
+                // Going on with the line, Enter running it and Backspace
+                // erasing the operator
+                Key::Char('0'..='9' | '.' | '+' | '-' | '*' | '/' | '^')
+                | Key::Enter
+                | Key::Backspace => {}
+                Key::Char(' ') => return self.run_pending(calc),
+                _ => self.run_pending(calc),
+            }
+        }
+        let empty = self.editor.is_empty();
+        match key {
+            Key::Enter if empty => self.run_line(calc, "dup"),
+            Key::Backspace if empty => self.run_line(calc, "drop"),
+            Key::Char('+' | '-' | '*' | '/' | '^') if empty && !calc.stack.is_empty() => {
+                self.editor.feed(key);
+                self.pending = true;
+            }
+            Key::PageUp => {
+                let last = self.history_lines().len().saturating_sub(self.panes());
+                self.scroll = (self.scroll + self.panes()).min(last);
+            }
+            Key::PageDown => self.scroll = self.scroll.saturating_sub(self.panes()),
+            _ => match self.editor.feed(key) {
+                Action::Submit(line) if !line.trim().is_empty() => {
+                    if let Err(e) = self.editor.add_to_history(&line) {
+                        self.message = format!("Error saving history: {}", e);
+                    }
+                    self.run_line(calc, &line);
+                }
+                Action::Eof => self.keep_running = false,
+                Action::ShowCompletions(words) => self.message = words.join("  "),
+                _ => {}
+            },
+        }
+    }
+
+    fn run_pending(&mut self, calc: &mut RpnCalc) {
+        let line = self.editor.take_line();
+        self.run_line(calc, &line);
+    }
+
+    fn run_line(&mut self, calc: &mut RpnCalc, line: &str) {
+        let cmds = CliCmd::parse_line(line, self.notation, &calc.aliases);
+        if cmds.iter().any(|cmd| cmd.oper.is_command("quit")) {
+            self.keep_running = false;
+        }
+        let output = std::mem::replace(&mut calc.output, Output::Captured(vec![]));
+        let keys = std::mem::replace(&mut calc.debugger.keys, Box::new(|| None));
+        let errors = calc.run_line(&cmds);
+        calc.debugger.keys = keys;
+        let mut lines = match std::mem::replace(&mut calc.output, output) {
+            Output::Captured(lines) => lines,
+            _ => vec![],
+        };
+        let result = match errors.first() {
+            Some((i, e)) => super::error_message(e, &cmds[*i]),
+            None => calc
+                .stack
+                .last()
+                .map_or(String::new(), |top| top.to_string()),
+        };
+        if !result.is_empty() {
+            lines.push(result);
+        }
+        // cls clears the history, the line with it
+        if cmds.iter().any(|cmd| cmd.oper.is_command("cls")) {
+            self.history.clear();
+        } else {
+            self.history.push((line.to_string(), lines));
+        }
+        self.scroll = 0;
+        // The line may have defined words or aliases
+        self.editor.set_completions(calc.completions());
+    }
+
+    // Rows of the stack and history panes
+    fn panes(&self) -> usize {
+        self.rows - 2
+    }
+
+    // Escape sequence drawing the whole screen, the cursor left on the input
+    fn render(&self, calc: &RpnCalc) -> String {
+        let left = self.cols / 2;
+        let right = self.cols - left - 1;
+        let stack = self.stack_lines(calc);
+        let history = self.history_lines();
+        let end = history.len() - self.scroll;
+        let start = end.saturating_sub(self.panes());
+
+        let mut screen = String::from("\x1B[?25l");
+        for (row, level) in stack.iter().enumerate() {
+            let entry = history.get(start + row).map_or("", String::as_str);
+            screen.push_str(&format!(
+                "\x1B[{};1H{}│{}",
+                row + 1,
+                fit(level, left),
+                fit(entry, right)
+            ));
+        }
+
+        let status = if self.message.is_empty() {
+            HINTS
+        } else {
+            &self.message
+        };
+        let modes = status_modes(calc);
+        let gap = self
+            .cols
+            .saturating_sub(modes.chars().count() + status.chars().count());
+        let bar = format!("{}{}{}", modes, " ".repeat(gap.max(2)), status);
+        screen.push_str(&format!(
+            "\x1B[{};1H\x1B[7m{}\x1B[0m",
+            self.rows,
+            fit(&bar, self.cols)
+        ));
+
+        let prompt = format!("{} ", self.prompt);
+        screen.push_str(&format!(
+            "\x1B[{};1H{}",
+            self.rows - 1,
+            self.editor.render(&prompt)
+        ));
+        screen.push_str("\x1B[?25h");
+        screen
+    }
+
+    // A line per level, the top of the stack on the last one
+    fn stack_lines(&self, calc: &RpnCalc) -> Vec<String> {
+        let len = calc.stack.len();
+        let shown = if len > self.panes() {
+            self.panes() - 1
+        } else {
+            len
+        };
+        let width = len.to_string().len();
+        let mut lines = vec![String::new(); self.panes() - shown];
+        if shown < len {
+            *lines.last_mut().unwrap() = format!(" {} more levels", len - shown);
+        }
+        for (i, value) in calc.stack.iter().enumerate().skip(len - shown) {
+            let mut line = format!(" {:>width$}: {}", len - i, value);
+            if let Some(expr) = calc.exprs.as_ref().and_then(|exprs| exprs.get(i)) {
+                line.push_str(&format!("  {}", expr));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn history_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (line, shown) in self.history.iter() {
+            lines.push(format!("{} {}", self.prompt, line));
+            lines.extend(shown.iter().map(|text| format!("  {}", text)));
+        }
+        lines
+    }
+}
+
+// The number mode, angle mode and display of the values
+fn status_modes(calc: &RpnCalc) -> String {
+    let mut modes = vec![
+        match calc.precision {
+            Some(digits) => format!("{} digits", digits),
+            None => "f64".to_string(),
+        },
+        calc.angle.name().to_string(),
+        match calc.exprs {
+            Some(_) => "exprs".to_string(),
+            None => "values".to_string(),
+        },
+    ];
+    if calc.atomic {
+        modes.push("atomic".to_string());
+    }
+    if calc.debugger.trace {
+        modes.push("trace".to_string());
+    }
+    format!(" {}", modes.join(" | "))
+}
+
+// The text on one line of `width` characters, cut with '…' when longer
+fn fit(text: &str, width: usize) -> String {
+    let text = text.replace('\n', " ");
+    let len = text.chars().count();
+    if len > width {
+        let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        cut.push('…');
+        return cut;
+    }
+    format!("{}{}", text, " ".repeat(width - len))
+}
+
+// Ctrl+D once the input ends
+fn read_key<R: Read>(input: &mut R) -> io::Result<Key> {
+    Ok(editor::read_key(input)?.unwrap_or(Key::Eof))
+}
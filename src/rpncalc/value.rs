@@ -0,0 +1,217 @@
+use std::fmt;
+
+use super::bigfloat::BigFloat;
+use super::cli::CliCmd;
+use super::date::{DateTime, Duration};
+use super::error::CalcError;
+use super::expr::Expr;
+use super::matrix::Matrix;
+
+// A stack level: a number, an expression over unbound symbols, a program, a
+// vector of numbers, as in [1 -3 2], a matrix, as in [[1 2][3 4]], a date or
+// a duration. Numbers are Big in the precision mode.
+#[derive(PartialEq, Clone)]
+pub enum Value {
+    Number(f64),
+    Big(BigFloat),
+    Symbolic(Expr),
+    Program(Program),
+    Vector(Vec<f64>),
+    Matrix(Matrix),
+    Date(DateTime),
+    Duration(Duration),
+}
+
+// Commands pushed unevaluated, as in { 2 ^ 2 - }
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    source: String,
+}
+
+impl Program {
+    pub fn new(source: &str) -> Program {
+        Program {
+            source: source.trim().to_string(),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Read when run, with the built-in names only, so that the aliases of
+    // the calculator running it don't change what it does
+    pub(super) fn commands(&self) -> Vec<CliCmd> {
+        CliCmd::tokenize(&self.source)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.source.is_empty() {
+            write!(f, "{{ }}")
+        } else {
+            write!(f, "{{ {} }}", self.source)
+        }
+    }
+}
+
+impl Value {
+    // The simplified expression, a plain number when no symbol is left
+    pub fn symbolic(expr: Expr) -> Value {
+        match expr.simplify() {
+            Expr::Number(n) => Value::Number(n),
+            expr => Value::Symbolic(expr),
+        }
+    }
+
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Big(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    // None for the values that can't be part of an expression
+    pub fn to_expr(&self) -> Option<Expr> {
+        match self {
+            Value::Number(n) => Some(Expr::Number(*n)),
+            Value::Big(n) => Some(Expr::Number(n.to_f64())),
+            Value::Symbolic(expr) => Some(expr.clone()),
+            Value::Program(_)
+            | Value::Vector(_)
+            | Value::Matrix(_)
+            | Value::Date(_)
+            | Value::Duration(_) => None,
+        }
+    }
+
+    // The expression displayed for the value when expressions are tracked
+    pub fn tracked_expr(&self) -> Expr {
+        self.to_expr()
+            .unwrap_or_else(|| Expr::Symbol(self.to_string()))
+    }
+
+    // `number` on numbers, elementwise on vectors and matrices with numbers
+    // broadcast to every element, the simplified `symbolic` expression
+    // otherwise. Big numbers are rounded to f64 with vectors and matrices.
+    pub fn binary(
+        a: &Value,
+        b: &Value,
+        number: fn(f64, f64) -> Result<f64, CalcError>,
+        symbolic: fn(Expr, Expr) -> Expr,
+    ) -> Result<Value, CalcError> {
+        match (a, b) {
+            (Value::Big(a), b @ (Value::Vector(_) | Value::Matrix(_))) => {
+                Value::binary(&Value::Number(a.to_f64()), b, number, symbolic)
+            }
+            (a @ (Value::Vector(_) | Value::Matrix(_)), Value::Big(b)) => {
+                Value::binary(a, &Value::Number(b.to_f64()), number, symbolic)
+            }
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(number(*a, *b)?)),
+            (Value::Vector(a), Value::Vector(b)) => {
+                if a.len() != b.len() {
+                    return Err(CalcError::DimensionMismatch);
+                }
+                let v = a.iter().zip(b.iter()).map(|(a, b)| number(*a, *b));
+                Ok(Value::Vector(v.collect::<Result<_, _>>()?))
+            }
+            (Value::Vector(a), Value::Number(b)) => {
+                let v = a.iter().map(|a| number(*a, *b));
+                Ok(Value::Vector(v.collect::<Result<_, _>>()?))
+            }
+            (Value::Number(a), Value::Vector(b)) => {
+                let v = b.iter().map(|b| number(*a, *b));
+                Ok(Value::Vector(v.collect::<Result<_, _>>()?))
+            }
+            (Value::Matrix(a), Value::Matrix(b)) => Ok(Value::Matrix(a.try_zip(b, number)?)),
+            (Value::Matrix(a), Value::Number(b)) => {
+                Ok(Value::Matrix(a.try_map(|a| number(a, *b))?))
+            }
+            (Value::Number(a), Value::Matrix(b)) => {
+                Ok(Value::Matrix(b.try_map(|b| number(*a, b))?))
+            }
+            _ => {
+                let (a, b) = (a.to_expr(), b.to_expr());
+                let (a, b) = a.zip(b).ok_or(CalcError::WrongType)?;
+                Ok(Value::symbolic(symbolic(a, b)))
+            }
+        }
+    }
+
+    pub fn unary(
+        a: &Value,
+        number: fn(f64) -> Result<f64, CalcError>,
+        symbolic: fn(Expr) -> Expr,
+    ) -> Result<Value, CalcError> {
+        match a {
+            Value::Number(a) => Ok(Value::Number(number(*a)?)),
+            Value::Big(a) => Ok(Value::Number(number(a.to_f64())?)),
+            Value::Vector(v) => Ok(Value::Vector(
+                v.iter().map(|a| number(*a)).collect::<Result<_, _>>()?,
+            )),
+            Value::Matrix(m) => Ok(Value::Matrix(m.try_map(number)?)),
+            Value::Symbolic(a) => Ok(Value::symbolic(symbolic(a.clone()))),
+            Value::Program(_) | Value::Date(_) | Value::Duration(_) => Err(CalcError::WrongType),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        self.number() == Some(*other)
+    }
+}
+
+// Numbers as f64 and expressions quoted, as in [1.0, 'x + 1', [1.0 2.0]]
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{:?}", n),
+            Value::Big(n) => write!(f, "{}", n),
+            Value::Symbolic(expr) => write!(f, "'{}'", expr),
+            Value::Program(program) => write!(f, "{}", program),
+            Value::Vector(v) => write_vector(f, v, |f, n| write!(f, "{:?}", n)),
+            Value::Matrix(m) => write!(f, "{:?}", m),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::Duration(duration) => write!(f, "{}", duration),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Big(n) => write!(f, "{}", n),
+            Value::Symbolic(expr) => write!(f, "{}", expr),
+            Value::Program(program) => write!(f, "{}", program),
+            Value::Vector(v) => write_vector(f, v, |f, n| write!(f, "{}", n)),
+            Value::Matrix(m) => write!(f, "{}", m),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::Duration(duration) => write!(f, "{}", duration),
+        }
+    }
+}
+
+// The elements separated by spaces, in brackets
+fn write_vector<F>(f: &mut fmt::Formatter<'_>, v: &[f64], element: F) -> fmt::Result
+where
+    F: Fn(&mut fmt::Formatter<'_>, f64) -> fmt::Result,
+{
+    write!(f, "[")?;
+    for (i, n) in v.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        element(f, *n)?;
+    }
+    write!(f, "]")
+}